@@ -0,0 +1,66 @@
+//! Rejecting records with an `off` filter.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ic_log::Builder;
+use log::{Level, Log, Metadata, Record};
+
+fn off_filter(c: &mut Criterion) {
+    let (logger, _config) = Builder::new()
+        .parse_filters("off,ledger=off,ledger::transfer=off,ledger::fees=off")
+        .build();
+    let metadata = Metadata::builder()
+        .level(Level::Debug)
+        .target("ledger::transfer")
+        .build();
+
+    c.bench_function("enabled", |b| {
+        b.iter(|| logger.enabled(black_box(&metadata)))
+    });
+
+    c.bench_function("log", |b| {
+        b.iter(|| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("transfer started"))
+                    .metadata(black_box(metadata.clone()))
+                    .build(),
+            )
+        })
+    });
+}
+
+fn many_modules(c: &mut Criterion) {
+    let directives: Vec<String> = (0..30)
+        .map(|i| format!("canister::module{}=debug", i))
+        .collect();
+    let (logger, _config) = Builder::new()
+        .parse_filters(&format!(
+            "info,{},-canister::module3::tmp",
+            directives.join(",")
+        ))
+        .build();
+    let targets: Vec<String> = (0..40)
+        .map(|i| format!("canister::module{}::handler", i))
+        .collect();
+    let metadata: Vec<Metadata> = targets
+        .iter()
+        .map(|target| {
+            Metadata::builder()
+                .level(Level::Debug)
+                .target(target)
+                .build()
+        })
+        .collect();
+
+    c.bench_function("enabled with 30 directives", |b| {
+        b.iter(|| {
+            metadata
+                .iter()
+                .filter(|metadata| logger.enabled(black_box(metadata)))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, off_filter, many_modules);
+criterion_main!(benches);
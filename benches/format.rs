@@ -0,0 +1,57 @@
+//! Formatting records with the default format.
+
+use std::io::{self, Write};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ic_log::{Builder, Target, WriteStyle};
+use log::{Level, LevelFilter, Log, Record};
+
+/// A pipe discarding the records.
+struct Sink;
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn default_format(c: &mut Criterion) {
+    let (logger, _config) = Builder::new()
+        .target(Target::Pipe(Box::new(Sink)))
+        .write_style(WriteStyle::Never)
+        .filter_level(LevelFilter::Trace)
+        .build();
+
+    c.bench_function("str message", |b| {
+        b.iter(|| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("transfer started"))
+                    .level(Level::Info)
+                    .target("ledger")
+                    .module_path_static(Some("ledger::transfer"))
+                    .build(),
+            )
+        })
+    });
+
+    c.bench_function("formatted message", |b| {
+        b.iter(|| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("transfer {} of {} started", 7, "icp"))
+                    .level(Level::Info)
+                    .target("ledger")
+                    .module_path_static(Some("ledger::transfer"))
+                    .build(),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, default_format);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+//! Per-call output byte budget.
+//!
+//! A canister that logs in a runaway loop can spend its whole instruction limit
+//! formatting strings. The budget caps the amount of formatted bytes emitted
+//! during a single call; once it is exhausted further records are dropped
+//! without being formatted, and the number of records suppressed is reported
+//! when the next call starts.
+
+use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use crate::platform::Clock;
+use crate::stats::Counters;
+
+pub(crate) struct CallBudget {
+    max_bytes: usize,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    tick: Option<u64>,
+    used: usize,
+    exhausted: bool,
+    // Suppressed during the current call
+    suppressed: u64,
+    // Suppressed during the previous calls, not reported yet
+    unreported: u64,
+}
+
+/// Whether a record can be formatted.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Admission {
+    /// The record can be formatted, and must then be charged with
+    /// [`CallBudget::consume`].
+    pub allowed: bool,
+    /// The number of records suppressed during the previous call, to report
+    /// before the record.
+    pub ended: Option<u64>,
+}
+
+impl CallBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        CallBudget {
+            max_bytes,
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    /// Checks whether a new record can be formatted.
+    ///
+    /// On wasm32 the time doesn't change during a message execution, so the
+    /// first record seen with a new time of `clock` starts a new call.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
+    pub fn begin_record(&self, clock: &Clock, counters: &Counters) -> Admission {
+        let mut state = self.lock();
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let tick = clock.now_nanos();
+            if state.tick != Some(tick) {
+                state.tick = Some(tick);
+                new_call(&mut state);
+            }
+        }
+
+        let ended = Some(mem::take(&mut state.unreported)).filter(|count| *count > 0);
+        if state.exhausted {
+            state.suppressed += 1;
+            counters.budget_suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+        Admission {
+            allowed: !state.exhausted,
+            ended,
+        }
+    }
+
+    /// Accounts for a formatted record of `len` bytes, all its formats
+    /// included.
+    ///
+    /// Returns `false` if the record doesn't fit in the remaining budget, in
+    /// which case it must be dropped.
+    pub fn consume(&self, len: usize, counters: &Counters) -> bool {
+        let mut state = self.lock();
+
+        if state.exhausted || state.used.saturating_add(len) > self.max_bytes {
+            state.exhausted = true;
+            state.suppressed += 1;
+            counters.budget_suppressed.fetch_add(1, Ordering::Relaxed);
+            false
+        } else {
+            state.used += len;
+            true
+        }
+    }
+
+    /// Starts a new call.
+    pub fn reset(&self) {
+        new_call(&mut self.lock());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BudgetState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+fn new_call(state: &mut BudgetState) {
+    *state = BudgetState {
+        tick: state.tick,
+        unreported: state.unreported + state.suppressed,
+        ..Default::default()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission(allowed: bool, ended: Option<u64>) -> Admission {
+        Admission { allowed, ended }
+    }
+
+    #[test]
+    fn budget_drops_records_once_exhausted() {
+        let budget = CallBudget::new(10);
+        let (clock, counters) = (Clock::new(None, None), Counters::default());
+
+        assert_eq!(
+            admission(true, None),
+            budget.begin_record(&clock, &counters)
+        );
+        assert!(budget.consume(6, &counters));
+        assert_eq!(
+            admission(true, None),
+            budget.begin_record(&clock, &counters)
+        );
+        assert!(!budget.consume(6, &counters));
+
+        // The budget is exhausted, the record isn't even formatted
+        assert_eq!(
+            admission(false, None),
+            budget.begin_record(&clock, &counters)
+        );
+
+        assert_eq!(2, counters.snapshot().budget_suppressed);
+    }
+
+    #[test]
+    fn budget_reset_reports_the_suppressed_records() {
+        let budget = CallBudget::new(10);
+        let (clock, counters) = (Clock::new(None, None), Counters::default());
+
+        assert!(!budget.consume(11, &counters));
+        assert_eq!(
+            admission(false, None),
+            budget.begin_record(&clock, &counters)
+        );
+
+        budget.reset();
+
+        assert_eq!(
+            admission(true, Some(2)),
+            budget.begin_record(&clock, &counters)
+        );
+        assert!(budget.consume(10, &counters));
+        assert_eq!(
+            admission(true, None),
+            budget.begin_record(&clock, &counters)
+        );
+        assert_eq!(2, counters.snapshot().budget_suppressed);
+
+        // Nothing to report after a call without suppressed records
+        budget.reset();
+        assert_eq!(
+            admission(true, None),
+            budget.begin_record(&clock, &counters)
+        );
+    }
+
+    #[test]
+    fn a_small_budget_still_logs_records() {
+        let budget = CallBudget::new(10);
+        let (clock, counters) = (Clock::new(None, None), Counters::default());
+
+        assert_eq!(
+            admission(true, None),
+            budget.begin_record(&clock, &counters)
+        );
+        assert!(budget.consume(10, &counters));
+        assert_eq!(0, counters.snapshot().budget_suppressed);
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use ic_cdk::export::Principal;
+use log::LevelFilter;
+
+use crate::context;
+
+/// Level overrides for the records logged while serving specific callers.
+///
+/// The caller is the one recorded with [`context::record_caller`], so the
+/// overrides never apply outside of update and query calls.
+#[derive(Default)]
+pub(crate) struct CallerLevels {
+    levels: ArcSwap<HashMap<Principal, LevelFilter>>,
+}
+
+impl CallerLevels {
+    pub fn set(&self, caller: Principal, level: LevelFilter) {
+        self.levels.rcu(|levels| {
+            let mut levels = HashMap::clone(levels);
+            levels.insert(caller, level);
+            Arc::new(levels)
+        });
+    }
+
+    pub fn remove(&self, caller: &Principal) {
+        self.levels.rcu(|levels| {
+            let mut levels = HashMap::clone(levels);
+            levels.remove(caller);
+            Arc::new(levels)
+        });
+    }
+
+    /// Returns the highest level of the overrides.
+    pub fn max_level(&self) -> LevelFilter {
+        self.levels
+            .load()
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    /// Returns the override for the caller of the current message, if any.
+    pub fn current(&self) -> Option<LevelFilter> {
+        let levels = self.levels.load();
+        if levels.is_empty() {
+            return None;
+        }
+        self.level_of(context::caller()?)
+    }
+
+    fn level_of(&self, caller: Principal) -> Option<LevelFilter> {
+        self.levels.load().get(&caller).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_remove_overrides() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let levels = CallerLevels::default();
+        assert_eq!(LevelFilter::Off, levels.max_level());
+
+        levels.set(alice, LevelFilter::Trace);
+        levels.set(bob, LevelFilter::Info);
+        assert_eq!(Some(LevelFilter::Trace), levels.level_of(alice));
+        assert_eq!(LevelFilter::Trace, levels.max_level());
+
+        levels.remove(&alice);
+        assert_eq!(None, levels.level_of(alice));
+        assert_eq!(LevelFilter::Info, levels.max_level());
+
+        // no caller is recorded on native targets
+        assert_eq!(None, levels.current());
+    }
+}
@@ -0,0 +1,90 @@
+//! Canister endpoints managing the logger.
+
+/// Defines the canister endpoints managing the logger.
+///
+/// It takes the path of a function returning the [`LoggerConfig`] of the
+/// logger, and optionally the path of a guard function returning
+/// `Result<(), String>`, called first by every endpoint:
+///
+/// - `#[update] set_logger_filter(String) -> Result<(), String>` replaces the
+///   directives of the filter, see [`LoggerConfig::update_filters`]. It returns
+///   the error of the guard or the parse error.
+/// - `#[query] logger_stats() -> LoggerStats` returns the [`LoggerStats`]. It
+///   rejects the call with the error of the guard.
+///
+/// The endpoints are only exported on wasm32, on the other targets they are
+/// plain functions, so crates shared with native code still build and the
+/// endpoints can be unit tested. The canister must depend on `ic-cdk`, which
+/// the exported endpoints use.
+///
+/// This macro is only available with the `candid` feature.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// use ic_log::LoggerConfig;
+///
+/// thread_local! {
+///     static CONFIG: RefCell<Option<LoggerConfig>> = const { RefCell::new(None) };
+/// }
+///
+/// fn logger_config() -> LoggerConfig {
+///     CONFIG.with(|config| config.borrow().clone().expect("the logger is initialized"))
+/// }
+///
+/// fn is_controller() -> Result<(), String> {
+///     // check `ic_cdk::caller()` against the controllers
+///     Ok(())
+/// }
+///
+/// ic_log::export_log_endpoints!(logger_config, guard = is_controller);
+///
+/// CONFIG.with(|config| *config.borrow_mut() = Some(ic_log::init()));
+/// set_logger_filter("debug".to_owned()).unwrap();
+/// assert_eq!(0, logger_stats().records_logged);
+/// ```
+///
+/// [`LoggerConfig`]: struct.LoggerConfig.html
+/// [`LoggerConfig::update_filters`]: struct.LoggerConfig.html#method.update_filters
+/// [`LoggerStats`]: struct.LoggerStats.html
+#[macro_export]
+macro_rules! export_log_endpoints {
+    ($config:path) => {
+        $crate::export_log_endpoints!($config, guard = $crate::__unguarded);
+    };
+    ($config:path, guard = $guard:path) => {
+        #[cfg_attr(target_arch = "wasm32", ic_cdk::update)]
+        #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+        fn set_logger_filter(filters: String) -> Result<(), String> {
+            $guard()?;
+            let config: $crate::LoggerConfig = $config();
+            config.update_filters(&filters).map_err(|e| e.to_string())
+        }
+
+        #[cfg_attr(target_arch = "wasm32", ic_cdk::query)]
+        #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+        fn logger_stats() -> $crate::LoggerStats {
+            if let Err(e) = $guard() {
+                $crate::__reject(&e);
+            }
+            let config: $crate::LoggerConfig = $config();
+            config.stats()
+        }
+    };
+}
+
+#[doc(hidden)]
+pub fn __unguarded() -> Result<(), String> {
+    Ok(())
+}
+
+/// Rejects the call with `message`, or panics outside of a canister.
+#[doc(hidden)]
+pub fn __reject(message: &str) -> ! {
+    #[cfg(target_arch = "wasm32")]
+    ic_cdk::trap(message);
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("{}", message)
+}
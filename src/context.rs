@@ -0,0 +1,184 @@
+//! Information about the message execution a record is emitted from.
+//!
+//! The IC system API only exposes the name of the executing method to
+//! `canister_inspect_message`; calling it from any other context traps. For this
+//! reason the method name is instead recorded by the canister itself, usually
+//! as the first statement of each endpoint, keeping the returned guard until
+//! the end of the endpoint:
+//!
+//! ```
+//! #[ic_cdk::update]
+//! fn transfer() {
+//!     let _context = ic_log::context::set_method_name("transfer");
+//!     // ...
+//! }
+//! ```
+//!
+//! The context is cleared when the guard is dropped, or with [`clear`], so
+//! records emitted from heartbeats, timers or `init` never show a stale method
+//! name. The end of a message execution can't be detected from the system API
+//! reliably, since all the messages of a round see the same time, so the
+//! context lasts exactly as long as the guard. In an async endpoint other
+//! messages run while it awaits, and those not recording a context of their
+//! own see this one: set it again after an `await` rather than keeping the
+//! guard across it.
+//!
+//! The same goes for the caller used by [`LoggerConfig::set_caller_level`]:
+//! `ic_cdk::caller()` traps in heartbeats and timers, so the logger never reads
+//! it on its own. Call [`record_caller`] from the endpoints whose callers can
+//! have a level override.
+//!
+//! Records can therefore be logged from `init`, `post_upgrade`, heartbeats and
+//! timers. While formatting and printing them, the logger only calls
+//! `ic0.time`, `ic0.debug_print` and, with [`Builder::min_cycles_for_level`],
+//! `ic0.canister_cycle_balance128`, which are available in all of these
+//! contexts. Without a recorded method name the default format writes `-`,
+//! and without a recorded caller the caller level overrides don't apply.
+//!
+//! The method name is only written by the default format on wasm32 with the
+//! `ic` feature, and [`record_caller`] is a no-op on native targets.
+//!
+//! [`LoggerConfig::set_caller_level`]: ../struct.LoggerConfig.html#method.set_caller_level
+//! [`Builder::min_cycles_for_level`]: ../struct.Builder.html#method.min_cycles_for_level
+
+use std::cell::Cell;
+
+#[cfg(feature = "ic")]
+use ic_cdk::export::Principal;
+
+thread_local! {
+    static METHOD_NAME: Cell<Option<&'static str>> = const { Cell::new(None) };
+    #[cfg(feature = "ic")]
+    static CALLER: Cell<Option<Principal>> = const { Cell::new(None) };
+    // The cycles balance read during the current context
+    #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+    static BALANCE: Cell<Option<u128>> = const { Cell::new(None) };
+}
+
+/// Clears the context recorded for the current message execution when dropped.
+///
+/// Returned by [`set_method_name`] and [`record_caller`]. Dropping any of the
+/// guards clears the whole context.
+#[must_use = "the context is cleared when the guard is dropped"]
+#[derive(Debug)]
+pub struct ContextGuard {
+    _priv: (),
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        clear();
+    }
+}
+
+/// Records the name of the canister method being executed, until the returned
+/// guard is dropped.
+///
+/// The name is printed by the default format when [`Builder::format_method_name`]
+/// is enabled.
+///
+/// [`Builder::format_method_name`]: ../struct.Builder.html#method.format_method_name
+pub fn set_method_name(name: &'static str) -> ContextGuard {
+    let _ = METHOD_NAME.try_with(|method| method.set(Some(name)));
+    ContextGuard { _priv: () }
+}
+
+/// Returns the method name recorded for the current message execution, if any.
+#[cfg_attr(not(all(target_arch = "wasm32", feature = "ic")), allow(dead_code))]
+pub(crate) fn method_name() -> Option<&'static str> {
+    METHOD_NAME.try_with(Cell::get).ok().flatten()
+}
+
+/// Records the caller of the message being executed, as returned by
+/// `ic_cdk::caller()`, until the returned guard is dropped.
+///
+/// The caller selects the level overrides set with
+/// [`LoggerConfig::set_caller_level`]. It must only be called from update and
+/// query methods, since `ic_cdk::caller()` traps in heartbeats and timers.
+/// This is a no-op on native targets. It is only available with the `ic`
+/// feature.
+///
+/// [`LoggerConfig::set_caller_level`]: ../struct.LoggerConfig.html#method.set_caller_level
+#[cfg(feature = "ic")]
+pub fn record_caller() -> ContextGuard {
+    #[cfg(target_arch = "wasm32")]
+    set_caller(ic_cdk::caller());
+    ContextGuard { _priv: () }
+}
+
+#[cfg(feature = "ic")]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn set_caller(caller: Principal) {
+    let _ = CALLER.try_with(|cell| cell.set(Some(caller)));
+}
+
+/// Returns the caller recorded for the current message execution, if any.
+#[cfg(feature = "ic")]
+pub(crate) fn caller() -> Option<Principal> {
+    CALLER.try_with(Cell::get).ok().flatten()
+}
+
+/// Clears the method name and the caller recorded for the current message
+/// execution.
+///
+/// This is what dropping a [`ContextGuard`] does, for the endpoints which
+/// can't keep the guard until their end.
+pub fn clear() {
+    let _ = METHOD_NAME.try_with(|method| method.set(None));
+    #[cfg(feature = "ic")]
+    let _ = CALLER.try_with(|cell| cell.set(None));
+    #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+    let _ = BALANCE.try_with(|balance| balance.set(None));
+}
+
+/// Returns the cycles balance of the canister.
+///
+/// It is read once per recorded context, see [`set_method_name`], and for
+/// every record outside of one.
+#[cfg(all(target_arch = "wasm32", feature = "ic"))]
+pub(crate) fn cycles_balance() -> u128 {
+    let in_context = method_name().is_some() || caller().is_some();
+    match BALANCE.try_with(Cell::get).ok().flatten() {
+        Some(balance) if in_context => balance,
+        _ => {
+            let balance = ic_cdk::api::canister_balance128();
+            if in_context {
+                let _ = BALANCE.try_with(|cell| cell.set(Some(balance)));
+            }
+            balance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_context_is_cleared_with_the_guard() {
+        let guard = set_method_name("transfer");
+        assert_eq!(Some("transfer"), method_name());
+        drop(guard);
+        // a later message, like a timer, doesn't see the method name
+        assert_eq!(None, method_name());
+
+        let _guard = set_method_name("approve");
+        assert_eq!(Some("approve"), method_name());
+        clear();
+        assert_eq!(None, method_name());
+    }
+
+    #[test]
+    #[cfg(feature = "ic")]
+    fn the_caller_is_cleared_with_the_method_name() {
+        let guard = set_method_name("transfer");
+        set_caller(Principal::from_slice(&[1]));
+        assert_eq!(Some(Principal::from_slice(&[1])), caller());
+        drop(guard);
+        assert_eq!(None, caller());
+
+        // the caller can't be read on native targets
+        let _guard = record_caller();
+        assert_eq!(None, caller());
+    }
+}
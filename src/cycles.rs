@@ -0,0 +1,91 @@
+//! Throttling of the records while the cycles balance of the canister is low.
+//!
+//! Printing records costs cycles, so a canister close to freezing drops its
+//! less severe records. Errors are always kept.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::Level;
+
+#[derive(Debug)]
+pub(crate) struct CyclesThrottle {
+    // The records of each level and the less severe ones need a balance of at
+    // least the threshold
+    min_cycles: Vec<(Level, u128)>,
+    // Set while the balance is under a threshold
+    engaged: AtomicBool,
+}
+
+/// Whether a record is kept by the throttle.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Admission {
+    pub allowed: bool,
+    /// The threshold the balance went under, if the throttling just engaged.
+    pub engaged: Option<u128>,
+}
+
+impl CyclesThrottle {
+    pub fn new(min_cycles: Vec<(Level, u128)>) -> Self {
+        CyclesThrottle {
+            min_cycles,
+            engaged: AtomicBool::new(false),
+        }
+    }
+
+    /// Checks a record of `level` against the cycles `balance`.
+    pub fn admit(&self, level: Level, balance: u128) -> Admission {
+        let under = || {
+            self.min_cycles
+                .iter()
+                .filter(|(_, threshold)| balance < *threshold)
+        };
+        let threshold = match under().map(|(_, threshold)| *threshold).max() {
+            Some(threshold) => threshold,
+            None => {
+                self.engaged.store(false, Ordering::Relaxed);
+                return Admission {
+                    allowed: true,
+                    engaged: None,
+                };
+            }
+        };
+
+        let throttled = under().any(|(min_level, _)| level >= *min_level);
+        Admission {
+            allowed: level == Level::Error || !throttled,
+            engaged: Some(threshold).filter(|_| !self.engaged.swap(true, Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission(allowed: bool, engaged: Option<u128>) -> Admission {
+        Admission { allowed, engaged }
+    }
+
+    #[test]
+    fn less_severe_records_are_dropped_under_the_threshold() {
+        let throttle = CyclesThrottle::new(vec![(Level::Debug, 1_000), (Level::Warn, 100)]);
+
+        assert_eq!(admission(true, None), throttle.admit(Level::Trace, 1_000));
+        assert_eq!(
+            admission(false, Some(1_000)),
+            throttle.admit(Level::Debug, 999)
+        );
+        assert_eq!(admission(true, None), throttle.admit(Level::Info, 999));
+        assert_eq!(admission(false, None), throttle.admit(Level::Trace, 500));
+
+        assert_eq!(admission(false, None), throttle.admit(Level::Warn, 99));
+        assert_eq!(admission(true, None), throttle.admit(Level::Error, 0));
+
+        // The warning is emitted again once the balance recovered
+        assert_eq!(admission(true, None), throttle.admit(Level::Debug, 2_000));
+        assert_eq!(
+            admission(false, Some(1_000)),
+            throttle.admit(Level::Warn, 0)
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Collapsing of consecutive duplicate records.
+//!
+//! A retry loop can log the same line thousands of times. When enabled, a
+//! record with the same level, target and message as the previous one is
+//! dropped and counted, and a single `last message repeated N times` line is
+//! logged once a different record arrives or the maximum interval passed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use log::{Level, Record};
+
+use crate::platform::Clock;
+
+pub(crate) struct Dedup {
+    max_interval_ns: u64,
+    clock: Arc<Clock>,
+    state: Mutex<Option<Previous>>,
+}
+
+/// The last record logged, identified by the hash of its level, target and
+/// message.
+struct Previous {
+    hash: u64,
+    level: Level,
+    target: String,
+    repeated: u64,
+    since: u64,
+}
+
+/// The repetitions of a record to report before logging the next one.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Repeated {
+    pub level: Level,
+    pub target: String,
+    pub count: u64,
+}
+
+/// What to do with a record.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Verdict {
+    /// The repetitions to report before the record.
+    pub repeated: Option<Repeated>,
+    /// Whether the record repeats the previous one and must be dropped.
+    pub duplicate: bool,
+}
+
+/// Feeds the formatted arguments to a hasher, without allocating.
+struct HashWriter<'a>(&'a mut DefaultHasher);
+
+impl<'a> Write for HashWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+fn hash(record: &Record) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    record.level().hash(&mut hasher);
+    record.target().hash(&mut hasher);
+    let _ = write!(HashWriter(&mut hasher), "{}", record.args());
+    hasher.finish()
+}
+
+impl Dedup {
+    pub fn new(max_interval_ns: u64, clock: Arc<Clock>) -> Dedup {
+        Dedup {
+            max_interval_ns,
+            clock,
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Checks whether `record` repeats the previous one.
+    ///
+    /// The repetitions of a record are also reported once the maximum interval
+    /// passed since they started, or since they were last reported, so a flood
+    /// is summarized periodically instead of being silent until it stops.
+    pub fn check(&self, record: &Record) -> Verdict {
+        let hash = hash(record);
+        let now = self.clock.now_nanos();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(previous) = state.as_mut().filter(|previous| previous.hash == hash) {
+            previous.repeated += 1;
+            let due = now.saturating_sub(previous.since) >= self.max_interval_ns;
+            if due {
+                previous.since = now;
+            }
+            return Verdict {
+                repeated: if due { previous.report() } else { None },
+                duplicate: true,
+            };
+        }
+
+        let repeated = state.as_mut().and_then(Previous::report);
+        *state = Some(Previous {
+            hash,
+            level: record.level(),
+            target: record.target().to_owned(),
+            repeated: 0,
+            since: now,
+        });
+        Verdict {
+            repeated,
+            duplicate: false,
+        }
+    }
+}
+
+impl Previous {
+    /// Takes the repetitions to report, if any.
+    fn report(&mut self) -> Option<Repeated> {
+        if self.repeated == 0 {
+            return None;
+        }
+        Some(Repeated {
+            level: self.level,
+            target: self.target.clone(),
+            count: std::mem::take(&mut self.repeated),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn check(dedup: &Dedup, message: &str) -> Verdict {
+        dedup.check(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Warn)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn repetitions_are_reported_after_the_interval() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+        let repeated = |count| Repeated {
+            level: Level::Warn,
+            target: String::new(),
+            count,
+        };
+
+        let dedup = Dedup::new(10, Arc::new(Clock::new(Some(clock), None)));
+        assert!(!check(&dedup, "retry").duplicate);
+        NOW.store(5, Ordering::SeqCst);
+        assert_eq!(
+            Verdict {
+                repeated: None,
+                duplicate: true
+            },
+            check(&dedup, "retry")
+        );
+        NOW.store(10, Ordering::SeqCst);
+        assert_eq!(
+            Verdict {
+                repeated: Some(repeated(2)),
+                duplicate: true
+            },
+            check(&dedup, "retry")
+        );
+        NOW.store(15, Ordering::SeqCst);
+        assert_eq!(
+            Verdict {
+                repeated: None,
+                duplicate: true
+            },
+            check(&dedup, "retry")
+        );
+        assert_eq!(
+            Verdict {
+                repeated: Some(repeated(1)),
+                duplicate: false
+            },
+            check(&dedup, "done")
+        );
+    }
+
+    #[test]
+    fn different_messages_are_not_duplicates() {
+        let dedup = Dedup::new(u64::MAX, Arc::new(Clock::new(Some(|| 0), None)));
+
+        assert!(!check(&dedup, "a").duplicate);
+        assert!(!check(&dedup, "b").duplicate);
+        assert!(!check(&dedup, "a").duplicate);
+        assert!(check(&dedup, "a").duplicate);
+    }
+}
@@ -0,0 +1,60 @@
+//! The error type of the fallible operations of the crate.
+
+use std::{error, fmt, io};
+
+use log::SetLoggerError;
+
+use crate::FilterParseError;
+
+/// An error returned when building, installing or configuring a logger.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A global logger not built by this crate is already installed.
+    AlreadyInitialized,
+    /// The builder was already built with one of the `&mut self` methods.
+    AlreadyBuilt,
+    /// The filter contains invalid directives.
+    InvalidFilter(FilterParseError),
+    /// Writing to a target failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AlreadyInitialized => f.write_str("a global logger is already installed"),
+            Error::AlreadyBuilt => f.write_str("attempt to re-use consumed builder"),
+            Error::InvalidFilter(e) => fmt::Display::fmt(e, f),
+            Error::Io(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::AlreadyInitialized | Error::AlreadyBuilt => None,
+            Error::InvalidFilter(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<SetLoggerError> for Error {
+    fn from(_: SetLoggerError) -> Self {
+        Error::AlreadyInitialized
+    }
+}
+
+impl From<FilterParseError> for Error {
+    fn from(e: FilterParseError) -> Self {
+        Error::InvalidFilter(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,74 @@
+//! The directives matched by the recent targets.
+//!
+//! Canisters log from a small set of targets, so the index of the directive
+//! applying to a target is kept in a small cache: each target hashes to two
+//! slots, and takes the first one if it is empty, or else replaces the target
+//! cached in the second one. The cache belongs to a `Filter`, which is replaced as a whole when
+//! the directives change.
+
+use arc_swap::ArcSwapOption;
+use std::sync::Arc;
+
+const SLOTS: usize = 64;
+
+struct Entry {
+    target: Box<str>,
+    // The index of the directive, or `None` if no directive applies
+    directive: Option<usize>,
+}
+
+pub(crate) struct TargetCache {
+    slots: [ArcSwapOption<Entry>; SLOTS],
+}
+
+impl TargetCache {
+    pub fn new() -> TargetCache {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: ArcSwapOption<Entry> = ArcSwapOption::const_empty();
+        TargetCache {
+            slots: [EMPTY; SLOTS],
+        }
+    }
+
+    /// Returns the directive cached for the target, or finds it with `find`
+    /// and caches it.
+    pub fn get_or_insert<F>(&self, target: &str, find: F) -> Option<usize>
+    where
+        F: FnOnce() -> Option<usize>,
+    {
+        let (first, second) = slots(target);
+        let (first, second) = (&self.slots[first], &self.slots[second]);
+        let (cached_first, cached_second) = (first.load(), second.load());
+        for entry in [&*cached_first, &*cached_second].iter().copied().flatten() {
+            if &*entry.target == target {
+                return entry.directive;
+            }
+        }
+
+        let directive = find();
+        let entry = Some(Arc::new(Entry {
+            target: target.into(),
+            directive,
+        }));
+        match &*cached_first {
+            None => first.store(entry),
+            Some(_) => second.store(entry),
+        }
+        directive
+    }
+}
+
+// The two slots of a target, from the halves of its FNV-1a hash. The hash is
+// mixed since the low bits of FNV-1a are similar for module paths that only
+// differ at the end.
+fn slots(target: &str) -> (usize, usize) {
+    let mut hash = target
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    (hash as u32 as usize % SLOTS, (hash >> 32) as usize % SLOTS)
+}
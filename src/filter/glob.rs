@@ -0,0 +1,133 @@
+//! Wildcard module patterns, e.g. `svc_*` or `my_canister::**::db`.
+//!
+//! `*` matches any characters of a path segment and `**` matches any characters,
+//! including the `::` separators. Like the literal module names, a pattern only
+//! needs to match the beginning of the target.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    // `*`, matching within a path segment
+    Segment,
+    // `**`, matching across path segments
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+    literal_len: usize,
+}
+
+impl Glob {
+    /// Compiles the pattern, or returns `None` if it has no wildcards.
+    pub fn new(pattern: &str) -> Option<Glob> {
+        if !pattern.contains('*') {
+            return None;
+        }
+
+        let mut tokens = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix("**") {
+                tokens.push(Token::Any);
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix('*') {
+                tokens.push(Token::Segment);
+                rest = tail;
+            } else {
+                let end = rest.find('*').unwrap_or(rest.len());
+                tokens.push(Token::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        let literal_len = pattern.chars().filter(|c| *c != '*').count();
+        Some(Glob {
+            tokens,
+            literal_len,
+        })
+    }
+
+    /// Returns the number of non-wildcard characters of the pattern, which
+    /// measures how specific it is.
+    pub fn literal_len(&self) -> usize {
+        self.literal_len
+    }
+
+    /// Checks if the pattern matches the beginning of `target`.
+    pub fn matches(&self, target: &str) -> bool {
+        matches(&self.tokens, target)
+    }
+}
+
+fn matches(tokens: &[Token], target: &str) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+
+    match token {
+        Token::Literal(literal) => {
+            target.starts_with(literal.as_str()) && matches(rest, &target[literal.len()..])
+        }
+        Token::Segment => {
+            let segment_len = target.find(':').unwrap_or(target.len());
+            (0..=segment_len)
+                .filter(|i| target.is_char_boundary(*i))
+                .any(|i| matches(rest, &target[i..]))
+        }
+        Token::Any => (0..=target.len())
+            .filter(|i| target.is_char_boundary(*i))
+            .any(|i| matches(rest, &target[i..])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Glob;
+
+    #[test]
+    fn literal_is_not_a_glob() {
+        assert_eq!(None, Glob::new("crate1::mod1"));
+    }
+
+    #[test]
+    fn glob_matches() {
+        // (pattern, target, expected)
+        let cases = [
+            ("svc_*", "svc_users", true),
+            ("svc_*", "svc_ledger::db", true),
+            ("svc_*", "other", false),
+            ("svc_*::db", "svc_ledger::db", true),
+            ("svc_*::db", "svc_ledger::api::db", false),
+            ("svc_*::**::db", "svc_ledger::api::v1::db", true),
+            ("svc_*::**::db", "svc_ledger::db", false),
+            ("svc_*::**", "svc_ledger::db", true),
+            ("svc_*::**", "svc_ledger", false),
+            ("*::db", "svc_ledger::db", true),
+            ("*_ledger", "svc_ledger", true),
+            ("*_ledger", "svc_ledger_v2", true),
+            ("*_ledger", "svc::ledger", false),
+            ("**db", "svc::ledger::db", true),
+            ("é*", "éa", true),
+        ];
+
+        for (pattern, target, expected) in cases {
+            let glob = Glob::new(pattern).unwrap();
+            assert_eq!(
+                expected,
+                glob.matches(target),
+                "`{}` on `{}`",
+                pattern,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn literal_len() {
+        assert_eq!(4, Glob::new("svc_*").unwrap().literal_len());
+        assert_eq!(10, Glob::new("svc_*::**::db").unwrap().literal_len());
+    }
+}
@@ -0,0 +1,1431 @@
+//! Filtering for log records.
+//!
+//! This module contains the log filtering used by `ic_log` to match records.
+//! Directives use the same syntax as the `RUST_LOG` environment variable of
+//! `env_logger`: a comma-separated list of `module=level` entries, where a bare
+//! level sets the default for all modules and a bare module enables all of its
+//! levels, optionally followed by `/filter` to only keep records whose message
+//! matches `filter`.
+//!
+//! With the `regex` feature the filter is a regular expression, otherwise it is
+//! a plain substring. Wasm binaries that don't need regular expressions can leave
+//! the feature off to save code size.
+//!
+//! A directive applies to every record whose target starts with its module, and
+//! the directive with the longest matching module wins.
+//!
+//! A module can be excluded with `-module`, which turns it off regardless of the
+//! default level, e.g. `debug,-candid` logs everything at debug except `candid`.
+//! Exclusions are applied after the other directives, so `-candid` wins over
+//! `candid=debug`, but a more specific `candid::de=warn` still applies.
+//!
+//! Module names can contain wildcards: `*` matches within a path segment and
+//! `**` across segments, so `svc_*=debug` applies to both `svc_users` and
+//! `svc_ledger`. When several directives match, the one with the most literal
+//! characters wins, and a literal module wins over a pattern of the same length.
+//!
+//! The level of a directive can also be a range, to only keep the levels in
+//! between: `my_canister=warn..=info` drops `error` records, and
+//! `my_canister=only:warn` keeps `warn` records only.
+//!
+//! A `target:name=level` directive matches records whose target is exactly
+//! `name`, e.g. the ones logged with `info!(target: "audit", ...)`. Since it is
+//! an exact match, it wins over any module directive matching the same record.
+//!
+//! Parsing is lenient by default: invalid directives are reported on stderr and
+//! skipped. [`Builder::try_parse`] is strict instead and fails with a
+//! [`FilterParseError`] listing every bad directive, including the bare words
+//! that are not a level, like `infoo`. Modules enabled with a bare name must be
+//! written as `module=trace` in strict mode.
+//!
+//! [`Builder::try_parse`]: struct.Builder.html#method.try_parse
+//! [`FilterParseError`]: struct.FilterParseError.html
+
+use log::{Level, LevelFilter, Metadata, Record};
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use self::cache::TargetCache;
+use self::glob::Glob;
+
+mod cache;
+mod glob;
+
+#[cfg(feature = "regex")]
+#[path = "regex.rs"]
+mod inner;
+
+#[cfg(not(feature = "regex"))]
+#[path = "string.rs"]
+mod inner;
+
+/// A builder for a log filter.
+///
+/// It can be used to parse a set of directives from a string before building
+/// a [`Filter`] instance.
+///
+/// [`Filter`]: struct.Filter.html
+pub(crate) struct Builder {
+    directives: Vec<Directive>,
+    filter: Option<inner::Filter>,
+    built: bool,
+}
+
+// A clone of a built builder can be built again.
+impl Clone for Builder {
+    fn clone(&self) -> Self {
+        Builder {
+            directives: self.directives.clone(),
+            filter: self.filter.clone(),
+            built: false,
+        }
+    }
+}
+
+impl Builder {
+    /// Initializes the filter builder with defaults.
+    pub fn new() -> Builder {
+        Builder {
+            directives: Vec::new(),
+            filter: None,
+            built: false,
+        }
+    }
+
+    /// Insert the directive replacing any directive with the same name.
+    fn insert_directive(&mut self, mut directive: Directive) {
+        if let Some(pos) = self
+            .directives
+            .iter()
+            .position(|d| d.name == directive.name && d.target == directive.target)
+        {
+            mem::swap(&mut self.directives[pos], &mut directive);
+        } else {
+            self.directives.push(directive);
+        }
+    }
+
+    /// Adds a directive to the filter for a specific module.
+    pub fn filter_module(&mut self, module: &str, level: LevelFilter) -> &mut Self {
+        self.filter(Some(module), level)
+    }
+
+    /// Adds a directive to the filter for all modules.
+    pub fn filter_level(&mut self, level: LevelFilter) -> &mut Self {
+        self.filter(None, level)
+    }
+
+    /// Adds a directive to the filter.
+    ///
+    /// The given module (if any) will log at most the specified level provided.
+    /// If no module is provided then the filter will apply to all log messages.
+    pub fn filter(&mut self, module: Option<&str>, level: LevelFilter) -> &mut Self {
+        self.insert_directive(Directive::new(module, level));
+        self
+    }
+
+    /// Removes the directive for a specific module, if any.
+    pub fn remove_module(&mut self, module: &str) -> &mut Self {
+        self.directives
+            .retain(|d| d.target || d.name.as_deref() != Some(module));
+        self
+    }
+
+    /// Parses the directives string.
+    ///
+    /// See the [module documentation] for more details.
+    ///
+    /// [module documentation]: index.html
+    pub fn parse(&mut self, filters: &str) -> &mut Self {
+        let spec = parse_spec(filters);
+
+        for error in spec.errors.iter().filter(|e| !e.lenient) {
+            eprintln!("warning: invalid logging spec {}, ignoring it", error);
+        }
+
+        self.insert_spec(spec);
+        self
+    }
+
+    /// Parses the directives string, failing on any invalid directive.
+    ///
+    /// Unlike [`parse`], nothing is changed on failure.
+    ///
+    /// [`parse`]: #method.parse
+    pub fn try_parse(&mut self, filters: &str) -> Result<&mut Self, FilterParseError> {
+        let mut spec = parse_spec(filters);
+
+        if !spec.errors.is_empty() {
+            return Err(FilterParseError {
+                invalid: mem::take(&mut spec.errors),
+            });
+        }
+
+        self.insert_spec(spec);
+        Ok(self)
+    }
+
+    /// Merges the directives of another builder, which win on conflicts.
+    ///
+    /// The message filter is replaced only if the other builder has one.
+    pub fn merge(&mut self, other: Builder) -> &mut Self {
+        if other.filter.is_some() {
+            self.filter = other.filter;
+        }
+
+        for directive in other.directives {
+            self.insert_directive(directive);
+        }
+        self
+    }
+
+    fn insert_spec(&mut self, spec: ParsedSpec) {
+        self.filter = spec.filter;
+
+        for directive in spec.directives {
+            self.insert_directive(directive);
+        }
+    }
+
+    /// Build a log filter.
+    pub fn build(&mut self) -> Filter {
+        assert!(!self.built, "attempt to re-use consumed builder");
+        self.built = true;
+
+        let mut directives = Vec::new();
+        if self.directives.is_empty() {
+            // Adds the default filter if none exist
+            directives.push(Directive::new(None, LevelFilter::Error));
+        } else {
+            directives = self.directives.clone();
+            // Sort the directives by length of their name, this allows a
+            // little more efficient lookup at runtime.
+            directives.sort_by(|a, b| {
+                let alen = a.name.as_ref().map(|a| a.len()).unwrap_or(0);
+                let blen = b.name.as_ref().map(|b| b.len()).unwrap_or(0);
+                alen.cmp(&blen)
+            });
+        }
+
+        let max_level = directives
+            .iter()
+            .map(|d| d.level)
+            .max()
+            .unwrap_or(LevelFilter::Off);
+
+        Filter {
+            directives,
+            filter: self.filter.clone(),
+            max_level,
+            cache: TargetCache::new(),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.built {
+            f.debug_struct("Filter").field("built", &true).finish()
+        } else {
+            f.debug_struct("Filter")
+                .field("filter", &self.filter)
+                .field("directives", &self.directives)
+                .finish()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Directive {
+    name: Option<String>,
+    // The most severe level enabled, `Error` unless the directive is a range.
+    min_level: Level,
+    level: LevelFilter,
+    // Set for `-module` directives, whose level is always `Off`.
+    exclude: bool,
+    // Set for `target:name` directives, matching the exact target.
+    target: bool,
+    // Set when the module name has wildcards.
+    glob: Option<Glob>,
+}
+
+impl Directive {
+    fn new(name: Option<&str>, level: LevelFilter) -> Directive {
+        Directive {
+            name: name.map(|s| s.to_string()),
+            min_level: Level::Error,
+            level,
+            exclude: false,
+            target: false,
+            glob: name.and_then(Glob::new),
+        }
+    }
+
+    /// Returns how specifically the directive matches a module `target`, if
+    /// it does. Literal modules win over patterns with the same specificity.
+    fn specificity(&self, target: &str) -> Option<(usize, bool)> {
+        match (&self.name, &self.glob) {
+            (_, Some(glob)) => Some((glob.literal_len(), false)).filter(|_| glob.matches(target)),
+            (Some(name), None) => Some((name.len(), true)).filter(|_| target.starts_with(&**name)),
+            (None, None) => Some((0, true)),
+        }
+    }
+
+    fn enables(&self, level: Level) -> bool {
+        self.min_level <= level && level <= self.level
+    }
+
+    /// Returns the levels of the directive as they are parsed.
+    fn levels(&self) -> String {
+        let lowercase = |level: &dyn fmt::Display| level.to_string().to_lowercase();
+        if self.min_level == Level::Error {
+            lowercase(&self.level)
+        } else if self.min_level.to_level_filter() == self.level {
+            format!("{}{}", ONLY_PREFIX, lowercase(&self.min_level))
+        } else {
+            format!(
+                "{}..={}",
+                lowercase(&self.min_level),
+                lowercase(&self.level)
+            )
+        }
+    }
+}
+
+/// A log filter.
+///
+/// This struct can be used to determine whether or not a log record
+/// should be written to the output.
+/// Use the [`Builder`] type to parse and construct a `Filter`.
+///
+/// [`Builder`]: struct.Builder.html
+pub(crate) struct Filter {
+    directives: Vec<Directive>,
+    filter: Option<inner::Filter>,
+    max_level: LevelFilter,
+    cache: TargetCache,
+}
+
+impl Filter {
+    /// Returns the maximum `LevelFilter` that this filter instance is
+    /// configured to output.
+    pub fn filter(&self) -> LevelFilter {
+        self.max_level
+    }
+
+    /// Checks if this record matches the configured filter.
+    pub fn matches(&self, record: &Record) -> bool {
+        if !self.enabled(record.metadata()) {
+            return false;
+        }
+
+        if let Some(filter) = self.filter.as_ref() {
+            if !filter.is_match(&record.args().to_string()) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Determines if a log message with the specified metadata would be logged.
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        self.directive(metadata.target())
+            .map_or(false, |directive| directive.enables(metadata.level()))
+    }
+
+    /// Returns the most verbose level enabled for a module or target.
+    pub fn effective_level(&self, target: &str) -> LevelFilter {
+        self.directive(target).map_or(LevelFilter::Off, |d| d.level)
+    }
+
+    // Finds the directive applying to a target. Matching a single directive
+    // is cheaper than the cache lookup.
+    fn directive(&self, target: &str) -> Option<&Directive> {
+        let index = if self.directives.len() <= 1 {
+            directive_for(&self.directives, target)
+        } else {
+            self.cache
+                .get_or_insert(target, || directive_for(&self.directives, target))
+        };
+        index.map(|index| &self.directives[index])
+    }
+
+    /// Describes the directives and message filter, in canonical order.
+    pub fn describe(&self) -> ParsedDirectives {
+        ParsedDirectives {
+            directives: self
+                .sorted_directives()
+                .into_iter()
+                .map(|d| ParsedDirective {
+                    name: d.name.clone(),
+                    target: d.target,
+                    excluded: d.exclude,
+                    min_level: d.min_level,
+                    max_level: d.level,
+                })
+                .collect(),
+            message_filter: self.filter.as_ref().map(|f| f.to_string()),
+        }
+    }
+
+    // The default directive first, then the module and target directives by name.
+    fn sorted_directives(&self) -> Vec<&Directive> {
+        let mut directives: Vec<_> = self.directives.iter().collect();
+        directives.sort_by(|a, b| (a.target, &a.name).cmp(&(b.target, &b.name)));
+        directives
+    }
+
+    /// Returns a builder initialized with the directives and message filter of
+    /// this filter, to derive a modified copy.
+    pub fn to_builder(&self) -> Builder {
+        Builder {
+            directives: self.directives.clone(),
+            filter: self.filter.clone(),
+            built: false,
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    /// Writes the canonical directives string of the filter.
+    ///
+    /// The default directive comes first, then the module directives sorted by
+    /// name, then the message filter. Parsing the output yields an equivalent
+    /// filter.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, directive) in self.sorted_directives().iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match &directive.name {
+                Some(name) if directive.exclude => write!(f, "-{}", name)?,
+                Some(name) if directive.target => {
+                    write!(f, "{}{}={}", TARGET_PREFIX, name, directive.levels())?
+                }
+                Some(name) => write!(f, "{}={}", name, directive.levels())?,
+                None => f.write_str(&directive.levels())?,
+            }
+        }
+
+        if let Some(filter) = &self.filter {
+            write!(f, "/{}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("filter", &self.filter)
+            .field("directives", &self.directives)
+            .finish()
+    }
+}
+
+/// A description of valid directives, returned by [`validate_filters`].
+///
+/// [`validate_filters`]: fn.validate_filters.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedDirectives {
+    /// The directives in canonical order: the default level first, then the
+    /// module and target directives sorted by name.
+    pub directives: Vec<ParsedDirective>,
+    /// The filter applied to the messages, if any.
+    pub message_filter: Option<String>,
+}
+
+/// A single directive of [`ParsedDirectives`].
+///
+/// [`ParsedDirectives`]: struct.ParsedDirectives.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedDirective {
+    /// The module or target the directive applies to, `None` for the default level.
+    pub name: Option<String>,
+    /// Whether the directive matches the exact target rather than a module prefix.
+    pub target: bool,
+    /// Whether the module is excluded with `-module`.
+    pub excluded: bool,
+    /// The most severe level enabled, `Error` unless the directive is a range.
+    pub min_level: Level,
+    /// The most verbose level enabled.
+    pub max_level: LevelFilter,
+}
+
+/// Parses a directives string strictly without applying it, returning a
+/// description of the directives or the invalid ones.
+///
+/// The same parser is used by [`Builder::try_parse_filters`] and
+/// [`LoggerConfig::update_filters`], so they accept exactly the same strings.
+/// Without any directive, the default `error` level is described.
+///
+/// ```
+/// use log::LevelFilter;
+///
+/// let parsed = ic_log::validate_filters("warn,my_canister=debug/transfer").unwrap();
+/// assert_eq!(2, parsed.directives.len());
+/// assert_eq!(Some("my_canister"), parsed.directives[1].name.as_deref());
+/// assert_eq!(LevelFilter::Debug, parsed.directives[1].max_level);
+/// assert_eq!(Some("transfer"), parsed.message_filter.as_deref());
+///
+/// assert!(ic_log::validate_filters("infoo").is_err());
+/// ```
+///
+/// [`Builder::try_parse_filters`]: struct.Builder.html#method.try_parse_filters
+/// [`LoggerConfig::update_filters`]: struct.LoggerConfig.html#method.update_filters
+pub fn validate_filters(filters: &str) -> Result<ParsedDirectives, FilterParseError> {
+    Ok(Builder::new().try_parse(filters)?.build().describe())
+}
+
+/// An error returned when a directives string contains invalid directives.
+///
+/// Every bad directive is listed with its byte position in the string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterParseError {
+    invalid: Vec<InvalidDirective>,
+}
+
+impl FilterParseError {
+    /// Returns the invalid directives, in the order they appear.
+    pub fn invalid_directives(&self) -> &[InvalidDirective] {
+        &self.invalid
+    }
+}
+
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid logging spec")?;
+        for (i, invalid) in self.invalid.iter().enumerate() {
+            f.write_str(if i == 0 { ": " } else { "; " })?;
+            fmt::Display::fmt(invalid, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FilterParseError {}
+
+/// A single invalid directive of a [`FilterParseError`].
+///
+/// [`FilterParseError`]: struct.FilterParseError.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidDirective {
+    directive: String,
+    position: usize,
+    reason: String,
+    // Whether the lenient parser accepts the directive anyway.
+    lenient: bool,
+}
+
+impl InvalidDirective {
+    /// Returns the text of the invalid directive.
+    pub fn directive(&self) -> &str {
+        &self.directive
+    }
+
+    /// Returns the byte position of the directive in the directives string.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns why the directive is invalid.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for InvalidDirective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' at {} ({})",
+            self.directive, self.position, self.reason
+        )
+    }
+}
+
+/// The outcome of parsing a directives string.
+struct ParsedSpec {
+    directives: Vec<Directive>,
+    filter: Option<inner::Filter>,
+    errors: Vec<InvalidDirective>,
+}
+
+impl ParsedSpec {
+    fn invalid(&mut self, directive: &str, position: usize, reason: String, lenient: bool) {
+        self.errors.push(InvalidDirective {
+            directive: directive.to_string(),
+            position,
+            reason,
+            lenient,
+        });
+    }
+}
+
+const TARGET_PREFIX: &str = "target:";
+const ONLY_PREFIX: &str = "only:";
+
+/// Parse a logging specification string (e.g: "crate1,crate2::mod3,crate3::x=error/foo")
+/// and return the log directives, the message filter and the invalid directives.
+///
+/// Invalid directives are skipped, except for the ones marked as `lenient` which
+/// are kept for compatibility with `env_logger`.
+fn parse_spec(spec: &str) -> ParsedSpec {
+    let mut parsed = ParsedSpec {
+        directives: Vec::new(),
+        filter: None,
+        errors: Vec::new(),
+    };
+
+    let (mods, filter) = match spec.find('/') {
+        Some(pos) => (&spec[..pos], Some((pos + 1, &spec[pos + 1..]))),
+        None => (spec, None),
+    };
+    if let Some((start, filter)) = filter {
+        if let Some(pos) = filter.find('/') {
+            parsed.invalid(spec, start + pos, "too many '/'s".to_string(), false);
+            return parsed;
+        }
+    }
+
+    let mut exclusions = Vec::new();
+    let mut offset = 0;
+    for part in mods.split(',') {
+        let position = offset + part.len() - part.trim_start().len();
+        offset += part.len() + 1;
+
+        let s = part.trim();
+        if s.is_empty() {
+            if !mods
+                .trim_matches(|c: char| c == ',' || c.is_whitespace())
+                .is_empty()
+            {
+                parsed.invalid(s, position, "empty directive".to_string(), true);
+            }
+            continue;
+        }
+        if let Some(name) = s.strip_prefix('-') {
+            let name = name.trim();
+            if name.is_empty() || name.contains('=') {
+                let reason = "an exclusion takes a module name only".to_string();
+                parsed.invalid(s, position, reason, false);
+            } else {
+                exclusions.push(Directive {
+                    exclude: true,
+                    ..Directive::new(Some(name), LevelFilter::Off)
+                });
+            }
+            continue;
+        }
+        let all_levels = (Level::Error, LevelFilter::max());
+        let ((min_level, log_level), name) = match (parse_levels(s), s.split_once('=')) {
+            // if the single argument is a log-level string or range,
+            // treat that as a global fallback
+            (Some(levels), _) => (levels, None),
+            (None, None) => {
+                if !s.starts_with(TARGET_PREFIX) {
+                    let reason = format!("not a level, use '{}=trace' to enable a module", s);
+                    parsed.invalid(s, position, reason, true);
+                }
+                (all_levels, Some(s))
+            }
+            (None, Some((part0, part1))) => {
+                let part1 = part1.trim();
+                if part0.is_empty() {
+                    parsed.invalid(s, position, "missing module name".to_string(), true);
+                } else if !part0.starts_with(TARGET_PREFIX) && !is_module_path(part0) {
+                    parsed.invalid(s, position, "invalid module name".to_string(), true);
+                }
+                if part1.is_empty() {
+                    (all_levels, Some(part0))
+                } else {
+                    match parse_levels(part1) {
+                        Some(levels) => (levels, Some(part0)),
+                        None => {
+                            let reason = format!("invalid level '{}'", part1);
+                            parsed.invalid(s, position, reason, false);
+                            continue;
+                        }
+                    }
+                }
+            }
+        };
+        let (name, target) = match name.and_then(|name| name.strip_prefix(TARGET_PREFIX)) {
+            Some("") => {
+                parsed.invalid(s, position, "missing target name".to_string(), false);
+                continue;
+            }
+            Some(target) => (Some(target), true),
+            None => (name, false),
+        };
+        parsed.directives.push(Directive {
+            min_level,
+            target,
+            ..Directive::new(name, log_level)
+        });
+    }
+    // Exclusions go last so they replace positive directives for the same module.
+    parsed.directives.append(&mut exclusions);
+
+    if let Some((start, filter)) = filter {
+        match inner::Filter::new(filter) {
+            Ok(filter) => parsed.filter = Some(filter),
+            Err(e) => parsed.invalid(
+                filter,
+                start,
+                format!("invalid regex filter - {}", e),
+                false,
+            ),
+        }
+    }
+
+    parsed
+}
+
+/// Checks that a directive name looks like a module path, e.g. `crate1::mod1`.
+fn is_module_path(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == ':' || c == '*')
+}
+
+/// Parses a level, a `min..=max` range of levels in any order, or `only:level`.
+fn parse_levels(s: &str) -> Option<(Level, LevelFilter)> {
+    if let Some(level) = s.strip_prefix(ONLY_PREFIX) {
+        let level: Level = level.trim().parse().ok()?;
+        return Some((level, level.to_level_filter()));
+    }
+    if let Some((from, to)) = s.split_once("..=") {
+        let from: Level = from.trim().parse().ok()?;
+        let to: Level = to.trim().parse().ok()?;
+        return Some((from.min(to), from.max(to).to_level_filter()));
+    }
+    s.parse().ok().map(|level| (Level::Error, level))
+}
+
+// Check whether a level and target are enabled by the set of directives,
+// without the cache of `Filter`.
+#[cfg(test)]
+fn enabled(directives: &[Directive], level: Level, target: &str) -> bool {
+    directive_for(directives, target).map_or(false, |index| directives[index].enables(level))
+}
+
+// Find the index of the directive applying to a target.
+fn directive_for(directives: &[Directive], target: &str) -> Option<usize> {
+    // A target directive is an exact match, so it wins over module prefixes.
+    if let Some(index) = directives
+        .iter()
+        .position(|d| d.target && d.name.as_deref() == Some(target))
+    {
+        return Some(index);
+    }
+
+    // Search for the most specific match, the vector is assumed to be pre-sorted
+    // so the later directive wins a tie.
+    directives
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| !d.target)
+        .filter_map(|(index, d)| {
+            d.specificity(target)
+                .map(|specificity| (specificity, index))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, index)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter};
+
+    use super::{enabled, parse_spec, Builder, Directive, Filter, InvalidDirective};
+
+    const LEVELS: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    fn make_logger_filter(dirs: Vec<Directive>) -> Filter {
+        let mut logger = Builder::new().build();
+        logger.directives = dirs;
+        logger
+    }
+
+    fn directive(name: Option<&str>, level: LevelFilter) -> Directive {
+        Directive::new(name, level)
+    }
+
+    #[test]
+    fn filter_info() {
+        let logger = Builder::new().filter(None, LevelFilter::Info).build();
+        assert!(enabled(&logger.directives, Level::Info, "crate1"));
+        assert!(!enabled(&logger.directives, Level::Debug, "crate1"));
+    }
+
+    #[test]
+    fn filter_beginning_longest_match() {
+        let logger = Builder::new()
+            .filter(Some("crate2"), LevelFilter::Info)
+            .filter(Some("crate2::mod"), LevelFilter::Debug)
+            .filter(Some("crate1::mod1"), LevelFilter::Warn)
+            .build();
+        assert!(enabled(&logger.directives, Level::Debug, "crate2::mod1"));
+        assert!(!enabled(&logger.directives, Level::Debug, "crate2"));
+    }
+
+    #[test]
+    fn filter_default_is_error() {
+        let logger = Builder::new().build();
+        assert!(enabled(&logger.directives, Level::Error, "crate1"));
+        assert!(!enabled(&logger.directives, Level::Warn, "crate1"));
+        assert_eq!(LevelFilter::Error, logger.filter());
+    }
+
+    #[test]
+    fn filter_replaces_same_module() {
+        let logger = Builder::new()
+            .filter_module("crate1", LevelFilter::Trace)
+            .filter_level(LevelFilter::Info)
+            .filter_module("crate1", LevelFilter::Warn)
+            .build();
+        assert_eq!(2, logger.directives.len());
+        assert!(!enabled(&logger.directives, Level::Info, "crate1"));
+        assert_eq!(LevelFilter::Info, logger.filter());
+    }
+
+    #[test]
+    fn to_builder_derives_filter() {
+        let logger = Builder::new()
+            .parse("info,crate1=debug,crate2=warn/needle")
+            .build();
+
+        let derived = logger
+            .to_builder()
+            .filter_module("crate1", LevelFilter::Trace)
+            .remove_module("crate2")
+            .build();
+
+        assert_eq!("info,crate1=trace/needle", derived.to_string());
+        assert_eq!("info,crate1=debug,crate2=warn/needle", logger.to_string());
+    }
+
+    #[test]
+    fn parse_default_bare_levels() {
+        // Every spelling of each level against every level of record
+        let specs = [
+            ("off", LevelFilter::Off),
+            ("OFF", LevelFilter::Off),
+            ("error", LevelFilter::Error),
+            ("ERROR", LevelFilter::Error),
+            ("warn", LevelFilter::Warn),
+            ("WARN", LevelFilter::Warn),
+            ("info", LevelFilter::Info),
+            ("INFO", LevelFilter::Info),
+            ("debug", LevelFilter::Debug),
+            ("DEBUG", LevelFilter::Debug),
+            ("Debug", LevelFilter::Debug),
+            ("debuG", LevelFilter::Debug),
+            ("DeBuG", LevelFilter::Debug),
+            ("trace", LevelFilter::Trace),
+            ("TRACE", LevelFilter::Trace),
+        ];
+
+        for (spec, max) in specs {
+            let logger = Builder::new().parse(spec).build();
+            for level in LEVELS {
+                assert_eq!(
+                    level <= max,
+                    enabled(&logger.directives, level, ""),
+                    "spec `{}` with level {}",
+                    spec,
+                    level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parse_default() {
+        let logger = Builder::new().parse("info,crate1::mod1=warn").build();
+        assert!(enabled(&logger.directives, Level::Warn, "crate1::mod1"));
+        assert!(enabled(&logger.directives, Level::Info, "crate2::mod2"));
+    }
+
+    #[test]
+    fn match_full_path() {
+        let logger = make_logger_filter(vec![
+            directive(Some("crate2"), LevelFilter::Info),
+            directive(Some("crate1::mod1"), LevelFilter::Warn),
+        ]);
+        assert!(enabled(&logger.directives, Level::Warn, "crate1::mod1"));
+        assert!(!enabled(&logger.directives, Level::Info, "crate1::mod1"));
+        assert!(enabled(&logger.directives, Level::Info, "crate2"));
+        assert!(!enabled(&logger.directives, Level::Debug, "crate2"));
+    }
+
+    #[test]
+    fn no_match() {
+        let logger = make_logger_filter(vec![
+            directive(Some("crate2"), LevelFilter::Info),
+            directive(Some("crate1::mod1"), LevelFilter::Warn),
+        ]);
+        assert!(!enabled(&logger.directives, Level::Warn, "crate3"));
+    }
+
+    #[test]
+    fn match_beginning() {
+        let logger = make_logger_filter(vec![
+            directive(Some("crate2"), LevelFilter::Info),
+            directive(Some("crate1::mod1"), LevelFilter::Warn),
+        ]);
+        assert!(enabled(&logger.directives, Level::Info, "crate2::mod1"));
+    }
+
+    #[test]
+    fn match_beginning_longest_match() {
+        let logger = make_logger_filter(vec![
+            directive(Some("crate2"), LevelFilter::Info),
+            directive(Some("crate2::mod"), LevelFilter::Debug),
+            directive(Some("crate1::mod1"), LevelFilter::Warn),
+        ]);
+        assert!(enabled(&logger.directives, Level::Debug, "crate2::mod1"));
+        assert!(!enabled(&logger.directives, Level::Debug, "crate2"));
+    }
+
+    #[test]
+    fn match_default() {
+        let logger = make_logger_filter(vec![
+            directive(None, LevelFilter::Info),
+            directive(Some("crate1::mod1"), LevelFilter::Warn),
+        ]);
+        assert!(enabled(&logger.directives, Level::Warn, "crate1::mod1"));
+        assert!(enabled(&logger.directives, Level::Info, "crate2::mod2"));
+    }
+
+    #[test]
+    fn zero_level() {
+        let logger = make_logger_filter(vec![
+            directive(None, LevelFilter::Info),
+            directive(Some("crate1::mod1"), LevelFilter::Off),
+        ]);
+        assert!(!enabled(&logger.directives, Level::Error, "crate1::mod1"));
+        assert!(enabled(&logger.directives, Level::Info, "crate2::mod2"));
+    }
+
+    #[test]
+    fn parse_spec_matrix() {
+        // (spec, expected directives, expected message filter)
+        type Case<'a> = (
+            &'a str,
+            &'a [(Option<&'a str>, LevelFilter)],
+            Option<&'a str>,
+        );
+
+        let cases: &[Case] = &[
+            (
+                "crate1::mod1=error,crate1::mod2,crate2=debug",
+                &[
+                    (Some("crate1::mod1"), LevelFilter::Error),
+                    (Some("crate1::mod2"), LevelFilter::Trace),
+                    (Some("crate2"), LevelFilter::Debug),
+                ],
+                None,
+            ),
+            // multiple `=` in a directive
+            (
+                "crate1::mod1=warn=info,crate2=debug",
+                &[(Some("crate2"), LevelFilter::Debug)],
+                None,
+            ),
+            // invalid level
+            (
+                "crate1::mod1=noNumber,crate2=debug",
+                &[(Some("crate2"), LevelFilter::Debug)],
+                None,
+            ),
+            (
+                "crate1::mod1=wrong,crate2=warn",
+                &[(Some("crate2"), LevelFilter::Warn)],
+                None,
+            ),
+            // empty level
+            (
+                "crate1::mod1=wrong,crate2=",
+                &[(Some("crate2"), LevelFilter::Trace)],
+                None,
+            ),
+            // blank specs are ignored
+            ("", &[], None),
+            ("     ", &[], None),
+            (",", &[], None),
+            (",     ", &[], None),
+            ("     ,", &[], None),
+            // global level
+            (
+                "warn,crate2=debug",
+                &[
+                    (None, LevelFilter::Warn),
+                    (Some("crate2"), LevelFilter::Debug),
+                ],
+                None,
+            ),
+            ("warn", &[(None, LevelFilter::Warn)], None),
+            ("WARN", &[(None, LevelFilter::Warn)], None),
+            ("wArN", &[(None, LevelFilter::Warn)], None),
+            // message filters
+            (
+                "crate1::mod1=error,crate1::mod2,crate2=debug/abc",
+                &[
+                    (Some("crate1::mod1"), LevelFilter::Error),
+                    (Some("crate1::mod2"), LevelFilter::Trace),
+                    (Some("crate2"), LevelFilter::Debug),
+                ],
+                Some("abc"),
+            ),
+            (
+                "crate1::mod1=error=warn,crate2=debug/a.c",
+                &[(Some("crate2"), LevelFilter::Debug)],
+                Some("a.c"),
+            ),
+            (
+                "crate1/a*c",
+                &[(Some("crate1"), LevelFilter::Trace)],
+                Some("a*c"),
+            ),
+            (
+                "crate1/a+c",
+                &[(Some("crate1"), LevelFilter::Trace)],
+                Some("a+c"),
+            ),
+            // too many `/`
+            ("crate1/a/b", &[], None),
+        ];
+
+        for (spec, expected, expected_filter) in cases {
+            let parsed = parse_spec(spec);
+
+            let dirs: Vec<_> = parsed
+                .directives
+                .iter()
+                .map(|d| (d.name.as_deref(), d.level))
+                .collect();
+            assert_eq!(*expected, &dirs[..], "spec `{}`", spec);
+            assert_eq!(
+                *expected_filter,
+                parsed.filter.map(|f| f.to_string()).as_deref(),
+                "spec `{}`",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn try_parse_reports_invalid_directives() {
+        // (spec, expected (directive, position) pairs)
+        let cases: &[(&str, &[(&str, usize)])] = &[
+            ("info,crate1=debug/abc", &[]),
+            ("crate1::mod1=trace,crate2=", &[]),
+            ("", &[]),
+            (" , ", &[]),
+            ("infoo", &[("infoo", 0)]),
+            ("=debug,,", &[("=debug", 0), ("", 7), ("", 8)]),
+            ("warn, crate1=loud", &[("crate1=loud", 6)]),
+            (
+                "crate1=warn=info,banana",
+                &[("crate1=warn=info", 0), ("banana", 17)],
+            ),
+            ("info/a/b", &[("info/a/b", 6)]),
+        ];
+
+        for (spec, expected) in cases {
+            let result = Builder::new().try_parse(spec).map(|_| ());
+            let invalid: Vec<_> = match &result {
+                Ok(()) => Vec::new(),
+                Err(e) => e
+                    .invalid_directives()
+                    .iter()
+                    .map(|i| (i.directive(), i.position()))
+                    .collect(),
+            };
+            assert_eq!(*expected, &invalid[..], "spec `{}`", spec);
+        }
+    }
+
+    #[test]
+    fn try_parse_keeps_builder_on_error() {
+        let mut builder = Builder::new();
+        builder.parse("info/needle");
+
+        let err = builder.try_parse("debug,crate1=loud").unwrap_err();
+        assert_eq!(
+            "invalid logging spec: 'crate1=loud' at 6 (invalid level 'loud')",
+            err.to_string()
+        );
+
+        let logger = builder.build();
+        assert_eq!(LevelFilter::Info, logger.filter());
+        assert!(logger.filter.is_some());
+    }
+
+    #[test]
+    fn parse_accepts_lenient_directives() {
+        let parsed = parse_spec("infoo,=debug,crate1=loud");
+        let lenient: Vec<_> = parsed
+            .errors
+            .iter()
+            .map(|e: &InvalidDirective| (e.directive(), e.lenient))
+            .collect();
+        assert_eq!(
+            vec![("infoo", true), ("=debug", true), ("crate1=loud", false)],
+            lenient
+        );
+
+        let logger = Builder::new().parse("infoo,=debug,crate1=loud").build();
+        assert!(enabled(&logger.directives, Level::Trace, "infoo"));
+        assert!(enabled(&logger.directives, Level::Debug, "crate1"));
+    }
+
+    #[test]
+    fn parse_exclusions() {
+        let logger = Builder::new()
+            .parse("-candid,debug,candid=trace,candid::de=warn,-serde::json")
+            .build();
+
+        assert!(enabled(&logger.directives, Level::Debug, "my_canister"));
+        assert!(!enabled(&logger.directives, Level::Error, "candid"));
+        assert!(!enabled(&logger.directives, Level::Error, "candid::ser"));
+        assert!(enabled(&logger.directives, Level::Warn, "candid::de"));
+        assert!(!enabled(&logger.directives, Level::Info, "candid::de"));
+        assert!(enabled(&logger.directives, Level::Debug, "serde"));
+        assert!(!enabled(&logger.directives, Level::Error, "serde::json"));
+        assert_eq!(LevelFilter::Debug, logger.filter());
+
+        assert_eq!(
+            "debug,-candid,candid::de=warn,-serde::json",
+            logger.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_level_ranges() {
+        let logger = Builder::new()
+            .parse(
+                "only:error,crate1=warn..=info,crate2=trace..=DEBUG,crate3=only:warn,crate4=info",
+            )
+            .build();
+
+        let cases = [
+            ("other", &[Level::Error][..]),
+            ("crate1", &[Level::Warn, Level::Info]),
+            ("crate2", &[Level::Debug, Level::Trace]),
+            ("crate3", &[Level::Warn]),
+            ("crate4", &[Level::Error, Level::Warn, Level::Info]),
+        ];
+        for (target, expected) in cases {
+            for level in LEVELS {
+                assert_eq!(
+                    expected.contains(&level),
+                    enabled(&logger.directives, level, target),
+                    "{} at {}",
+                    target,
+                    level
+                );
+            }
+        }
+        assert_eq!(LevelFilter::Trace, logger.filter());
+
+        assert_eq!(
+            "error,crate1=warn..=info,crate2=debug..=trace,crate3=only:warn,crate4=info",
+            logger.to_string()
+        );
+
+        let err = Builder::new()
+            .try_parse("crate1=off..=info,crate2=only:,info..=")
+            .unwrap_err();
+        assert_eq!(3, err.invalid_directives().len());
+    }
+
+    #[test]
+    fn parse_glob_directives() {
+        let logger = Builder::new()
+            .parse("warn,svc_*=debug,svc_ledger=info,svc_*::**::store=trace,svc=error,-svc_*::tmp")
+            .build();
+
+        // (target, most verbose level enabled)
+        let cases = [
+            ("other", Some(Level::Warn)),
+            ("svc_users", Some(Level::Debug)),
+            ("svc_index::api", Some(Level::Debug)),
+            // the literal wins over the shorter pattern
+            ("svc_ledger", Some(Level::Info)),
+            ("svc_ledger::api", Some(Level::Info)),
+            // the longer pattern wins over the literal
+            ("svc_ledger::api::store", Some(Level::Trace)),
+            ("svc_users::store", Some(Level::Debug)),
+            ("svc_users::tmp", None),
+            ("svc", Some(Level::Error)),
+        ];
+        for (target, expected) in cases {
+            let max = LEVELS
+                .iter()
+                .copied()
+                .filter(|level| enabled(&logger.directives, *level, target))
+                .max();
+            assert_eq!(expected, max, "target `{}`", target);
+        }
+
+        assert_eq!(
+            "warn,svc=error,svc_*=debug,svc_*::**::store=trace,-svc_*::tmp,svc_ledger=info",
+            logger.to_string()
+        );
+    }
+
+    #[test]
+    fn cached_matching_agrees_with_the_directives() {
+        let modules = [
+            "a",
+            "a::b",
+            "a::b::c",
+            "ab",
+            "svc_*",
+            "svc_ledger",
+            "svc_*::**::db",
+        ];
+        let levels = [
+            "",
+            "=error",
+            "=warn",
+            "=info..=debug",
+            "=only:trace",
+            "=off",
+        ];
+        let mut targets: Vec<String> = ["a", "a::b::c::d", "ab::c", "svc_ledger::db", "svc"]
+            .iter()
+            .map(|target| target.to_string())
+            .collect();
+        // More targets than the slots of the cache, so the entries are replaced
+        targets.extend((0..100).map(|i| format!("{}::m{}", modules[i % 4], i)));
+
+        // A linear congruential generator, to pick the directives and records
+        let mut seed = 42u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (seed >> 33) as usize % bound
+        };
+
+        for _ in 0..50 {
+            let mut spec = vec!["warn".to_string()];
+            for _ in 0..1 + next(6) {
+                let module = modules[next(modules.len())];
+                spec.push(match next(4) {
+                    0 => format!("-{}", module),
+                    1 => format!("target:{}{}", targets[next(5)], levels[1 + next(5)]),
+                    _ => format!("{}{}", module, levels[next(levels.len())]),
+                });
+            }
+            let filter = Builder::new().parse(&spec.join(",")).build();
+
+            for _ in 0..500 {
+                let target = &targets[next(targets.len())];
+                let level = LEVELS[next(LEVELS.len())];
+                let metadata = log::Metadata::builder().level(level).target(target).build();
+                assert_eq!(
+                    enabled(&filter.directives, level, target),
+                    filter.enabled(&metadata),
+                    "`{}` at {} with `{}`",
+                    target,
+                    level,
+                    filter
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn glob_and_literal_of_same_length() {
+        let logger = Builder::new().parse("svc_*=debug,svc_=warn").build();
+        assert!(enabled(&logger.directives, Level::Warn, "svc_users"));
+        assert!(!enabled(&logger.directives, Level::Info, "svc_users"));
+    }
+
+    #[test]
+    fn parse_target_directives() {
+        let logger = Builder::new()
+            .parse("warn,audit=error,target:audit=info,my_canister=debug,target:my_canister::db")
+            .build();
+
+        // target directives match the exact target only
+        assert!(enabled(&logger.directives, Level::Info, "audit"));
+        assert!(!enabled(&logger.directives, Level::Debug, "audit"));
+        assert!(!enabled(&logger.directives, Level::Warn, "audit::mod1"));
+        assert!(enabled(&logger.directives, Level::Trace, "my_canister::db"));
+        assert!(!enabled(
+            &logger.directives,
+            Level::Trace,
+            "my_canister::db::pool"
+        ));
+        assert!(enabled(
+            &logger.directives,
+            Level::Debug,
+            "my_canister::db::pool"
+        ));
+        assert!(!enabled(&logger.directives, Level::Info, "other"));
+
+        assert_eq!(
+            "warn,audit=error,my_canister=debug,target:audit=info,target:my_canister::db=trace",
+            logger.to_string()
+        );
+
+        let derived = logger.to_builder().remove_module("audit").build();
+        assert_eq!(
+            "warn,my_canister=debug,target:audit=info,target:my_canister::db=trace",
+            derived.to_string()
+        );
+    }
+
+    #[test]
+    fn matches_target_directive() {
+        let logger = Builder::new().parse("error,target:audit=info").build();
+
+        let record = |target, level| {
+            log::Record::builder()
+                .args(format_args!("message"))
+                .level(level)
+                .target(target)
+                .module_path(Some("my_canister::ledger"))
+                .build()
+        };
+
+        assert!(logger.matches(&record("audit", Level::Info)));
+        assert!(!logger.matches(&record("audit", Level::Debug)));
+        assert!(!logger.matches(&record("my_canister::ledger", Level::Info)));
+    }
+
+    #[test]
+    fn try_parse_rejects_invalid_exclusions_and_targets() {
+        let err = Builder::new()
+            .try_parse("info,-,-candid=debug,target:=info")
+            .unwrap_err();
+        let invalid: Vec<_> = err
+            .invalid_directives()
+            .iter()
+            .map(|i| (i.directive(), i.position()))
+            .collect();
+        assert_eq!(
+            vec![("-", 5), ("-candid=debug", 7), ("target:=info", 21)],
+            invalid
+        );
+    }
+
+    #[test]
+    fn describe_directives() {
+        let parsed =
+            super::validate_filters("warn..=info,-candid,target:audit=debug,svc_*=trace/a+")
+                .unwrap();
+        let described: Vec<_> = parsed
+            .directives
+            .iter()
+            .map(|d| {
+                (
+                    d.name.as_deref(),
+                    d.target,
+                    d.excluded,
+                    d.min_level,
+                    d.max_level,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (None, false, false, Level::Warn, LevelFilter::Info),
+                (Some("candid"), false, true, Level::Error, LevelFilter::Off),
+                (
+                    Some("svc_*"),
+                    false,
+                    false,
+                    Level::Error,
+                    LevelFilter::Trace
+                ),
+                (Some("audit"), true, false, Level::Error, LevelFilter::Debug),
+            ],
+            described
+        );
+        assert_eq!(Some("a+"), parsed.message_filter.as_deref());
+
+        let parsed = super::validate_filters("").unwrap();
+        assert_eq!(LevelFilter::Error, parsed.directives[0].max_level);
+        assert!(super::validate_filters("svc_*").is_err());
+    }
+
+    #[test]
+    fn matches_message_filter() {
+        let logger = Builder::new().parse("info/needle").build();
+
+        let record = |args| {
+            log::Record::builder()
+                .args(args)
+                .level(Level::Info)
+                .target("crate1")
+                .build()
+        };
+
+        assert!(logger.matches(&record(format_args!("a needle in a haystack"))));
+        assert!(!logger.matches(&record(format_args!("just hay"))));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_regex_filter() {
+        let logger = Builder::new()
+            .parse("crate1=debug,crate2=warn/^time(out)?$")
+            .build();
+
+        let record = |target, level, args| {
+            log::Record::builder()
+                .args(args)
+                .level(level)
+                .target(target)
+                .build()
+        };
+
+        assert!(logger.matches(&record("crate1", Level::Debug, format_args!("timeout"))));
+        assert!(logger.matches(&record("crate1", Level::Debug, format_args!("time"))));
+        assert!(!logger.matches(&record("crate1", Level::Debug, format_args!("timeouts"))));
+        assert!(logger.matches(&record("crate2", Level::Warn, format_args!("timeout"))));
+        assert!(!logger.matches(&record("crate2", Level::Info, format_args!("timeout"))));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn try_parse_rejects_invalid_regex() {
+        let mut builder = Builder::new();
+        builder.parse("info/valid");
+
+        assert!(builder.try_parse("debug/(unclosed").is_err());
+
+        let logger = builder.build();
+        assert_eq!(LevelFilter::Info, logger.filter());
+        assert_eq!(
+            Some("valid".to_string()),
+            logger.filter.map(|f| f.to_string())
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn parse_ignores_invalid_regex() {
+        let logger = Builder::new().parse("debug/(unclosed").build();
+        assert_eq!(LevelFilter::Debug, logger.filter());
+        assert!(logger.filter.is_none());
+    }
+}
@@ -0,0 +1,83 @@
+//! Flushing the batched records at the end of a call, and the queued ones at
+//! the end of the program.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::{fmt, thread};
+
+use crate::fmt::writer::Writer;
+
+/// Writes the batched records of a logger when dropped.
+///
+/// Returned by [`LoggerConfig::flush_on_drop`], it's meant to be kept alive
+/// for the whole update call, so the records of the call are printed when the
+/// call returns.
+///
+/// The records are also flushed when the guard is dropped while unwinding
+/// from a panic, in which case the errors and panics of the targets are
+/// ignored. Nested guards flush the records logged so far when each of them
+/// is dropped.
+///
+/// [`LoggerConfig::flush_on_drop`]: struct.LoggerConfig.html#method.flush_on_drop
+#[must_use = "the records are flushed when the guard is dropped"]
+pub struct FlushGuard {
+    writer: Arc<Writer>,
+}
+
+impl FlushGuard {
+    pub(crate) fn new(writer: Arc<Writer>) -> FlushGuard {
+        FlushGuard { writer }
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // A panic escaping the drop while unwinding would abort
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| self.writer.flush()));
+        } else {
+            let _ = self.writer.flush();
+        }
+    }
+}
+
+impl fmt::Debug for FlushGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlushGuard").finish()
+    }
+}
+
+/// Stops the background writer thread of a logger when dropped, once all the
+/// queued records are written.
+///
+/// Returned by [`LoggerConfig::worker_guard`], it's meant to be kept alive
+/// until the end of `main`, so no record is lost when the program exits.
+/// Records logged after the guard is dropped are written synchronously.
+///
+/// [`LoggerConfig::worker_guard`]: struct.LoggerConfig.html#method.worker_guard
+#[cfg(feature = "async-writer")]
+#[must_use = "the writer thread is stopped when the guard is dropped"]
+pub struct WorkerGuard {
+    writer: Arc<Writer>,
+}
+
+#[cfg(feature = "async-writer")]
+impl WorkerGuard {
+    pub(crate) fn new(writer: Arc<Writer>) -> WorkerGuard {
+        WorkerGuard { writer }
+    }
+}
+
+#[cfg(feature = "async-writer")]
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let _ = self.writer.shutdown();
+    }
+}
+
+#[cfg(feature = "async-writer")]
+impl fmt::Debug for WorkerGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WorkerGuard").finish()
+    }
+}
@@ -0,0 +1,158 @@
+/*
+This internal module contains helpers to write binary payloads as hex.
+
+The bytes are encoded through a small stack buffer, so no allocation happens
+regardless of the payload size.
+*/
+
+use std::io::{self, Write};
+
+use crate::fmt::Formatter;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// The amount of bytes encoded in one go.
+const CHUNK_LEN: usize = 32;
+
+/// The amount of bytes in each row of a hex dump.
+const ROW_LEN: usize = 16;
+
+impl Formatter {
+    /// Writes `bytes` as lowercase hex.
+    ///
+    /// If there are more than `max_len` bytes, only the first and the last ones
+    /// are written, separated by `…`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let mut builder = ic_log::Builder::new();
+    ///
+    /// builder.format(|buf, record| {
+    ///     write!(buf, "{} hash=", record.args())?;
+    ///     buf.write_hex(&[0xde, 0xad, 0xbe, 0xef], 32)?;
+    ///     writeln!(buf)
+    /// });
+    /// ```
+    pub fn write_hex(&mut self, bytes: &[u8], max_len: usize) -> io::Result<()> {
+        if bytes.len() <= max_len {
+            return self.write_hex_bytes(bytes);
+        }
+
+        let head = (max_len + 1) / 2;
+        let tail = max_len / 2;
+
+        self.write_hex_bytes(&bytes[..head])?;
+        self.write_all("…".as_bytes())?;
+        self.write_hex_bytes(&bytes[bytes.len() - tail..])
+    }
+
+    /// Writes `bytes` as rows of 16 lowercase hex bytes, each prefixed by its offset.
+    ///
+    /// Rows are separated by a newline, with no newline after the last one.
+    ///
+    /// ```text
+    /// 00000000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f
+    /// 00000010: 10 11
+    /// ```
+    pub fn write_hex_dump(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for (row, chunk) in bytes.chunks(ROW_LEN).enumerate() {
+            if row > 0 {
+                self.write_all(b"\n")?;
+            }
+
+            write!(self, "{:08x}:", row * ROW_LEN)?;
+
+            let mut line = [0u8; ROW_LEN * 3];
+            for (idx, byte) in chunk.iter().enumerate() {
+                line[idx * 3] = b' ';
+                line[idx * 3 + 1] = HEX_DIGITS[(byte >> 4) as usize];
+                line[idx * 3 + 2] = HEX_DIGITS[(byte & 0xf) as usize];
+            }
+            self.write_all(&line[..chunk.len() * 3])?;
+        }
+
+        Ok(())
+    }
+
+    fn write_hex_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut encoded = [0u8; CHUNK_LEN * 2];
+
+        for chunk in bytes.chunks(CHUNK_LEN) {
+            for (idx, byte) in chunk.iter().enumerate() {
+                encoded[idx * 2] = HEX_DIGITS[(byte >> 4) as usize];
+                encoded[idx * 2 + 1] = HEX_DIGITS[(byte & 0xf) as usize];
+            }
+            self.write_all(&encoded[..chunk.len() * 2])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::writer;
+    use crate::fmt::Formatter;
+
+    fn written(f: &Formatter) -> String {
+        String::from_utf8(f.buf.borrow().bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn write_hex_short_payload() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+
+        f.write_hex(&[0x00, 0x0f, 0xab, 0xff], 8).unwrap();
+
+        assert_eq!("000fabff", written(&f));
+    }
+
+    #[test]
+    fn write_hex_long_payload() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+
+        let bytes: Vec<u8> = (0..100).collect();
+        f.write_hex(&bytes, 100).unwrap();
+
+        assert_eq!(200, written(&f).len());
+    }
+
+    #[test]
+    fn write_hex_elides_middle() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+
+        f.write_hex(&[1, 2, 3, 4, 5, 6, 7], 3).unwrap();
+
+        assert_eq!("0102…07", written(&f));
+    }
+
+    #[test]
+    fn write_hex_dump_rows() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+
+        let bytes: Vec<u8> = (0..18).collect();
+        f.write_hex_dump(&bytes).unwrap();
+
+        assert_eq!(
+            "00000000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n00000010: 10 11",
+            written(&f)
+        );
+    }
+
+    #[test]
+    fn write_hex_dump_empty() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+
+        f.write_hex_dump(&[]).unwrap();
+
+        assert_eq!("", written(&f));
+    }
+}
@@ -4,18 +4,21 @@ use humantime::{
     format_rfc3339_micros, format_rfc3339_millis, format_rfc3339_nanos, format_rfc3339_seconds,
 };
 
-use crate::{fmt::{Formatter, TimestampPrecision}, platform};
+use std::time::{Duration, SystemTime};
 
-pub(in crate::fmt) mod glob {
-    pub use super::*;
-}
+use crate::fmt::{Formatter, TimestampPrecision};
 
 impl Formatter {
+    // The time of the current record, from the time source of the logger
+    fn now(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.now_nanos())
+    }
+
     /// Get a [`Timestamp`] for the current date and time in UTC.
     /// [`Timestamp`]: struct.Timestamp.html
     pub fn timestamp(&self) -> Timestamp {
         Timestamp {
-            time: platform::current_system_time(),
+            time: self.now(),
             precision: TimestampPrecision::Seconds,
         }
     }
@@ -24,7 +27,7 @@ impl Formatter {
     /// second precision.
     pub fn timestamp_seconds(&self) -> Timestamp {
         Timestamp {
-            time: platform::current_system_time(),
+            time: self.now(),
             precision: TimestampPrecision::Seconds,
         }
     }
@@ -33,7 +36,7 @@ impl Formatter {
     /// millisecond precision.
     pub fn timestamp_millis(&self) -> Timestamp {
         Timestamp {
-            time: platform::current_system_time(),
+            time: self.now(),
             precision: TimestampPrecision::Millis,
         }
     }
@@ -42,7 +45,7 @@ impl Formatter {
     /// microsecond precision.
     pub fn timestamp_micros(&self) -> Timestamp {
         Timestamp {
-            time: platform::current_system_time(),
+            time: self.now(),
             precision: TimestampPrecision::Micros,
         }
     }
@@ -51,7 +54,7 @@ impl Formatter {
     /// nanosecond precision.
     pub fn timestamp_nanos(&self) -> Timestamp {
         Timestamp {
-            time: platform::current_system_time(),
+            time: self.now(),
             precision: TimestampPrecision::Nanos,
         }
     }
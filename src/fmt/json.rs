@@ -0,0 +1,139 @@
+/*
+This internal module contains the serde-powered format.
+
+It is only available with the `json` feature.
+*/
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use log::Record;
+use serde::Serialize;
+
+use crate::fmt::{FormatFn, Formatter};
+
+/// Builds a format that serializes the value returned by `f` as JSON.
+///
+/// The suffix is appended by the format builder.
+pub(crate) fn serde_format<F, T>(f: F) -> FormatFn
+where
+    F: Fn(&Record) -> T + Sync + Send + 'static,
+    T: Serialize,
+{
+    Arc::new(move |buf: &mut Formatter, record: &Record| {
+        serde_json::to_writer(&mut *buf, &f(record)).map_err(io::Error::from)
+    })
+}
+
+/// A record serialized by the built-in JSON format.
+#[derive(Serialize)]
+struct JsonRecord {
+    level: &'static str,
+    target: String,
+    message: String,
+}
+
+/// Builds the built-in JSON format, writing a record per line.
+pub(crate) fn record_format() -> FormatFn {
+    let format = serde_format(|record| JsonRecord {
+        level: record.level().as_str(),
+        target: record.target().to_owned(),
+        message: record.args().to_string(),
+    });
+    Arc::new(move |buf: &mut Formatter, record: &Record| {
+        format(buf, record)?;
+        buf.write_all(b"\n")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+    use serde::ser::Error;
+    use serde::{Serialize, Serializer};
+
+    use super::*;
+    use crate::fmt::{self, writer};
+
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        level: &'a str,
+        message: String,
+    }
+
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("unserializable"))
+        }
+    }
+
+    fn record_written(mut format: fmt::Builder) -> io::Result<String> {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+        let buf = f.buf.clone();
+
+        let format = format.build();
+        format(
+            &mut f,
+            &Record::builder()
+                .args(format_args!("log message"))
+                .level(Level::Warn)
+                .build(),
+        )?;
+        f.flush()?;
+
+        let written = String::from_utf8(buf.borrow().bytes().to_vec()).unwrap();
+        Ok(written)
+    }
+
+    #[test]
+    fn serde_format_writes_json_and_suffix() {
+        let format = fmt::Builder {
+            serde_format: Some(serde_format(|record| Entry {
+                level: record.level().as_str(),
+                message: record.args().to_string(),
+            })),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "{\"level\":\"WARN\",\"message\":\"log message\"}\n",
+            record_written(format).unwrap()
+        );
+    }
+
+    #[test]
+    fn serde_format_uses_configured_suffix() {
+        let mut format = fmt::Builder::default();
+        format.default_format.suffix("\r\n");
+        format.serde_format = Some(serde_format(|record| record.level().as_str()));
+
+        assert_eq!("\"WARN\"\r\n", record_written(format).unwrap());
+    }
+
+    #[test]
+    fn record_format_writes_a_json_line() {
+        let format = fmt::Builder {
+            custom_format: Some(record_format()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "{\"level\":\"WARN\",\"target\":\"\",\"message\":\"log message\"}\n",
+            record_written(format).unwrap()
+        );
+    }
+
+    #[test]
+    fn serde_format_errors_are_io_errors() {
+        let format = fmt::Builder {
+            serde_format: Some(serde_format(|_| Unserializable)),
+            ..Default::default()
+        };
+
+        let err = record_written(format).unwrap_err();
+        assert!(err.to_string().contains("unserializable"));
+    }
+}
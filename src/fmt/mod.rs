@@ -29,19 +29,28 @@
 //! [`Builder::format`]: ../struct.Builder.html#method.format
 //! [`Write`]: https://doc.rust-lang.org/stable/std/io/trait.Write.html
 
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::fmt::Display;
 use std::io::prelude::*;
+use std::panic::AssertUnwindSafe;
 use std::rc::Rc;
-use std::{fmt, io, mem};
+use std::sync::Arc;
+use std::{fmt, io, panic};
 
+mod hex;
 mod humantime;
+#[cfg(feature = "json")]
+pub(crate) mod json;
 use log::Record;
 
 pub(crate) mod writer;
 
-use self::writer::{Buffer, Writer};
+use self::writer::{Buffer, Route, Style, StyledValue, Writer};
+use crate::info::FormatInfo;
+use crate::platform::Clock;
 
+pub use self::writer::{OverflowPolicy, Target, TargetId, WriteStyle};
 
 /// Formatting precision of timestamps.
 ///
@@ -67,6 +76,45 @@ impl Default for TimestampPrecision {
     }
 }
 
+/// A format built into `ic_log`.
+///
+/// See [`LoggerConfig::set_builtin_format`].
+///
+/// [`LoggerConfig::set_builtin_format`]: ../struct.LoggerConfig.html#method.set_builtin_format
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FormatKind {
+    /// The default format, with the default settings of [`ConfigurableFormat`].
+    Default,
+    /// A JSON object per line, with the level, target and message of the record.
+    ///
+    /// Only available with the `json` feature.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl FormatKind {
+    pub(crate) fn build(self) -> BuiltFormat {
+        let options = ConfigurableFormat::default();
+        match self {
+            FormatKind::Default => {
+                let fmt = options.clone();
+                BuiltFormat {
+                    format: Arc::new(move |buf, record| fmt.write(buf, record)),
+                    kind: "default",
+                    options,
+                }
+            }
+            #[cfg(feature = "json")]
+            FormatKind::Json => BuiltFormat {
+                format: json::record_format(),
+                kind: "json",
+                options,
+            },
+        }
+    }
+}
+
 /// A formatter to write logs into.
 ///
 /// `Formatter` implements the standard [`Write`] trait for writing log records.
@@ -90,22 +138,119 @@ impl Default for TimestampPrecision {
 /// [`style`]: #method.style
 pub struct Formatter {
     buf: Rc<RefCell<Buffer>>,
+    // The clock of the logger, or `None` for the default platform
+    clock: Option<Arc<Clock>>,
+    // The time of the current record, read once from the time source
+    now: Cell<Option<u64>>,
 }
 
 impl Formatter {
     pub(crate) fn new(writer: &Writer) -> Self {
         Formatter {
             buf: Rc::new(RefCell::new(writer.buffer())),
+            clock: None,
+            now: Cell::new(None),
+        }
+    }
+
+    /// Prepares the formatter for a new record, whose time is read from
+    /// `clock` the first time it is needed.
+    pub(crate) fn start_record(&mut self, clock: &Arc<Clock>) {
+        self.clock = Some(clock.clone());
+        self.now.set(None);
+    }
+
+    // The time of the current record in nanoseconds, the same for all the
+    // timestamps of the record
+    fn now_nanos(&self) -> u64 {
+        if let Some(now) = self.now.get() {
+            return now;
         }
+
+        let now = match &self.clock {
+            Some(clock) => clock.now_nanos(),
+            None => crate::platform::current_timestamp_in_nanosecs(),
+        };
+        self.now.set(Some(now));
+        now
     }
 
-    pub(crate) fn print(&self, writer: &Writer) -> io::Result<()> {
-        writer.print(&self.buf.borrow())
+    /// Prints the buffer to the targets routed for the level and the format
+    /// of the record, or to all the targets if there is no record.
+    pub(crate) fn print(&self, writer: &Writer, route: Option<Route>) -> io::Result<()> {
+        writer.print(&self.buf.borrow(), route)
     }
 
     pub(crate) fn clear(&mut self) {
         self.buf.borrow_mut().clear()
     }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buf.borrow().len()
+    }
+
+    /// Copies the formatted bytes.
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
+        self.buf.borrow().bytes().to_vec()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.borrow().capacity()
+    }
+
+    pub(crate) fn is_colored(&self) -> bool {
+        self.buf.borrow().is_colored()
+    }
+
+    /// Wraps the value in the style, if this formatter writes styles.
+    fn styled<T>(&self, style: Style, value: T) -> StyledValue<T> {
+        StyledValue {
+            style: Some(style).filter(|_| self.buf.borrow().is_colored()),
+            value,
+        }
+    }
+
+    /// Formats the record with `format`.
+    ///
+    /// If the format panics the record is replaced with a fixed message and
+    /// `None` is returned. Panics can only be caught on native targets, on
+    /// wasm32 they trap the canister.
+    pub(crate) fn format(&mut self, format: &FormatFn, record: &Record) -> Option<io::Result<()>> {
+        match panic::catch_unwind(AssertUnwindSafe(|| format(self, record))) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                let mut buf = self.buf.borrow_mut();
+                buf.clear();
+                let message = format!("{} {} {}\n", FORMAT_FAILED, record.level(), record.target());
+                let _ = buf.write(message.as_bytes());
+                None
+            }
+        }
+    }
+
+    /// Applies the redaction hook to the rendered record.
+    ///
+    /// A borrowed result leaves the record unchanged. If the hook panics the
+    /// whole record is replaced with a fixed message.
+    pub(crate) fn redact(&mut self, redact: RedactFn) {
+        let mut buf = self.buf.borrow_mut();
+
+        let redacted = {
+            // Only records with invalid UTF-8 are copied
+            let text = crate::platform::text(buf.bytes());
+            match panic::catch_unwind(|| redact(&text)) {
+                Ok(Cow::Borrowed(_)) => None,
+                Ok(Cow::Owned(redacted)) => Some(redacted),
+                Err(_) if text.ends_with('\n') => Some(REDACTION_FAILED.to_owned() + "\n"),
+                Err(_) => Some(REDACTION_FAILED.to_owned()),
+            }
+        };
+
+        if let Some(redacted) = redacted {
+            buf.clear();
+            let _ = buf.write(redacted.as_bytes());
+        }
+    }
 }
 
 impl Write for Formatter {
@@ -124,102 +269,392 @@ impl fmt::Debug for Formatter {
     }
 }
 
-pub(crate) type FormatFn = Box<dyn Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send>;
+pub(crate) type FormatFn = Arc<dyn Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send>;
+
+/// The format of a logger, with what is needed to describe it.
+pub(crate) struct BuiltFormat {
+    pub format: FormatFn,
+    pub kind: &'static str,
+    pub options: ConfigurableFormat,
+}
+
+impl BuiltFormat {
+    pub fn info(&self) -> FormatInfo {
+        FormatInfo {
+            kind: self.kind.to_owned(),
+            level: self.options.level,
+            target: self.options.target,
+            module_path: self.options.module_path,
+            indent: self.options.indent,
+            suffix: self.options.suffix.to_owned(),
+        }
+    }
+}
+
+pub(crate) type RedactFn = for<'a> fn(&'a str) -> Cow<'a, str>;
+
+pub(crate) type ErrorFn = Arc<dyn Fn(&io::Error) + Sync + Send>;
+
+/// The message written in place of a record whose redaction hook panicked.
+const REDACTION_FAILED: &str = "[REDACTION FAILED]";
+
+/// The message written in place of a record whose format panicked, followed by
+/// the level and target of the record.
+const FORMAT_FAILED: &str = "[FORMAT FAILED]";
 
+#[derive(Default)]
 pub(crate) struct Builder {
-    pub format_timestamp: Option<TimestampPrecision>,
-    pub format_module_path: bool,
-    pub format_target: bool,
-    pub format_level: bool,
-    pub format_indent: Option<usize>,
+    pub default_format: ConfigurableFormat,
     pub custom_format: Option<FormatFn>,
-    pub format_suffix: &'static str,
+    pub serde_format: Option<FormatFn>,
     built: bool,
 }
 
-impl Default for Builder {
-    fn default() -> Self {
+// A clone of a built builder can be built again.
+impl Clone for Builder {
+    fn clone(&self) -> Self {
         Builder {
-            format_timestamp: Some(Default::default()),
-            format_module_path: false,
-            format_target: true,
-            format_level: true,
-            format_indent: Some(4),
-            custom_format: None,
-            format_suffix: "\n",
+            default_format: self.default_format.clone(),
+            custom_format: self.custom_format.clone(),
+            serde_format: self.serde_format.clone(),
             built: false,
         }
     }
 }
 
 impl Builder {
+    /// Builds the format like [`build`], along with its description.
+    ///
+    /// [`build`]: #method.build
+    pub fn build_described(&mut self) -> BuiltFormat {
+        let kind = if self.custom_format.is_some() {
+            "custom"
+        } else if self.serde_format.is_some() {
+            "serde"
+        } else {
+            "default"
+        };
+        let options = self.default_format.clone();
+        BuiltFormat {
+            format: self.build(),
+            kind,
+            options,
+        }
+    }
+
     /// Convert the format into a callable function.
     ///
     /// If the `custom_format` is `Some`, then any `default_format` switches are ignored.
-    /// If the `custom_format` is `None`, then a default format is returned.
+    /// If the `serde_format` is `Some`, then it is followed by the `default_format` suffix.
+    /// If both are `None`, then a default format is returned.
     /// Any `default_format` switches set to `false` won't be written by the format.
     pub fn build(&mut self) -> FormatFn {
         assert!(!self.built, "attempt to re-use consumed builder");
+        self.built = true;
 
-        let built = mem::replace(
-            self,
-            Builder {
-                built: true,
-                ..Default::default()
-            },
-        );
+        let built = self.clone();
 
         if let Some(fmt) = built.custom_format {
             fmt
-        } else {
-            Box::new(move |buf, record| {
-                let fmt = DefaultFormat {
-                    timestamp: built.format_timestamp,
-                    module_path: built.format_module_path,
-                    target: built.format_target,
-                    level: built.format_level,
-                    written_header_value: false,
-                    indent: built.format_indent,
-                    suffix: built.format_suffix,
-                    buf,
-                };
-
-                fmt.write(record)
+        } else if let Some(fmt) = built.serde_format {
+            let suffix = built.default_format.suffix;
+            Arc::new(move |buf, record| {
+                fmt(buf, record)?;
+                buf.write_all(suffix.as_bytes())
             })
+        } else {
+            let plan = built.default_format.plan();
+            Arc::new(move |buf, record| plan.write(buf, record))
         }
     }
 }
 
-type SubtleStyle = &'static str;
-
-/// The default format.
+/// The default `ic_log` format.
 ///
-/// This format needs to work with any combination of crate features.
-struct DefaultFormat<'a> {
+/// This is the format used when no custom format is set on the [`Builder`].
+/// It is exposed so that custom formats can reuse the default layout and only
+/// add what they need on top of it.
+///
+/// # Examples
+///
+/// Append the source file to every record:
+///
+/// ```
+/// use std::io::Write;
+/// use ic_log::fmt::ConfigurableFormat;
+///
+/// let mut default_format = ConfigurableFormat::default();
+/// default_format.suffix("");
+///
+/// let mut builder = ic_log::Builder::new();
+///
+/// builder.format(move |buf, record| {
+///     default_format.write(buf, record)?;
+///     writeln!(buf, " ({})", record.file().unwrap_or("<unknown>"))
+/// });
+/// ```
+///
+/// [`Builder`]: ../struct.Builder.html
+#[derive(Clone, Debug)]
+pub struct ConfigurableFormat {
     timestamp: Option<TimestampPrecision>,
     module_path: bool,
     target: bool,
+    target_width: Option<usize>,
+    origin_fallback: bool,
     level: bool,
-    written_header_value: bool,
+    method_name: bool,
+    indent: Option<usize>,
+    suffix: &'static str,
+}
+
+impl Default for ConfigurableFormat {
+    fn default() -> Self {
+        ConfigurableFormat {
+            timestamp: Some(Default::default()),
+            module_path: false,
+            target: true,
+            target_width: None,
+            origin_fallback: true,
+            level: true,
+            method_name: false,
+            indent: Some(4),
+            suffix: "\n",
+        }
+    }
+}
+
+impl ConfigurableFormat {
+    /// Configures the precision of the timestamp.
+    /// A value of `None` disables the timestamp.
+    pub fn timestamp(&mut self, timestamp: Option<TimestampPrecision>) -> &mut Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Whether or not to write the module path.
+    pub fn module_path(&mut self, write: bool) -> &mut Self {
+        self.module_path = write;
+        self
+    }
+
+    /// Whether or not to write the target.
+    pub fn target(&mut self, write: bool) -> &mut Self {
+        self.target = write;
+        self
+    }
+
+    /// Configures the column width of the target.
+    ///
+    /// Shorter targets are padded with spaces and longer ones are truncated
+    /// with a trailing `…`, so that messages start at the same column.
+    /// A value of `None` writes the target as is.
+    pub fn target_width(&mut self, width: Option<usize>) -> &mut Self {
+        self.target_width = width;
+        self
+    }
+
+    /// Whether or not to write the target when the module path is missing, and
+    /// vice versa.
+    ///
+    /// This only applies when exactly one of the module path and the target is
+    /// enabled, so that records always carry some origin information.
+    pub fn origin_fallback(&mut self, fallback: bool) -> &mut Self {
+        self.origin_fallback = fallback;
+        self
+    }
+
+    /// Whether or not to write the level.
+    pub fn level(&mut self, write: bool) -> &mut Self {
+        self.level = write;
+        self
+    }
+
+    /// Whether or not to write the name of the executing canister method.
+    ///
+    /// The method is written as `method=<name>`, or `method=-` when no name was
+    /// recorded for the current message execution.
+    /// See [`context::set_method_name`]. This has no effect on native targets.
+    ///
+    /// [`context::set_method_name`]: ../context/fn.set_method_name.html
+    pub fn method_name(&mut self, write: bool) -> &mut Self {
+        self.method_name = write;
+        self
+    }
+
+    /// Configures the amount of spaces to use to indent multiline log records.
+    /// A value of `None` disables any kind of indentation.
+    pub fn indent(&mut self, indent: Option<usize>) -> &mut Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Configures the end of line suffix.
+    pub fn suffix(&mut self, suffix: &'static str) -> &mut Self {
+        self.suffix = suffix;
+        self
+    }
+
+    /// Writes the record to the formatter using this format.
+    pub fn write(&self, buf: &mut Formatter, record: &Record) -> io::Result<()> {
+        self.plan().write(buf, record)
+    }
+
+    /// Decides which header fields are written, once for all the records.
+    fn plan(&self) -> FormatPlan {
+        let mut plan = FormatPlan {
+            header: [HeaderField::Timestamp; MAX_HEADER_FIELDS],
+            header_len: 0,
+            indent: self.indent,
+            suffix: self.suffix,
+        };
+        let mut push = |field| {
+            plan.header[plan.header_len] = field;
+            plan.header_len += 1;
+        };
+
+        if self.timestamp.is_some() {
+            push(HeaderField::Timestamp);
+        }
+        if self.level {
+            push(HeaderField::Level);
+        }
+        if self.module_path {
+            push(HeaderField::ModulePath {
+                target_fallback: self.origin_fallback && !self.target,
+            });
+        }
+        if self.target {
+            push(HeaderField::Target {
+                width: self.target_width,
+                module_path_fallback: self.origin_fallback && !self.module_path,
+            });
+        }
+        if self.method_name && cfg!(all(target_arch = "wasm32", feature = "ic")) {
+            push(HeaderField::MethodName);
+        }
+        plan
+    }
+}
+
+const MAX_HEADER_FIELDS: usize = 5;
+
+/// A header field written by a [`FormatPlan`].
+#[derive(Clone, Copy, Debug)]
+enum HeaderField {
+    Timestamp,
+    Level,
+    ModulePath {
+        target_fallback: bool,
+    },
+    Target {
+        width: Option<usize>,
+        module_path_fallback: bool,
+    },
+    MethodName,
+}
+
+/// The fields written by a [`ConfigurableFormat`], in order.
+///
+/// The built formats decide the fields once, so writing a record doesn't
+/// check the options of the format again.
+#[derive(Clone, Debug)]
+struct FormatPlan {
+    header: [HeaderField; MAX_HEADER_FIELDS],
+    header_len: usize,
     indent: Option<usize>,
+    suffix: &'static str,
+}
+
+impl FormatPlan {
+    fn write(&self, buf: &mut Formatter, record: &Record) -> io::Result<()> {
+        DefaultFormat {
+            plan: self,
+            written_header_value: false,
+            buf,
+        }
+        .write(record)
+    }
+}
+
+type SubtleStyle = StyledValue<&'static str>;
+
+/// A target padded or truncated to a fixed column width.
+struct TargetColumn<'a> {
+    target: &'a str,
+    width: usize,
+}
+
+impl<'a> Display for TargetColumn<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.width == 0 {
+            return Ok(());
+        }
+
+        // Truncate on a char boundary, leaving room for the ellipsis
+        match self.target.char_indices().nth(self.width) {
+            None => write!(f, "{:<width$}", self.target, width = self.width),
+            Some(_) => {
+                let end = self
+                    .target
+                    .char_indices()
+                    .nth(self.width - 1)
+                    .map(|(idx, _)| idx)
+                    .unwrap_or(self.target.len());
+                write!(f, "{}…", &self.target[..end])
+            }
+        }
+    }
+}
+
+/// The state of a single record written with a [`ConfigurableFormat`].
+///
+/// This format needs to work with any combination of crate features.
+struct DefaultFormat<'a> {
+    plan: &'a FormatPlan,
+    written_header_value: bool,
     buf: &'a mut Formatter,
-    suffix: &'a str,
 }
 
 impl<'a> DefaultFormat<'a> {
     fn write(mut self, record: &Record) -> io::Result<()> {
-        self.write_timestamp()?;
-        self.write_level(record)?;
-        self.write_module_path(record)?;
-        self.write_target(record)?;
+        for field in &self.plan.header[..self.plan.header_len] {
+            match *field {
+                HeaderField::Timestamp => self.write_timestamp()?,
+                HeaderField::Level => self.write_level(record)?,
+                HeaderField::ModulePath { target_fallback } => {
+                    self.write_module_path(record, target_fallback)?
+                }
+                HeaderField::Target {
+                    width,
+                    module_path_fallback,
+                } => self.write_target(record, width, module_path_fallback)?,
+                HeaderField::MethodName => self.write_method_name()?,
+            }
+        }
         self.finish_header()?;
 
         self.write_args(record)
     }
 
     fn subtle_style(&self, text: &'static str) -> SubtleStyle {
-        {
-            text
+        self.buf.styled(Style::subtle(), text)
+    }
+
+    // Writes the brace before the first header value, or the space before
+    // the others. The fixed parts of the header are written as bytes,
+    // skipping the formatting machinery.
+    fn open_header_value(&mut self) -> io::Result<()> {
+        if self.written_header_value {
+            return self.buf.write_all(b" ");
+        }
+
+        self.written_header_value = true;
+        if self.buf.is_colored() {
+            let open_brace = self.subtle_style("[");
+            write!(self.buf, "{}", open_brace)
+        } else {
+            self.buf.write_all(b"[")
         }
     }
 
@@ -227,75 +662,98 @@ impl<'a> DefaultFormat<'a> {
     where
         T: Display,
     {
-        if !self.written_header_value {
-            self.written_header_value = true;
+        self.open_header_value()?;
+        write!(self.buf, "{}", value)
+    }
 
-            let open_brace = self.subtle_style("[");
-            write!(self.buf, "{}{}", open_brace, value)
-        } else {
-            write!(self.buf, " {}", value)
-        }
+    fn write_header_str(&mut self, value: &str) -> io::Result<()> {
+        self.open_header_value()?;
+        self.buf.write_all(value.as_bytes())
     }
 
     fn write_level(&mut self, record: &Record) -> io::Result<()> {
-        if !self.level {
-            return Ok(());
+        let level = record.level();
+        if self.buf.is_colored() {
+            let padded = format_args!("{:<5}", level);
+            return self.write_header_value(self.buf.styled(Style::level(level), padded));
         }
 
-        let level = {
-            {
-                record.level()
-            }
-        };
-
-        self.write_header_value(format_args!("{:<5}", level))
+        let level = level.as_str();
+        self.write_header_str(level)?;
+        self.buf.write_all(&b"     "[level.len().min(5)..])
     }
 
+    #[cfg(feature = "humantime")]
     fn write_timestamp(&mut self) -> io::Result<()> {
-        
-        if self.timestamp.is_none() {
-            return Ok(())
-        }
         self.write_header_value(self.buf.timestamp_nanos())
+    }
 
+    #[cfg(not(feature = "humantime"))]
+    fn write_timestamp(&mut self) -> io::Result<()> {
+        let nanos = self.buf.timestamp_nanos();
+        self.open_header_value()?;
+        write_u64(self.buf, nanos)
     }
 
-    fn write_module_path(&mut self, record: &Record) -> io::Result<()> {
-        if !self.module_path {
-            return Ok(());
+    fn write_module_path(&mut self, record: &Record, target_fallback: bool) -> io::Result<()> {
+        match (record.module_path(), record.target()) {
+            (Some(module_path), _) => self.write_header_str(module_path),
+            (None, "") => Ok(()),
+            (None, target) if target_fallback => self.write_header_str(target),
+            (None, _) => Ok(()),
         }
+    }
 
-        if let Some(module_path) = record.module_path() {
-            self.write_header_value(module_path)
-        } else {
-            Ok(())
+    fn write_target(
+        &mut self,
+        record: &Record,
+        width: Option<usize>,
+        module_path_fallback: bool,
+    ) -> io::Result<()> {
+        match (record.target(), width) {
+            ("", _) if module_path_fallback => match record.module_path() {
+                Some(module_path) => self.write_header_str(module_path),
+                None => Ok(()),
+            },
+            ("", _) => Ok(()),
+            (target, None) => self.write_header_str(target),
+            (target, Some(width)) => self.write_header_value(TargetColumn { target, width }),
         }
     }
 
-    fn write_target(&mut self, record: &Record) -> io::Result<()> {
-        if !self.target {
-            return Ok(());
-        }
+    #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+    fn write_method_name(&mut self) -> io::Result<()> {
+        let method_name = crate::context::method_name().unwrap_or("-");
+        self.write_header_str("method=")?;
+        self.buf.write_all(method_name.as_bytes())
+    }
 
-        match record.target() {
-            "" => Ok(()),
-            target => self.write_header_value(target),
-        }
+    #[cfg(not(all(target_arch = "wasm32", feature = "ic")))]
+    fn write_method_name(&mut self) -> io::Result<()> {
+        Ok(())
     }
 
     fn finish_header(&mut self) -> io::Result<()> {
-        if self.written_header_value {
+        if !self.written_header_value {
+            Ok(())
+        } else if self.buf.is_colored() {
             let close_brace = self.subtle_style("]");
             write!(self.buf, "{} ", close_brace)
         } else {
-            Ok(())
+            self.buf.write_all(b"] ")
         }
     }
 
     fn write_args(&mut self, record: &Record) -> io::Result<()> {
-        match self.indent {
-            // Fast path for no indentation
-            None => write!(self.buf, "{}{}", record.args(), self.suffix),
+        match self.plan.indent {
+            // Fast path for no indentation, and for messages without arguments
+            None => {
+                match record.args().as_str() {
+                    Some(message) => self.buf.write_all(message.as_bytes())?,
+                    None => write!(self.buf, "{}", record.args())?,
+                }
+                self.buf.write_all(self.plan.suffix.as_bytes())
+            }
 
             Some(indent_count) => {
                 // Create a wrapper around the buffer only if we have to actually indent the message
@@ -303,23 +761,21 @@ impl<'a> DefaultFormat<'a> {
                 struct IndentWrapper<'a, 'b: 'a> {
                     fmt: &'a mut DefaultFormat<'b>,
                     indent_count: usize,
+                    // The indentation is only written once the next line has some content,
+                    // so that a trailing newline doesn't produce an empty indented line
+                    pending_indent: bool,
                 }
 
                 impl<'a, 'b> Write for IndentWrapper<'a, 'b> {
                     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-                        let mut first = true;
-                        for chunk in buf.split(|&x| x == b'\n') {
-                            if !first {
-                                write!(
-                                    self.fmt.buf,
-                                    "{}{:width$}",
-                                    self.fmt.suffix,
-                                    "",
-                                    width = self.indent_count
-                                )?;
+                        // Splitting on `\n` keeps a preceding `\r` attached to its line
+                        for line in buf.split_inclusive(|&x| x == b'\n') {
+                            if self.pending_indent {
+                                write!(self.fmt.buf, "{:width$}", "", width = self.indent_count)?;
+                                self.pending_indent = false;
                             }
-                            self.fmt.buf.write_all(chunk)?;
-                            first = false;
+                            self.fmt.buf.write_all(line)?;
+                            self.pending_indent = line.ends_with(b"\n");
                         }
 
                         Ok(buf.len())
@@ -335,34 +791,52 @@ impl<'a> DefaultFormat<'a> {
                     let mut wrapper = IndentWrapper {
                         fmt: self,
                         indent_count,
+                        pending_indent: false,
                     };
-                    write!(wrapper, "{}", record.args())?;
+                    match record.args().as_str() {
+                        Some(message) => wrapper.write_all(message.as_bytes())?,
+                        None => write!(wrapper, "{}", record.args())?,
+                    }
                 }
 
-                write!(self.buf, "{}", self.suffix)?;
-
-                Ok(())
+                self.buf.write_all(self.plan.suffix.as_bytes())
             }
         }
     }
 }
 
+/// Writes the decimal digits of `value` without the formatting machinery.
+#[cfg(not(feature = "humantime"))]
+fn write_u64(buf: &mut Formatter, mut value: u64) -> io::Result<()> {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    buf.write_all(&digits[start..])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use log::{Level, Record};
 
-    fn write_record(record: Record, fmt: DefaultFormat) -> String {
-        let buf = fmt.buf.buf.clone();
+    fn write_record(record: Record, fmt: &ConfigurableFormat, f: &mut Formatter) -> String {
+        let buf = f.buf.clone();
 
-        fmt.write(&record).expect("failed to write record");
+        fmt.write(f, &record).expect("failed to write record");
 
         let buf = buf.borrow();
         String::from_utf8(buf.bytes().to_vec()).expect("failed to read record")
     }
 
-    fn write_target(target: &str, fmt: DefaultFormat) -> String {
+    fn write_target(target: &str, fmt: &ConfigurableFormat, f: &mut Formatter) -> String {
         write_record(
             Record::builder()
                 .args(format_args!("log\nmessage"))
@@ -373,179 +847,362 @@ mod tests {
                 .target(target)
                 .build(),
             fmt,
+            f,
+        )
+    }
+
+    fn write(fmt: &ConfigurableFormat, f: &mut Formatter) -> String {
+        write_target("", fmt, f)
+    }
+
+    fn write_message(message: &str, fmt: &ConfigurableFormat, f: &mut Formatter) -> String {
+        write_record(
+            Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Info)
+                .build(),
+            fmt,
+            f,
         )
     }
 
-    fn write(fmt: DefaultFormat) -> String {
-        write_target("", fmt)
+    fn indent_format(suffix: &'static str) -> ConfigurableFormat {
+        ConfigurableFormat {
+            timestamp: None,
+            module_path: false,
+            target: false,
+            target_width: None,
+            origin_fallback: true,
+            level: false,
+            method_name: false,
+            indent: Some(4),
+            suffix,
+        }
+    }
+
+    // Written by the default format before it was planned
+    const GOLDEN: &str = "\
+[WARN ] 0
+  message
+[WARN ] 0
+  message
+[WARN ] 0
+  message
+1
+  message
+[mod::path] 1
+  message
+1
+  message
+[WARN  tgt] 2
+  message
+[WARN ] 2
+  message
+[WARN ] 2
+  message
+[tgt] 3
+  message
+[mod::path] 3
+  message
+3
+  message
+[WARN ] 4
+  message
+[WARN ] 4
+  message
+[WARN ] 4
+  message
+5
+  message
+[mod::path] 5
+  message
+5
+  message
+[WARN  tgt   ] 6
+  message
+[WARN ] 6
+  message
+[WARN ] 6
+  message
+[tgt   ] 7
+  message
+[mod::path] 7
+  message
+7
+  message
+[WARN ] 8
+  message
+[WARN ] 8
+  message
+[WARN ] 8
+  message
+[tgt] 9
+  message
+[mod::path] 9
+  message
+9
+  message
+[WARN  tgt] 10
+  message
+[WARN  mod::path] 10
+  message
+[WARN ] 10
+  message
+[tgt] 11
+  message
+[mod::path] 11
+  message
+11
+  message
+[WARN ] 12
+  message
+[WARN ] 12
+  message
+[WARN ] 12
+  message
+[tgt] 13
+  message
+[mod::path] 13
+  message
+13
+  message
+[WARN  tgt   ] 14
+  message
+[WARN  mod::path] 14
+  message
+[WARN ] 14
+  message
+[tgt   ] 15
+  message
+[mod::path] 15
+  message
+15
+  message
+";
+
+    #[test]
+    fn plan_matches_the_golden_output() {
+        let writer = writer::Builder::new().build();
+        let mut f = Formatter::new(&writer);
+        let mut written = String::new();
+        for options in 0..16 {
+            let fmt = ConfigurableFormat {
+                timestamp: None,
+                module_path: options & 1 != 0,
+                target: options & 2 != 0,
+                target_width: Some(6).filter(|_| options & 4 != 0),
+                origin_fallback: options & 8 != 0,
+                level: options & 1 == 0,
+                method_name: true,
+                indent: Some(2),
+                suffix: "\n",
+            };
+            for (module_path, target) in [(None, "tgt"), (Some("mod::path"), ""), (None, "")] {
+                written += &write_record(
+                    Record::builder()
+                        .args(format_args!("{}\nmessage", options))
+                        .level(Level::Warn)
+                        .module_path(module_path)
+                        .target(target)
+                        .build(),
+                    &fmt,
+                    &mut f,
+                );
+                f.clear();
+            }
+        }
+
+        assert_eq!(GOLDEN, written);
     }
 
     #[test]
     fn format_with_header() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: true,
-            target: false,
-            level: true,
-            written_header_value: false,
-            indent: None,
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: true,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("[INFO  test::path] log\nmessage\n", written);
     }
 
     #[test]
     fn format_no_header() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: false,
-            target: false,
-            level: false,
-            written_header_value: false,
-            indent: None,
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("log\nmessage\n", written);
     }
 
     #[test]
     fn format_indent_spaces() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: true,
-            target: false,
-            level: true,
-            written_header_value: false,
-            indent: Some(4),
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: true,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: Some(4),
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("[INFO  test::path] log\n    message\n", written);
     }
 
     #[test]
     fn format_indent_zero_spaces() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: true,
-            target: false,
-            level: true,
-            written_header_value: false,
-            indent: Some(0),
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: true,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: Some(0),
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("[INFO  test::path] log\nmessage\n", written);
     }
 
     #[test]
     fn format_indent_spaces_no_header() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: false,
-            target: false,
-            level: false,
-            written_header_value: false,
-            indent: Some(4),
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: Some(4),
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("log\n    message\n", written);
     }
 
     #[test]
     fn format_suffix() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: false,
-            target: false,
-            level: false,
-            written_header_value: false,
-            indent: None,
-            suffix: "\n\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: None,
+                suffix: "\n\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("log\nmessage\n\n", written);
     }
 
     #[test]
     fn format_suffix_with_indent() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: false,
-            target: false,
-            level: false,
-            written_header_value: false,
-            indent: Some(4),
-            suffix: "\n\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: false,
+                target_width: None,
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: Some(4),
+                suffix: "\n\n",
+            },
+            &mut f,
+        );
 
-        assert_eq!("log\n\n    message\n\n", written);
+        assert_eq!("log\n    message\n\n", written);
     }
 
     #[test]
     fn format_target() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
         let written = write_target(
             "target",
-            DefaultFormat {
+            &ConfigurableFormat {
                 timestamp: None,
                 module_path: true,
                 target: true,
+                target_width: None,
+                origin_fallback: true,
                 level: true,
-                written_header_value: false,
+                method_name: false,
                 indent: None,
                 suffix: "\n",
-                buf: &mut f,
             },
+            &mut f,
         );
 
         assert_eq!("[INFO  test::path target] log\nmessage\n", written);
@@ -553,46 +1210,400 @@ mod tests {
 
     #[test]
     fn format_empty_target() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
-        let written = write(DefaultFormat {
-            timestamp: None,
-            module_path: true,
-            target: true,
-            level: true,
-            written_header_value: false,
-            indent: None,
-            suffix: "\n",
-            buf: &mut f,
-        });
+        let written = write(
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: true,
+                target: true,
+                target_width: None,
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
 
         assert_eq!("[INFO  test::path] log\nmessage\n", written);
     }
 
     #[test]
     fn format_no_target() {
-        let writer = writer::Builder::new()
-            .build();
+        let writer = writer::Builder::new().build();
 
         let mut f = Formatter::new(&writer);
 
         let written = write_target(
             "target",
-            DefaultFormat {
+            &ConfigurableFormat {
                 timestamp: None,
                 module_path: true,
                 target: false,
+                target_width: None,
+                origin_fallback: true,
                 level: true,
-                written_header_value: false,
+                method_name: false,
                 indent: None,
                 suffix: "\n",
-                buf: &mut f,
             },
+            &mut f,
         );
 
         assert_eq!("[INFO  test::path] log\nmessage\n", written);
     }
+
+    #[test]
+    fn configurable_format_reused_by_custom_format() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let mut fmt = ConfigurableFormat::default();
+        fmt.timestamp(None).indent(None).suffix("");
+
+        let custom = move |buf: &mut Formatter, record: &Record| {
+            fmt.write(buf, record)?;
+            writeln!(buf, " (extra)")
+        };
+
+        let buf = f.buf.clone();
+        custom(
+            &mut f,
+            &Record::builder()
+                .args(format_args!("message"))
+                .level(Level::Warn)
+                .target("target")
+                .build(),
+        )
+        .expect("failed to write record");
+
+        let written = String::from_utf8(buf.borrow().bytes().to_vec()).unwrap();
+        assert_eq!("[WARN  target] message (extra)\n", written);
+    }
+
+    #[test]
+    fn format_target_width_pads_short_target() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_target(
+            "target",
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: true,
+                target_width: Some(10),
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
+
+        assert_eq!("[INFO  target    ] log\nmessage\n", written);
+    }
+
+    #[test]
+    fn format_target_width_truncates_long_target() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_target(
+            "long::target::path",
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: true,
+                target_width: Some(8),
+                origin_fallback: true,
+                level: true,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
+
+        assert_eq!("[INFO  long::t…] log\nmessage\n", written);
+    }
+
+    #[test]
+    fn format_target_width_respects_char_boundaries() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_target(
+            "åäöåäö",
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: true,
+                target_width: Some(4),
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
+
+        assert_eq!("[åäö…] log\nmessage\n", written);
+    }
+
+    #[test]
+    fn format_target_width_exact_fit() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_target(
+            "target",
+            &ConfigurableFormat {
+                timestamp: None,
+                module_path: false,
+                target: true,
+                target_width: Some(6),
+                origin_fallback: true,
+                level: false,
+                method_name: false,
+                indent: None,
+                suffix: "\n",
+            },
+            &mut f,
+        );
+
+        assert_eq!("[target] log\nmessage\n", written);
+    }
+
+    fn redact_digits(text: &str) -> Cow<'_, str> {
+        regex::Regex::new(r"\d").unwrap().replace_all(text, "#")
+    }
+
+    #[test]
+    fn redact_replaces_rendered_record() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+        writeln!(f, "secret: 1234, target: 42").unwrap();
+
+        f.redact(redact_digits);
+
+        let written = String::from_utf8(f.buf.borrow().bytes().to_vec()).unwrap();
+        assert_eq!("secret: ####, target: ##\n", written);
+    }
+
+    #[test]
+    fn redact_without_matches_keeps_record() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+        writeln!(f, "no secrets here").unwrap();
+
+        f.redact(redact_digits);
+
+        let written = String::from_utf8(f.buf.borrow().bytes().to_vec()).unwrap();
+        assert_eq!("no secrets here\n", written);
+    }
+
+    #[test]
+    fn redact_borrowed_keeps_record() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+        writeln!(f, "secret: 1234").unwrap();
+
+        f.redact(|text| Cow::Borrowed(&text[..6]));
+
+        let written = String::from_utf8(f.buf.borrow().bytes().to_vec()).unwrap();
+        assert_eq!("secret: 1234\n", written);
+    }
+
+    #[test]
+    fn redact_panic_falls_back_to_fixed_message() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+        writeln!(f, "secret: 1234").unwrap();
+
+        f.redact(|_| panic!("redaction panicked"));
+
+        let written = String::from_utf8(f.buf.borrow().bytes().to_vec()).unwrap();
+        assert_eq!("[REDACTION FAILED]\n", written);
+    }
+
+    #[test]
+    fn format_indent_single_line() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("message", &indent_format("\n"), &mut f);
+
+        assert_eq!("message\n", written);
+    }
+
+    #[test]
+    fn format_indent_multiple_lines_with_suffix() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("first\nsecond\nthird", &indent_format(" <end>\n"), &mut f);
+
+        assert_eq!("first\n    second\n    third <end>\n", written);
+    }
+
+    #[test]
+    fn format_indent_crlf() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("first\r\nsecond\r\nthird", &indent_format("\r\n"), &mut f);
+
+        assert_eq!("first\r\n    second\r\n    third\r\n", written);
+    }
+
+    #[test]
+    fn format_indent_trailing_newline() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("first\nsecond\n", &indent_format("\n"), &mut f);
+
+        assert_eq!("first\n    second\n\n", written);
+    }
+
+    #[test]
+    fn format_indent_empty_lines() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("first\n\nthird", &indent_format("\n"), &mut f);
+
+        assert_eq!("first\n    \n    third\n", written);
+    }
+
+    fn write_origin(
+        module_path: Option<&str>,
+        target: &str,
+        fmt: &ConfigurableFormat,
+        f: &mut Formatter,
+    ) -> String {
+        write_record(
+            Record::builder()
+                .args(format_args!("message"))
+                .level(Level::Info)
+                .module_path(module_path)
+                .target(target)
+                .build(),
+            fmt,
+            f,
+        )
+    }
+
+    fn origin_format(module_path: bool, target: bool, origin_fallback: bool) -> ConfigurableFormat {
+        ConfigurableFormat {
+            timestamp: None,
+            module_path,
+            target,
+            target_width: None,
+            origin_fallback,
+            level: true,
+            method_name: false,
+            indent: None,
+            suffix: "\n",
+        }
+    }
+
+    #[test]
+    fn format_missing_module_path_falls_back_to_target() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_origin(None, "target", &origin_format(true, false, true), &mut f);
+
+        assert_eq!("[INFO  target] message\n", written);
+    }
+
+    #[test]
+    fn format_missing_target_falls_back_to_module_path() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_origin(
+            Some("test::path"),
+            "",
+            &origin_format(false, true, true),
+            &mut f,
+        );
+
+        assert_eq!("[INFO  test::path] message\n", written);
+    }
+
+    #[test]
+    fn format_origin_fallback_disabled() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_origin(None, "target", &origin_format(true, false, false), &mut f);
+
+        assert_eq!("[INFO ] message\n", written);
+    }
+
+    #[test]
+    fn format_origin_fallback_not_duplicated() {
+        let writer = writer::Builder::new().build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_origin(None, "target", &origin_format(true, true, true), &mut f);
+
+        assert_eq!("[INFO  target] message\n", written);
+    }
+
+    #[test]
+    fn format_never_writes_styles() {
+        let writer = writer::Builder::new()
+            .write_style(writer::WriteStyle::Never)
+            .build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("message", &origin_format(false, false, true), &mut f);
+
+        assert_eq!("[INFO ] message\n", written);
+    }
+
+    #[test]
+    fn format_always_writes_styles() {
+        let writer = writer::Builder::new()
+            .write_style(writer::WriteStyle::Always)
+            .build();
+
+        let mut f = Formatter::new(&writer);
+
+        let written = write_message("message", &origin_format(false, false, true), &mut f);
+
+        assert_eq!(
+            "\x1b[90m[\x1b[0m\x1b[32mINFO \x1b[0m\x1b[90m]\x1b[0m message\n",
+            written
+        );
+    }
 }
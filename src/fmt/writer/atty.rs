@@ -0,0 +1,36 @@
+/*
+This internal module contains the terminal detection implementation.
+
+If the `auto-color` feature is enabled then we detect whether we're attached to a particular TTY.
+Otherwise, assume we're not attached to anything. This effectively prevents styles from being
+printed. On wasm32 there's never a terminal to print styles to.
+*/
+
+#[cfg(all(feature = "auto-color", not(target_arch = "wasm32")))]
+mod imp {
+    use std::io::{self, IsTerminal};
+
+    // `IsTerminal` requires Rust 1.70, which the `auto-color` feature documents
+    #[allow(clippy::incompatible_msrv)]
+    pub(in crate::fmt) fn is_stdout() -> bool {
+        io::stdout().is_terminal()
+    }
+
+    #[allow(clippy::incompatible_msrv)]
+    pub(in crate::fmt) fn is_stderr() -> bool {
+        io::stderr().is_terminal()
+    }
+}
+
+#[cfg(not(all(feature = "auto-color", not(target_arch = "wasm32"))))]
+mod imp {
+    pub(in crate::fmt) fn is_stdout() -> bool {
+        false
+    }
+
+    pub(in crate::fmt) fn is_stderr() -> bool {
+        false
+    }
+}
+
+pub(in crate::fmt) use self::imp::*;
@@ -0,0 +1,194 @@
+//! Batching of the records written to a target.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// What to do with a record that doesn't fit in a full batch.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OverflowPolicy {
+    /// Print the batch to make room, blocking the logging call on the write.
+    Block,
+    /// Drop the new record, keeping the batched ones.
+    DropNewest,
+    /// Drop the oldest batched records to make room for the new one.
+    DropOldest,
+}
+
+/// The default policy drops the new records, so a full batch never grows the
+/// canister memory nor costs a print in the middle of a call.
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::DropNewest
+    }
+}
+
+/// The records waiting to be written to a target with a single print.
+pub(super) struct Batch {
+    max_bytes: usize,
+    policy: OverflowPolicy,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    // the length of every batched record, oldest first
+    records: VecDeque<usize>,
+    // records dropped since the last print
+    dropped: u64,
+}
+
+impl Batch {
+    pub fn new(max_bytes: usize, policy: OverflowPolicy) -> Batch {
+        Batch {
+            max_bytes,
+            policy,
+            pending: Mutex::new(Pending::default()),
+        }
+    }
+
+    /// Adds a record to the batch, applying the overflow policy if it doesn't
+    /// fit. Records are never split across prints.
+    ///
+    /// `dropped` counts the dropped records, and `print` writes to the target.
+    /// Returns `false` if the record was dropped.
+    pub fn push<F>(&self, record: &[u8], dropped: &AtomicU64, mut print: F) -> io::Result<bool>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if record.len() > self.max_bytes {
+            // the record can't fit in the batch, even empty
+            if self.policy == OverflowPolicy::Block {
+                Self::print(&mut pending, &mut print)?;
+                return print(record).map(|_| true);
+            }
+            Self::drop_records(&mut pending, 1, dropped);
+            return Ok(false);
+        }
+
+        if pending.bytes.len() + record.len() > self.max_bytes {
+            match self.policy {
+                OverflowPolicy::Block => Self::print(&mut pending, &mut print)?,
+                OverflowPolicy::DropNewest => {
+                    Self::drop_records(&mut pending, 1, dropped);
+                    return Ok(false);
+                }
+                OverflowPolicy::DropOldest => {
+                    let mut count = 0;
+                    while pending.bytes.len() + record.len() > self.max_bytes {
+                        let len = pending.records.pop_front().unwrap_or(pending.bytes.len());
+                        pending.bytes.drain(..len);
+                        count += 1;
+                    }
+                    Self::drop_records(&mut pending, count, dropped);
+                }
+            }
+        }
+
+        pending.bytes.extend_from_slice(record);
+        pending.records.push_back(record.len());
+        if self.policy == OverflowPolicy::Block && pending.bytes.len() >= self.max_bytes {
+            Self::print(&mut pending, &mut print)?;
+        }
+        Ok(true)
+    }
+
+    /// Prints the batched records, followed by a summary of the dropped ones.
+    pub fn flush<F>(&self, mut print: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        Self::print(&mut pending, &mut print)
+    }
+
+    fn print<F>(pending: &mut Pending, print: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let result = if pending.bytes.is_empty() {
+            Ok(())
+        } else {
+            print(&pending.bytes)
+        };
+        pending.bytes.clear();
+        pending.records.clear();
+
+        if pending.dropped > 0 {
+            let summary = format!(
+                "log batch overflowed, {} records dropped\n",
+                pending.dropped
+            );
+            pending.dropped = 0;
+            result.and(print(summary.as_bytes()))
+        } else {
+            result
+        }
+    }
+
+    fn drop_records(pending: &mut Pending, count: u64, dropped: &AtomicU64) {
+        pending.dropped += count;
+        dropped.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(policy: OverflowPolicy, records: &[&str]) -> (Vec<String>, u64) {
+        let batch = Batch::new(16, policy);
+        let dropped = AtomicU64::new(0);
+        let mut prints = Vec::new();
+        let mut print = |bytes: &[u8]| {
+            prints.push(String::from_utf8(bytes.to_vec()).unwrap());
+            Ok(())
+        };
+
+        for record in records {
+            batch.push(record.as_bytes(), &dropped, &mut print).unwrap();
+        }
+        batch.flush(&mut print).unwrap();
+        (prints, dropped.load(Ordering::Relaxed))
+    }
+
+    const RECORDS: [&str; 5] = ["one\n", "two\n", "three\n", "four\n", "a record too long\n"];
+
+    #[test]
+    fn block_prints_to_make_room() {
+        let (prints, dropped) = run(OverflowPolicy::Block, &RECORDS);
+        assert_eq!(
+            vec!["one\ntwo\nthree\n", "four\n", "a record too long\n"],
+            prints
+        );
+        assert_eq!(0, dropped);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_batch() {
+        let (prints, dropped) = run(OverflowPolicy::DropNewest, &RECORDS);
+        let summary = "log batch overflowed, 2 records dropped\n";
+        assert_eq!(vec!["one\ntwo\nthree\n", summary], prints);
+        assert_eq!(2, dropped);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_last_records() {
+        let (prints, dropped) = run(OverflowPolicy::DropOldest, &RECORDS);
+        let summary = "log batch overflowed, 2 records dropped\n";
+        assert_eq!(vec!["two\nthree\nfour\n", summary], prints);
+        assert_eq!(2, dropped);
+    }
+
+    #[test]
+    fn no_summary_without_drops() {
+        let (prints, _) = run(OverflowPolicy::DropNewest, &RECORDS[..2]);
+        assert_eq!(vec!["one\ntwo\n"], prints);
+        let (prints, _) = run(OverflowPolicy::DropNewest, &[]);
+        assert!(prints.is_empty());
+    }
+}
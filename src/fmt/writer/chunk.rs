@@ -0,0 +1,103 @@
+//! Splitting of oversized records.
+//!
+//! The replica truncates long debug prints, so on wasm32 a record longer than
+//! the chunk size is printed as several parts, each one prefixed with a
+//! `(part i/n) ` marker.
+
+/// The default maximum size of a single print.
+pub(crate) const DEFAULT_MAX_CHUNK_BYTES: usize = 8 * 1024;
+
+/// Splits `bytes` into chunks of at most `max_bytes`.
+///
+/// Chunks end after a newline when there is one, and never in the middle of
+/// a UTF-8 character.
+pub(super) fn split(bytes: &[u8], max_bytes: usize) -> Vec<&[u8]> {
+    let max_bytes = max_bytes.max(1);
+    let mut chunks = Vec::new();
+    let mut rest = bytes;
+
+    while rest.len() > max_bytes {
+        let at = match rest[..max_bytes].iter().rposition(|b| *b == b'\n') {
+            Some(newline) => newline + 1,
+            None => {
+                let mut at = max_bytes;
+                while at > 0 && is_continuation(rest[at]) {
+                    at -= 1;
+                }
+                if at == 0 {
+                    max_bytes
+                } else {
+                    at
+                }
+            }
+        };
+        let (chunk, tail) = rest.split_at(at);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks.push(rest);
+    chunks
+}
+
+/// Prefixes the chunk `index` of `count` with its marker, counting from 1.
+pub(super) fn with_marker(chunk: &[u8], index: usize, count: usize) -> Vec<u8> {
+    let mut marked = format!("(part {}/{}) ", index, count).into_bytes();
+    marked.extend_from_slice(chunk);
+    marked
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reassemble(chunks: &[&[u8]]) -> Vec<u8> {
+        let count = chunks.len();
+        chunks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, chunk)| {
+                let marked = with_marker(chunk, i + 1, count);
+                let marker = format!("(part {}/{}) ", i + 1, count);
+                assert!(marked.starts_with(marker.as_bytes()));
+                marked[marker.len()..].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_records_are_not_split() {
+        assert_eq!(vec![&b"short"[..]], split(b"short", 5));
+        assert_eq!(vec![&b""[..]], split(b"", 5));
+    }
+
+    #[test]
+    fn chunks_reassemble_to_the_record() {
+        let record = "line one\nline two is longer\néàü ünïcödé without newlines\n".repeat(20);
+
+        for max_bytes in [1, 2, 3, 7, 16, 64, 1000] {
+            let chunks = split(record.as_bytes(), max_bytes);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= max_bytes.max(1)));
+            assert_eq!(record.as_bytes(), &reassemble(&chunks)[..], "{}", max_bytes);
+
+            if max_bytes > 1 {
+                for chunk in chunks {
+                    assert!(std::str::from_utf8(chunk).is_ok(), "{}", max_bytes);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_end_at_newlines() {
+        let chunks = split(b"first line\nsecond line\nthird", 16);
+        assert_eq!(
+            vec![&b"first line\n"[..], b"second line\n", b"third"],
+            chunks
+        );
+    }
+}
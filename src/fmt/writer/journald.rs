@@ -0,0 +1,109 @@
+//! A target writing to journald with its native protocol.
+//!
+//! See <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>.
+
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use log::{Level, Metadata};
+
+/// The socket journald listens to.
+pub(super) const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// A connection to journald.
+///
+/// If journald can't be reached the records are written to `stderr` instead.
+pub struct Journald {
+    socket: Option<UnixDatagram>,
+}
+
+impl Journald {
+    /// Connects to the journald socket at `path`, printing a warning to
+    /// `stderr` if it fails.
+    pub(super) fn connect(path: &Path) -> Journald {
+        let socket = UnixDatagram::unbound().and_then(|socket| {
+            socket.connect(path)?;
+            Ok(socket)
+        });
+
+        match socket {
+            Ok(socket) => Journald {
+                socket: Some(socket),
+            },
+            Err(e) => {
+                let _ = writeln!(
+                    io::stderr(),
+                    "ic_log: failed to connect to journald at {}, logging to stderr: {}",
+                    path.display(),
+                    e
+                );
+                Journald { socket: None }
+            }
+        }
+    }
+
+    /// Sends a record, with the priority of its level and its target as the
+    /// identifier.
+    pub(super) fn send(&self, bytes: &[u8], metadata: Option<&Metadata>) -> io::Result<()> {
+        match &self.socket {
+            Some(socket) => socket.send(&encode(bytes, metadata)).map(|_| ()),
+            None => io::stderr().lock().write_all(bytes),
+        }
+    }
+}
+
+/// Maps a level to a syslog priority.
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Encodes a record as journal fields.
+///
+/// The message is always length-prefixed, so it can span multiple lines.
+fn encode(bytes: &[u8], metadata: Option<&Metadata>) -> Vec<u8> {
+    let message = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let level = metadata.map_or(Level::Info, |metadata| metadata.level());
+
+    let mut fields = format!("PRIORITY={}\n", priority(level)).into_bytes();
+    if let Some(metadata) = metadata.filter(|metadata| !metadata.target().contains('\n')) {
+        fields.extend_from_slice(b"SYSLOG_IDENTIFIER=");
+        fields.extend_from_slice(metadata.target().as_bytes());
+        fields.push(b'\n');
+    }
+    fields.extend_from_slice(b"MESSAGE\n");
+    fields.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    fields.extend_from_slice(message);
+    fields.push(b'\n');
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_encoded_as_fields() {
+        let metadata = Metadata::builder()
+            .level(Level::Warn)
+            .target("indexer")
+            .build();
+        let mut expected = b"PRIORITY=4\nSYSLOG_IDENTIFIER=indexer\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nmulti\n");
+
+        assert_eq!(expected, encode(b"a\nmulti\n", Some(&metadata)));
+    }
+
+    #[test]
+    fn records_without_metadata_are_informational() {
+        let encoded = encode(b"summary\n", None);
+
+        assert!(encoded.starts_with(b"PRIORITY=6\nMESSAGE\n"));
+    }
+}
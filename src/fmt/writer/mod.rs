@@ -1,48 +1,739 @@
+mod atty;
+mod batch;
+mod chunk;
+#[cfg(all(feature = "journald", unix))]
+mod journald;
+#[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+mod net;
 mod termcolor;
+#[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+mod worker;
 
+use self::atty::{is_stderr, is_stdout};
 use self::termcolor::BufferWriter;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
 use std::{fmt, io};
 
-pub(super) use self::termcolor::Buffer;
+use log::{Level, Metadata};
+
+use crate::info::BufferInfo;
+use crate::platform::SharedPlatform;
+use crate::stats::{TargetCounters, TargetStats};
+
+use self::batch::Batch;
+pub use self::batch::OverflowPolicy;
+pub(crate) use self::chunk::DEFAULT_MAX_CHUNK_BYTES;
+#[cfg(all(feature = "journald", unix))]
+pub use self::journald::Journald;
+#[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+pub use self::net::{Tcp, Udp};
+
+pub(super) use self::termcolor::{Buffer, Style, StyledValue};
+
+/// The default capacity kept by a formatting buffer between records.
+pub(crate) const DEFAULT_MAX_RETAINED_BYTES: usize = 8 * 1024;
+
+pub(crate) type CustomFn = Box<dyn Fn(&[u8]) + Send + Sync + 'static>;
+
+/// Log target, either `stdout`, `stderr`, a custom pipe, a custom function or
+/// nothing.
+///
+/// On wasm32 `stdout` and `stderr` both print with `ic_cdk::print`, since a
+/// canister has no standard streams. A pipe is written to on every target.
+pub enum Target {
+    /// Logs will be sent to standard output.
+    Stdout,
+    /// Logs will be sent to standard error.
+    Stderr,
+    /// Logs will be sent to a custom pipe.
+    ///
+    /// Each record is written with a single call to `write_all`. The pipe is
+    /// flushed when the logger is flushed.
+    Pipe(Box<dyn io::Write + Send + 'static>),
+    /// Logs will be passed to a function, see [`Target::custom`].
+    ///
+    /// [`Target::custom`]: #method.custom
+    Custom(CustomFn),
+    /// Logs will be formatted, then discarded.
+    ///
+    /// Useful to measure the cost of formatting, or to only collect the
+    /// logger statistics.
+    Void,
+    /// Logs will be sent to journald, see [`Target::journald`].
+    ///
+    /// [`Target::journald`]: #method.journald
+    #[cfg(all(feature = "journald", unix))]
+    Journald(Journald),
+    /// Logs will be sent over UDP, see [`Target::udp`].
+    ///
+    /// [`Target::udp`]: #method.udp
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    Udp(Udp),
+    /// Logs will be sent over TCP, see [`Target::tcp`].
+    ///
+    /// [`Target::tcp`]: #method.tcp
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    Tcp(Tcp),
+}
+
+impl Target {
+    /// Creates a target calling `f` with the bytes of every formatted record.
+    ///
+    /// The function receives one complete record per call. If it panics the
+    /// record is dropped and the panic is counted in [`LoggerStats::target_panics`],
+    /// rather than unwinding into the logging call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use std::sync::Mutex;
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let (sender, receiver) = mpsc::channel();
+    /// let sender = Mutex::new(sender);
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.target(Target::custom(move |bytes| {
+    ///     let _ = sender.lock().unwrap().send(bytes.to_vec());
+    /// }));
+    /// ```
+    ///
+    /// [`LoggerStats::target_panics`]: ../struct.LoggerStats.html#structfield.target_panics
+    pub fn custom<F>(f: F) -> Target
+    where
+        F: Fn(&[u8]) + Send + Sync + 'static,
+    {
+        Target::Custom(Box::new(f))
+    }
+
+    /// Creates a target sending every record to journald with its native
+    /// protocol.
+    ///
+    /// The level of a record is mapped to the syslog priority, and its target
+    /// is passed as the `SYSLOG_IDENTIFIER`. Records are never batched, each
+    /// one is sent in its own datagram.
+    ///
+    /// If the journald socket can't be connected to, a warning is printed and
+    /// the records are written to `stderr` instead.
+    ///
+    /// Requires the `journald` feature, on unix only.
+    #[cfg(all(feature = "journald", unix))]
+    pub fn journald() -> Target {
+        Target::journald_socket(self::journald::JOURNALD_SOCKET)
+    }
+
+    /// Creates a target sending every record to the journald socket at `path`.
+    ///
+    /// See [`Target::journald`] for more details.
+    ///
+    /// [`Target::journald`]: #method.journald
+    #[cfg(all(feature = "journald", unix))]
+    pub fn journald_socket<P: AsRef<std::path::Path>>(path: P) -> Target {
+        Target::Journald(Journald::connect(path.as_ref()))
+    }
+
+    /// Creates a target sending every record in its own UDP datagram to
+    /// `addr`.
+    ///
+    /// Sending waits at most 50 milliseconds, see [`Target::udp_with_timeout`].
+    ///
+    /// Requires the `net` feature, on native targets only.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the address can't be resolved or the local socket can't be
+    /// bound.
+    ///
+    /// [`Target::udp_with_timeout`]: #method.udp_with_timeout
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    pub fn udp<A: std::net::ToSocketAddrs>(addr: A) -> io::Result<Target> {
+        Target::udp_with_timeout(addr, self::net::DEFAULT_TIMEOUT)
+    }
+
+    /// Creates a target sending every record in its own UDP datagram to
+    /// `addr`, waiting at most `timeout` for each send.
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    pub fn udp_with_timeout<A: std::net::ToSocketAddrs>(
+        addr: A,
+        timeout: std::time::Duration,
+    ) -> io::Result<Target> {
+        Udp::connect(addr, timeout).map(Target::Udp)
+    }
+
+    /// Creates a target sending newline-delimited records over a TCP stream
+    /// to `addr`.
+    ///
+    /// The stream is connected on the first record, and reconnected at most
+    /// once per second after a failure. Meanwhile up to 64 KiB of records are
+    /// buffered, the following ones being dropped and counted in
+    /// [`LoggerStats::overflow_dropped`]. Connecting and sending wait at most
+    /// 50 milliseconds, see [`Target::tcp_with_timeout`].
+    ///
+    /// Requires the `net` feature, on native targets only.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the address can't be resolved.
+    ///
+    /// [`LoggerStats::overflow_dropped`]: ../struct.LoggerStats.html#structfield.overflow_dropped
+    /// [`Target::tcp_with_timeout`]: #method.tcp_with_timeout
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    pub fn tcp<A: std::net::ToSocketAddrs>(addr: A) -> io::Result<Target> {
+        Target::tcp_with_timeout(addr, self::net::DEFAULT_TIMEOUT)
+    }
+
+    /// Creates a target sending newline-delimited records over a TCP stream
+    /// to `addr`, waiting at most `timeout` to connect and send.
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    pub fn tcp_with_timeout<A: std::net::ToSocketAddrs>(
+        addr: A,
+        timeout: std::time::Duration,
+    ) -> io::Result<Target> {
+        Tcp::new(addr, timeout).map(Target::Tcp)
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::Stdout
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Stdout => "stdout",
+                Self::Stderr => "stderr",
+                Self::Pipe(_) => "pipe",
+                Self::Custom(_) => "custom",
+                Self::Void => "void",
+                #[cfg(all(feature = "journald", unix))]
+                Self::Journald(_) => "journald",
+                #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+                Self::Udp(_) => "udp",
+                #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+                Self::Tcp(_) => "tcp",
+            }
+        )
+    }
+}
+
+/// The target of a built writer, with the pipe behind a mutex.
+///
+/// It is shared by the writers built from clones of the same builder.
+pub(super) enum WritableTarget {
+    Stdout,
+    Stderr,
+    Pipe(Mutex<Box<dyn io::Write + Send + 'static>>),
+    Custom(CustomFn),
+    Void,
+    #[cfg(all(feature = "journald", unix))]
+    Journald(Journald),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    Udp(Udp),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    Tcp(Tcp),
+}
+
+impl From<Target> for WritableTarget {
+    fn from(target: Target) -> Self {
+        match target {
+            Target::Stdout => WritableTarget::Stdout,
+            Target::Stderr => WritableTarget::Stderr,
+            Target::Pipe(pipe) => WritableTarget::Pipe(Mutex::new(pipe)),
+            Target::Custom(f) => WritableTarget::Custom(f),
+            Target::Void => WritableTarget::Void,
+            #[cfg(all(feature = "journald", unix))]
+            Target::Journald(journald) => WritableTarget::Journald(journald),
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            Target::Udp(udp) => WritableTarget::Udp(udp),
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            Target::Tcp(tcp) => WritableTarget::Tcp(tcp),
+        }
+    }
+}
+
+impl WritableTarget {
+    fn kind(&self) -> &'static str {
+        match self {
+            WritableTarget::Stdout => "stdout",
+            WritableTarget::Stderr => "stderr",
+            WritableTarget::Pipe(_) => "pipe",
+            WritableTarget::Custom(_) => "custom",
+            WritableTarget::Void => "void",
+            #[cfg(all(feature = "journald", unix))]
+            WritableTarget::Journald(_) => "journald",
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Udp(_) => "udp",
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Tcp(_) => "tcp",
+        }
+    }
+
+    /// Whether records can be batched before being written to this target.
+    fn batchable(&self) -> bool {
+        match self {
+            WritableTarget::Stdout | WritableTarget::Stderr | WritableTarget::Pipe(_) => true,
+            WritableTarget::Custom(_) | WritableTarget::Void => false,
+            #[cfg(all(feature = "journald", unix))]
+            WritableTarget::Journald(_) => false,
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Udp(_) => false,
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Tcp(_) => true,
+        }
+    }
+}
+
+impl fmt::Debug for WritableTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.kind())
+    }
+}
+
+/// A range of levels routed to a target.
+pub(crate) type Levels = (Bound<Level>, Bound<Level>);
+
+/// The metadata of a printed record, with the format it was printed with,
+/// `None` being the format of the logger.
+pub(crate) type Route<'a> = (&'a Metadata<'a>, Option<usize>);
+
+/// Identifies a target added at runtime with [`LoggerConfig::add_target`].
+///
+/// [`LoggerConfig::add_target`]: ../struct.LoggerConfig.html#method.add_target
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TargetId(u64);
+
+/// A target added to the builder, with the levels routed to it and the index
+/// of its own format, if any.
+#[derive(Clone, Debug)]
+pub(super) struct RoutedTarget {
+    target: Arc<WritableTarget>,
+    levels: Levels,
+    format: Option<usize>,
+}
+
+/// A built target, with the levels routed to it.
+pub(super) struct Sink {
+    id: TargetId,
+    target: Arc<WritableTarget>,
+    levels: Levels,
+    format: Option<usize>,
+    batch: Option<Batch>,
+    counters: TargetCounters,
+}
+
+impl Sink {
+    fn new(
+        id: u64,
+        target: Arc<WritableTarget>,
+        levels: Levels,
+        format: Option<usize>,
+        batch: Option<(usize, OverflowPolicy)>,
+    ) -> Sink {
+        Sink {
+            id: TargetId(id),
+            // Custom targets always receive one record per call
+            batch: batch
+                .filter(|_| target.batchable())
+                .map(|(max_bytes, policy)| Batch::new(max_bytes, policy)),
+            target,
+            levels,
+            format,
+            counters: TargetCounters::default(),
+        }
+    }
+
+    fn accepts(&self, level: Level) -> bool {
+        self.levels.contains(&level)
+    }
+
+    /// Whether the records of `level` formatted with `format` are written to
+    /// this target, `None` being the format of the logger.
+    fn accepts_format(&self, level: Level, format: Option<usize>) -> bool {
+        self.format == format && self.accepts(level)
+    }
+
+    /// The kind of the target followed by its id, like `stdout#0`.
+    fn label(&self) -> String {
+        format!("{}#{}", self.target.kind(), self.id.0)
+    }
+}
+
+/// Whether or not to print styles to the target.
+///
+/// Styles are never printed on wasm32, whatever the value.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WriteStyle {
+    /// Print styles if all the targets are terminals.
+    Auto,
+    /// Always print styles, even to pipes and custom targets.
+    Always,
+    /// Never print styles.
+    Never,
+}
+
+impl Default for WriteStyle {
+    fn default() -> Self {
+        WriteStyle::Auto
+    }
+}
+
+impl WriteStyle {
+    /// Parses `auto`, `always` or `never`, falling back to `Auto` for any
+    /// other value.
+    pub(crate) fn parse(spec: &str) -> WriteStyle {
+        match spec {
+            "always" => WriteStyle::Always,
+            "never" => WriteStyle::Never,
+            _ => WriteStyle::Auto,
+        }
+    }
+}
 
 /// A terminal target with color awareness.
 pub(crate) struct Writer {
-    inner: BufferWriter,
+    inner: Arc<BufferWriter>,
+    #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+    worker: Option<self::worker::Worker>,
 }
 
 impl Writer {
+    /// Whether the records are formatted with styles.
+    pub(crate) fn is_colored(&self) -> bool {
+        self.inner.is_colored()
+    }
+
+    /// Returns the number of targets records are written to.
+    pub(crate) fn target_count(&self) -> usize {
+        self.inner.target_count()
+    }
+
+    /// Returns the number of panics caught in custom targets.
+    pub(crate) fn panics(&self) -> u64 {
+        self.inner.panics()
+    }
+
+    /// Returns the number of records dropped because a batch or the queue of
+    /// the background thread was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+        if let Some(worker) = &self.worker {
+            return self.inner.dropped() + worker.dropped();
+        }
+        self.inner.dropped()
+    }
+
+    /// Returns the number of records the background thread failed to write.
+    pub(crate) fn errors(&self) -> u64 {
+        #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+        if let Some(worker) = &self.worker {
+            return worker.errors();
+        }
+        0
+    }
+
+    /// Writes the batched records and flushes the pipes.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+        if let Some(worker) = &self.worker {
+            worker.wait_idle();
+        }
+        self.inner.flush()
+    }
+
+    /// Stops the background thread once the queued records are written, then
+    /// flushes. Records are written synchronously afterwards.
+    #[cfg(feature = "async-writer")]
+    pub(crate) fn shutdown(&self) -> io::Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(worker) = &self.worker {
+            worker.shutdown();
+        }
+        self.inner.flush()
+    }
+
+    /// Replaces the targets, returning the id of the new one.
+    pub(crate) fn set_target(&self, target: Target) -> io::Result<TargetId> {
+        self.inner.set_target(target)
+    }
+
+    /// Replaces the targets with ones receiving only the records of some levels.
+    pub(crate) fn set_routes(&self, targets: Vec<(Target, Levels)>) -> io::Result<Vec<TargetId>> {
+        self.inner.set_routes(targets)
+    }
+
+    /// Returns the statistics of every target, labelled by kind and id.
+    pub(crate) fn target_stats(&self) -> Vec<(String, TargetStats)> {
+        self.inner.target_stats()
+    }
+
+    /// Adds a target receiving all the records.
+    pub(crate) fn add_target(&self, target: Target) -> TargetId {
+        self.inner.add_target(target)
+    }
+
+    /// Removes a target, returning `false` if there was none with this id.
+    pub(crate) fn remove_target(&self, id: TargetId) -> io::Result<bool> {
+        self.inner.remove_target(id)
+    }
+
+    /// Returns the kinds of the targets, in the order records are written to
+    /// them.
+    pub(crate) fn target_kinds(&self) -> Vec<&'static str> {
+        self.inner.target_kinds()
+    }
+
+    /// Returns the buffering settings.
+    pub(crate) fn buffer_info(&self) -> BufferInfo {
+        self.inner.buffer_info()
+    }
+
+    /// Returns the ids of the targets, in the order records are written to them.
+    pub(crate) fn target_ids(&self) -> Vec<TargetId> {
+        self.inner.target_ids()
+    }
+
+    /// Checks if records of `level` are routed to any target.
+    pub(crate) fn accepts(&self, level: Level) -> bool {
+        self.inner.accepts(level)
+    }
+
+    /// Checks if records of `level` formatted with `format` are routed to any
+    /// target, `None` being the format of the logger.
+    pub(crate) fn accepts_format(&self, level: Level, format: Option<usize>) -> bool {
+        self.inner.accepts_format(level, format)
+    }
 
     pub(super) fn buffer(&self) -> Buffer {
         self.inner.buffer()
     }
 
-    pub(super) fn print(&self, buf: &Buffer) -> io::Result<()> {
-        self.inner.print(buf)
+    /// Writes the buffer to the targets using the format of `route`, or to all
+    /// of them if there is no route.
+    pub(super) fn print(&self, buf: &Buffer, route: Option<Route>) -> io::Result<()> {
+        #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+        if let Some(worker) = &self.worker {
+            if worker.send(buf.bytes(), route) {
+                return Ok(());
+            }
+        }
+        self.inner.print(buf.bytes(), route)
     }
 }
 
 /// A builder for a terminal writer.
 ///
-/// The target and style choice can be configured before building.
+/// The target and style choice can be configured before building. The
+/// targets are built when added, so they are shared by the clones of the
+/// builder.
 #[derive(Debug)]
 pub(crate) struct Builder {
+    targets: Vec<RoutedTarget>,
+    write_style: WriteStyle,
+    is_test: bool,
+    trim_trailing_newline: bool,
+    batch: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    max_chunk_bytes: usize,
+    buffer_capacity: usize,
+    max_retained_bytes: usize,
+    platform: Option<SharedPlatform>,
+    #[cfg(feature = "async-writer")]
+    non_blocking: Option<usize>,
     built: bool,
 }
 
+// A clone of a built builder can be built again.
+impl Clone for Builder {
+    fn clone(&self) -> Self {
+        Builder {
+            targets: self.targets.clone(),
+            platform: self.platform.clone(),
+            built: false,
+            ..*self
+        }
+    }
+}
+
 impl Builder {
     /// Initialize the writer builder with defaults.
     pub(crate) fn new() -> Self {
         Builder {
+            targets: Vec::new(),
+            write_style: Default::default(),
+            is_test: false,
+            trim_trailing_newline: cfg!(target_arch = "wasm32"),
+            batch: None,
+            overflow_policy: Default::default(),
+            max_chunk_bytes: DEFAULT_MAX_CHUNK_BYTES,
+            buffer_capacity: 0,
+            max_retained_bytes: DEFAULT_MAX_RETAINED_BYTES,
+            platform: None,
+            #[cfg(feature = "async-writer")]
+            non_blocking: None,
             built: false,
         }
     }
 
+    /// Set the target to write to, replacing the ones added before.
+    pub(crate) fn target(&mut self, target: Target) -> &mut Self {
+        self.targets.clear();
+        self.add_target(target)
+    }
+
+    /// Add a target to write to.
+    pub(crate) fn add_target(&mut self, target: Target) -> &mut Self {
+        self.route(.., target)
+    }
+
+    /// Add a target to write the records of some levels to.
+    pub(crate) fn route<R>(&mut self, levels: R, target: Target) -> &mut Self
+    where
+        R: RangeBounds<Level>,
+    {
+        let levels = (levels.start_bound().cloned(), levels.end_bound().cloned());
+        self.targets.push(RoutedTarget {
+            target: Arc::new(target.into()),
+            levels,
+            format: None,
+        });
+        self
+    }
+
+    /// Add a target receiving all the records, formatted with the format at
+    /// `format` in the formats of the logger.
+    pub(crate) fn add_target_with_format(&mut self, target: Target, format: usize) -> &mut Self {
+        self.targets.push(RoutedTarget {
+            target: Arc::new(target.into()),
+            levels: (Bound::Unbounded, Bound::Unbounded),
+            format: Some(format),
+        });
+        self
+    }
+
+    /// Whether or not to capture logs for `cargo test`.
+    pub(crate) fn is_test(&mut self, is_test: bool) -> &mut Self {
+        self.is_test = is_test;
+        self
+    }
+
+    /// Whether or not to strip the trailing newline of the records printed to
+    /// `stdout` and `stderr`.
+    pub(crate) fn trim_trailing_newline(&mut self, trim: bool) -> &mut Self {
+        self.trim_trailing_newline = trim;
+        self
+    }
+
+    /// Batch the records written to a target up to `max_bytes`.
+    pub(crate) fn batch(&mut self, max_bytes: usize) -> &mut Self {
+        self.batch = Some(max_bytes);
+        self
+    }
+
+    /// Set what to do with the records that don't fit in a full batch.
+    pub(crate) fn overflow_policy(&mut self, overflow_policy: OverflowPolicy) -> &mut Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Split the records printed with `ic_cdk::print` into chunks of up to
+    /// `max_chunk_bytes`.
+    pub(crate) fn max_chunk_bytes(&mut self, max_chunk_bytes: usize) -> &mut Self {
+        self.max_chunk_bytes = max_chunk_bytes;
+        self
+    }
+
+    /// Allocate `capacity` bytes for the formatting buffers upfront.
+    pub(crate) fn buffer_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    /// Shrink the formatting buffers to `max_retained_bytes` after printing a
+    /// larger record.
+    pub(crate) fn max_retained_bytes(&mut self, max_retained_bytes: usize) -> &mut Self {
+        self.max_retained_bytes = max_retained_bytes;
+        self
+    }
+
+    /// Print the records written to `stdout` and `stderr` with `platform`.
+    pub(crate) fn platform(&mut self, platform: SharedPlatform) -> &mut Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Write the records from a background thread, queuing up to `capacity`
+    /// records.
+    #[cfg(feature = "async-writer")]
+    pub(crate) fn non_blocking(&mut self, capacity: usize) -> &mut Self {
+        self.non_blocking = Some(capacity);
+        self
+    }
+
+    /// Whether or not to print styles to the target.
+    pub(crate) fn write_style(&mut self, write_style: WriteStyle) -> &mut Self {
+        self.write_style = write_style;
+        self
+    }
+
     /// Build a terminal writer.
     pub(crate) fn build(&mut self) -> Writer {
         assert!(!self.built, "attempt to re-use consumed builder");
         self.built = true;
+
+        let mut targets = self.targets.clone();
+        if targets.is_empty() {
+            targets.push(RoutedTarget {
+                target: Arc::new(Target::default().into()),
+                levels: (Bound::Unbounded, Bound::Unbounded),
+                format: None,
+            });
+        }
+
+        // The same buffer is written to every target, so styles are only
+        // printed if all of them accept styles.
+        let colored = match self.write_style {
+            _ if cfg!(target_arch = "wasm32") => false,
+            WriteStyle::Auto => targets.iter().all(|routed| match *routed.target {
+                WritableTarget::Stdout => is_stdout(),
+                WritableTarget::Stderr => is_stderr(),
+                _ => false,
+            }),
+            WriteStyle::Always => true,
+            WriteStyle::Never => false,
+        };
+
+        let policy = self.overflow_policy;
+        let batch = self.batch.map(|max_bytes| (max_bytes, policy));
+        let inner = Arc::new(BufferWriter::new(
+            targets,
+            batch,
+            colored,
+            self.is_test,
+            self.trim_trailing_newline,
+            self.max_chunk_bytes,
+            self.buffer_capacity,
+            self.max_retained_bytes,
+            self.platform.clone(),
+        ));
+
+        // There are no threads on wasm32, the records are written synchronously
+        #[cfg(all(feature = "async-writer", target_arch = "wasm32"))]
+        let _ = self.non_blocking;
+
         Writer {
-            inner: BufferWriter::new(),
+            #[cfg(all(feature = "async-writer", not(target_arch = "wasm32")))]
+            worker: self
+                .non_blocking
+                .map(|capacity| self::worker::Worker::spawn(inner.clone(), capacity, policy)),
+            inner,
         }
     }
 }
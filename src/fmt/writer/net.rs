@@ -0,0 +1,143 @@
+//! Targets sending the records over the network.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The default time a logging call may wait for the network.
+pub(super) const DEFAULT_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// The bytes buffered while the TCP stream is disconnected.
+const MAX_BUFFERED_BYTES: usize = 64 * 1024;
+
+/// The minimum delay between two connection attempts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))
+}
+
+/// A UDP socket sending one record per datagram.
+pub struct Udp {
+    socket: UdpSocket,
+}
+
+impl Udp {
+    pub(super) fn connect<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Udp> {
+        let addr = resolve(addr)?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_write_timeout(Some(timeout))?;
+        Ok(Udp { socket })
+    }
+
+    pub(super) fn send(&self, bytes: &[u8]) -> io::Result<()> {
+        self.socket.send(bytes).map(|_| ())
+    }
+}
+
+/// A TCP stream sending newline-delimited records, reconnected on failure.
+///
+/// The records are buffered while the stream is disconnected, the new ones
+/// being dropped once the buffer is full.
+pub struct Tcp {
+    addr: SocketAddr,
+    timeout: Duration,
+    state: Mutex<TcpState>,
+}
+
+struct TcpState {
+    stream: Option<TcpStream>,
+    pending: VecDeque<Vec<u8>>,
+    pending_bytes: usize,
+    last_attempt: Option<Instant>,
+}
+
+impl Tcp {
+    pub(super) fn new<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Tcp> {
+        Ok(Tcp {
+            addr: resolve(addr)?,
+            timeout,
+            state: Mutex::new(TcpState {
+                stream: None,
+                pending: VecDeque::new(),
+                pending_bytes: 0,
+                last_attempt: None,
+            }),
+        })
+    }
+
+    /// Queues a record and sends the queued ones, counting the record in
+    /// `dropped` if the queue is full.
+    pub(super) fn send(&self, bytes: &[u8], dropped: &AtomicU64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.pending_bytes + bytes.len() > MAX_BUFFERED_BYTES {
+            dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let mut record = bytes.to_vec();
+            if !record.ends_with(b"\n") {
+                record.push(b'\n');
+            }
+            state.pending_bytes += record.len();
+            state.pending.push_back(record);
+        }
+        self.send_pending(&mut state)
+    }
+
+    /// Sends the queued records, if the stream can be connected.
+    pub(super) fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        self.send_pending(&mut state)
+    }
+
+    fn send_pending(&self, state: &mut TcpState) -> io::Result<()> {
+        if state.pending.is_empty() {
+            return Ok(());
+        }
+
+        if state.stream.is_none() {
+            // Don't wait for an unreachable collector on every logging call
+            if state
+                .last_attempt
+                .map_or(false, |last| last.elapsed() < RECONNECT_DELAY)
+            {
+                return Ok(());
+            }
+            state.last_attempt = Some(Instant::now());
+
+            let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.set_nodelay(true)?;
+            state.stream = Some(stream);
+        }
+
+        while let Some(record) = state.pending.front() {
+            let result = match &mut state.stream {
+                Some(stream) => stream.write_all(record),
+                None => break,
+            };
+            match result {
+                Ok(()) => {
+                    state.pending_bytes -= record.len();
+                    state.pending.pop_front();
+                }
+                Err(e) => {
+                    // The record is sent again once reconnected
+                    state.stream = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,35 +1,398 @@
-use std::io;
+use std::io::Write;
+use std::ops::Bound;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::{fmt, io};
 
-use crate::platform;
+use arc_swap::ArcSwap;
+use log::{Level, Metadata};
+
+use crate::fmt::writer::{
+    chunk, Levels, OverflowPolicy, Route, RoutedTarget, Sink, Target, TargetId, WritableTarget,
+};
+use crate::info::BufferInfo;
+use crate::platform::{self, SharedPlatform};
+use crate::stats::TargetStats;
 
 pub(in crate::fmt::writer) struct BufferWriter {
+    // Swapped as a whole when targets are changed at runtime, so a record is
+    // written either to the previous targets or to the new ones.
+    sinks: ArcSwap<Vec<Arc<Sink>>>,
+    next_id: AtomicU64,
+    batch: Option<(usize, OverflowPolicy)>,
+    colored: bool,
+    is_test: bool,
+    trim_trailing_newline: bool,
+    max_chunk_bytes: usize,
+    buffer_capacity: usize,
+    max_retained_bytes: usize,
+    // Prints to `stdout` and `stderr` instead of the default platform
+    platform: Option<SharedPlatform>,
+    panics: AtomicU64,
+    dropped: AtomicU64,
 }
 
-pub(in crate::fmt) struct Buffer(Vec<u8>);
+pub(in crate::fmt) struct Buffer {
+    bytes: Vec<u8>,
+    colored: bool,
+    initial_capacity: usize,
+    max_retained_bytes: usize,
+}
 
 impl BufferWriter {
-    pub(in crate::fmt::writer) fn new() -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(in crate::fmt::writer) fn new(
+        targets: Vec<RoutedTarget>,
+        batch: Option<(usize, OverflowPolicy)>,
+        colored: bool,
+        is_test: bool,
+        trim_trailing_newline: bool,
+        max_chunk_bytes: usize,
+        buffer_capacity: usize,
+        max_retained_bytes: usize,
+        platform: Option<SharedPlatform>,
+    ) -> Self {
+        let count = targets.len() as u64;
+        let sinks = targets
+            .into_iter()
+            .zip(0..)
+            .map(|(routed, id)| {
+                let RoutedTarget {
+                    target,
+                    levels,
+                    format,
+                } = routed;
+                Arc::new(Sink::new(id, target, levels, format, batch))
+            })
+            .collect();
+
         Self {
+            sinks: ArcSwap::from_pointee(sinks),
+            next_id: AtomicU64::new(count),
+            batch,
+            colored,
+            is_test,
+            trim_trailing_newline,
+            max_chunk_bytes,
+            buffer_capacity,
+            max_retained_bytes,
+            platform,
+            panics: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub(in crate::fmt::writer) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(in crate::fmt::writer) fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    pub(in crate::fmt::writer) fn is_colored(&self) -> bool {
+        self.colored
+    }
+
+    pub(in crate::fmt::writer) fn target_count(&self) -> usize {
+        self.sinks.load().len()
+    }
+
+    pub(in crate::fmt::writer) fn target_kinds(&self) -> Vec<&'static str> {
+        self.sinks
+            .load()
+            .iter()
+            .map(|sink| sink.target.kind())
+            .collect()
+    }
+
+    pub(in crate::fmt::writer) fn buffer_info(&self) -> BufferInfo {
+        BufferInfo {
+            capacity: self.buffer_capacity,
+            max_retained_bytes: self.max_retained_bytes,
+            max_chunk_bytes: self.max_chunk_bytes,
+            batch_bytes: self.batch.map(|(max_bytes, _)| max_bytes),
+        }
+    }
+
+    pub(in crate::fmt::writer) fn target_ids(&self) -> Vec<TargetId> {
+        self.sinks.load().iter().map(|sink| sink.id).collect()
+    }
+
+    pub(in crate::fmt::writer) fn target_stats(&self) -> Vec<(String, TargetStats)> {
+        self.sinks
+            .load()
+            .iter()
+            .map(|sink| (sink.label(), sink.counters.snapshot()))
+            .collect()
+    }
+
+    pub(in crate::fmt::writer) fn accepts(&self, level: Level) -> bool {
+        self.sinks.load().iter().any(|sink| sink.accepts(level))
+    }
+
+    pub(in crate::fmt::writer) fn accepts_format(
+        &self,
+        level: Level,
+        format: Option<usize>,
+    ) -> bool {
+        self.sinks
+            .load()
+            .iter()
+            .any(|sink| sink.accepts_format(level, format))
+    }
+
+    fn new_sink(&self, target: Target, levels: Levels) -> Arc<Sink> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Arc::new(Sink::new(
+            id,
+            Arc::new(target.into()),
+            levels,
+            None,
+            self.batch,
+        ))
+    }
+
+    /// Replaces the targets, then flushes the previous ones.
+    pub(in crate::fmt::writer) fn set_target(&self, target: Target) -> io::Result<TargetId> {
+        let sink = self.new_sink(target, (Bound::Unbounded, Bound::Unbounded));
+        let id = sink.id;
+        self.replace_sinks(vec![sink])?;
+        Ok(id)
+    }
+
+    /// Replaces the targets with routed ones, then flushes the previous ones.
+    pub(in crate::fmt::writer) fn set_routes(
+        &self,
+        targets: Vec<(Target, Levels)>,
+    ) -> io::Result<Vec<TargetId>> {
+        let sinks: Vec<_> = targets
+            .into_iter()
+            .map(|(target, levels)| self.new_sink(target, levels))
+            .collect();
+        let ids = sinks.iter().map(|sink| sink.id).collect();
+        self.replace_sinks(sinks)?;
+        Ok(ids)
+    }
+
+    fn replace_sinks(&self, sinks: Vec<Arc<Sink>>) -> io::Result<()> {
+        let previous = self.sinks.swap(Arc::new(sinks));
+        Self::collect_errors(previous.iter().map(|sink| self.flush_sink(sink)))
+    }
+
+    pub(in crate::fmt::writer) fn add_target(&self, target: Target) -> TargetId {
+        let sink = self.new_sink(target, (Bound::Unbounded, Bound::Unbounded));
+        let id = sink.id;
+        self.sinks.rcu(|sinks| {
+            let mut sinks = Vec::clone(sinks);
+            sinks.push(sink.clone());
+            sinks
+        });
+        id
+    }
+
+    /// Removes a target, then flushes it.
+    pub(in crate::fmt::writer) fn remove_target(&self, id: TargetId) -> io::Result<bool> {
+        let previous = self.sinks.rcu(|sinks| {
+            let mut sinks = Vec::clone(sinks);
+            sinks.retain(|sink| sink.id != id);
+            sinks
+        });
+
+        match previous.iter().find(|sink| sink.id == id) {
+            Some(removed) => self.flush_sink(removed).map(|_| true),
+            None => Ok(false),
         }
     }
 
     pub(in crate::fmt::writer) fn buffer(&self) -> Buffer {
-        Buffer(Vec::new())
+        Buffer {
+            bytes: Vec::with_capacity(self.buffer_capacity),
+            colored: self.colored,
+            initial_capacity: self.buffer_capacity,
+            max_retained_bytes: self.max_retained_bytes,
+        }
+    }
+
+    /// Writes the bytes in order to every target routed for the level and the
+    /// format of the record, or to all of them if there is no record.
+    ///
+    /// A failing target doesn't prevent the record from being written to the
+    /// next ones, the errors are collected and returned together.
+    pub(in crate::fmt::writer) fn print(
+        &self,
+        record: &[u8],
+        route: Option<Route>,
+    ) -> io::Result<()> {
+        let metadata = route.map(|(metadata, _)| metadata);
+        self.for_each_sink(|sink| {
+            let accepts = |(metadata, format): Route| sink.accepts_format(metadata.level(), format);
+            if !route.map_or(true, accepts) {
+                return Ok(());
+            }
+            let result = match &sink.batch {
+                Some(batch) => batch.push(record, &self.dropped, |bytes| {
+                    self.print_to(&sink.target, bytes, None)
+                }),
+                None => self.print_to(&sink.target, record, metadata).map(|_| true),
+            };
+            sink.counters.record(record.len(), &result);
+            result.map(|_| ())
+        })
+    }
+
+    /// Writes the batched records of every target, then flushes the pipes.
+    pub(in crate::fmt::writer) fn flush(&self) -> io::Result<()> {
+        self.for_each_sink(|sink| self.flush_sink(sink))
+    }
+
+    fn flush_sink(&self, sink: &Sink) -> io::Result<()> {
+        if let Some(batch) = &sink.batch {
+            batch.flush(|bytes| self.print_to(&sink.target, bytes, None))?;
+        }
+        match &*sink.target {
+            WritableTarget::Pipe(pipe) => {
+                pipe.lock().unwrap_or_else(PoisonError::into_inner).flush()
+            }
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Tcp(tcp) => tcp.flush(),
+            _ => Ok(()),
+        }
     }
 
-    pub(in crate::fmt::writer) fn print(&self, buf: &Buffer) -> io::Result<()> {
-        platform::print(&buf.0);
+    /// Runs `f` on every target, collecting the errors.
+    fn for_each_sink<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnMut(&Sink) -> io::Result<()>,
+    {
+        let sinks = self.sinks.load();
+        Self::collect_errors(sinks.iter().map(|sink| &**sink).map(f))
+    }
+
+    fn collect_errors<I>(results: I) -> io::Result<()>
+    where
+        I: Iterator<Item = io::Result<()>>,
+    {
+        let mut errors = Vec::new();
+        for (index, result) in results.enumerate() {
+            if let Err(e) = result {
+                errors.push(format!("target {}: {}", index, e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other, errors.join("; ")))
+        }
+    }
+
+    /// Prints the bytes, split into chunks on wasm32 if they are too long for
+    /// a single `ic_cdk::print`.
+    fn print_chunked<F>(&self, bytes: &[u8], print: F) -> io::Result<()>
+    where
+        F: Fn(&[u8]) -> io::Result<()>,
+    {
+        // Every `ic_cdk::print` is already a line of the canister log
+        let bytes = if self.trim_trailing_newline {
+            trim_newline(bytes)
+        } else {
+            bytes
+        };
+        if !cfg!(target_arch = "wasm32") || bytes.len() <= self.max_chunk_bytes {
+            return print(bytes);
+        }
+
+        let chunks = chunk::split(bytes, self.max_chunk_bytes);
+        let count = chunks.len();
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            print(&chunk::with_marker(chunk, index + 1, count))?;
+        }
+        Ok(())
+    }
+
+    // the metadata is only used by journald
+    #[cfg_attr(not(all(feature = "journald", unix)), allow(unused_variables))]
+    fn print_to(
+        &self,
+        target: &WritableTarget,
+        bytes: &[u8],
+        metadata: Option<&Metadata>,
+    ) -> io::Result<()> {
+        match target {
+            WritableTarget::Stdout => self.print_chunked(bytes, |bytes| {
+                match &self.platform {
+                    Some(platform) => platform.get().print(bytes),
+                    // The print macros are captured by `cargo test`
+                    None if self.is_test => platform::print(bytes),
+                    None => return platform::write_stdout(bytes),
+                }
+                Ok(())
+            })?,
+            WritableTarget::Stderr => self.print_chunked(bytes, |bytes| {
+                match &self.platform {
+                    Some(platform) => platform.get().eprint(bytes),
+                    None if self.is_test => platform::eprint(bytes),
+                    None => return platform::write_stderr(bytes),
+                }
+                Ok(())
+            })?,
+            WritableTarget::Pipe(pipe) => {
+                let mut pipe = pipe.lock().unwrap_or_else(PoisonError::into_inner);
+                pipe.write_all(bytes)?;
+            }
+            WritableTarget::Custom(f) => {
+                // The panic must not unwind into the `log!` call
+                if panic::catch_unwind(AssertUnwindSafe(|| f(bytes))).is_err() {
+                    self.panics.fetch_add(1, Ordering::Relaxed);
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "custom target panicked",
+                    ));
+                }
+            }
+            WritableTarget::Void => {}
+            #[cfg(all(feature = "journald", unix))]
+            WritableTarget::Journald(journald) => journald.send(bytes, metadata)?,
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Udp(udp) => udp.send(bytes)?,
+            #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+            WritableTarget::Tcp(tcp) => tcp.send(bytes, &self.dropped)?,
+        }
         Ok(())
     }
 }
 
+/// Strips a single trailing `\n` or `\r\n`.
+fn trim_newline(bytes: &[u8]) -> &[u8] {
+    match bytes.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => bytes,
+    }
+}
+
 impl Buffer {
+    /// Clears the buffer, shrinking it back to its initial capacity if a large
+    /// record grew it beyond the retained capacity.
     pub(in crate::fmt) fn clear(&mut self) {
-        self.0.clear();
+        self.bytes.clear();
+        if self.bytes.capacity() > self.max_retained_bytes {
+            self.bytes
+                .shrink_to(self.initial_capacity.min(self.max_retained_bytes));
+        }
+    }
+
+    pub(in crate::fmt) fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    pub(in crate::fmt) fn len(&self) -> usize {
+        self.bytes.len()
     }
 
     pub(in crate::fmt) fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.extend(buf);
+        self.bytes.extend(buf);
         Ok(buf.len())
     }
 
@@ -37,8 +400,51 @@ impl Buffer {
         Ok(())
     }
 
-    #[cfg(test)]
     pub(in crate::fmt) fn bytes(&self) -> &[u8] {
-        &self.0
+        &self.bytes
+    }
+
+    pub(in crate::fmt) fn is_colored(&self) -> bool {
+        self.colored
+    }
+}
+
+/// An ANSI style that can be applied to a value.
+#[derive(Clone, Copy, Debug)]
+pub(in crate::fmt) struct Style {
+    prefix: &'static str,
+}
+
+impl Style {
+    /// The style of the header braces.
+    pub(in crate::fmt) fn subtle() -> Self {
+        Style { prefix: "\x1b[90m" }
+    }
+
+    /// The style of a level.
+    pub(in crate::fmt) fn level(level: Level) -> Self {
+        let prefix = match level {
+            Level::Trace => "\x1b[36m",
+            Level::Debug => "\x1b[34m",
+            Level::Info => "\x1b[32m",
+            Level::Warn => "\x1b[33m",
+            Level::Error => "\x1b[1;31m",
+        };
+        Style { prefix }
+    }
+}
+
+/// A value that is written with a style, if any.
+pub(in crate::fmt) struct StyledValue<T> {
+    pub(in crate::fmt) style: Option<Style>,
+    pub(in crate::fmt) value: T,
+}
+
+impl<T: fmt::Display> fmt::Display for StyledValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.style {
+            Some(style) => write!(f, "{}{}\x1b[0m", style.prefix, self.value),
+            None => self.value.fmt(f),
+        }
     }
 }
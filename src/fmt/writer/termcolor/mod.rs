@@ -1,8 +1,8 @@
 /*
 This internal module contains the style and terminal writing implementation.
 
-Its public API is available when the `termcolor` crate is available.
-The terminal printing is shimmed when the `termcolor` crate is not available.
+Styles are written as plain ANSI escape codes, and only when the writer
+resolved its write style to print them.
 */
 
 mod imp;
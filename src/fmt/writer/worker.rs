@@ -0,0 +1,179 @@
+//! A background thread writing the records, so logging calls don't wait for
+//! the targets.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+use log::{Level, Metadata};
+
+use super::termcolor::BufferWriter;
+use super::{OverflowPolicy, Route};
+
+pub(super) struct Worker {
+    queue: Arc<Queue>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    // notified on every change of the state
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// The bytes of a record, with its level, target and format.
+type QueuedRecord = (Vec<u8>, Option<(Level, String, Option<usize>)>);
+
+#[derive(Default)]
+struct State {
+    records: VecDeque<QueuedRecord>,
+    // a record is being written
+    busy: bool,
+    closed: bool,
+}
+
+impl Worker {
+    /// Starts the thread writing to `inner`.
+    pub fn spawn(inner: Arc<BufferWriter>, capacity: usize, policy: OverflowPolicy) -> Worker {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        });
+
+        let worker_queue = queue.clone();
+        let handle = thread::Builder::new()
+            .name("ic_log writer".to_string())
+            .spawn(move || worker_queue.run(&inner))
+            .expect("failed to spawn the log writer thread");
+
+        Worker {
+            queue,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Queues a record, applying the overflow policy if the queue is full.
+    ///
+    /// Returns `false` if the worker was shut down, in which case the record
+    /// must be written by the caller.
+    pub fn send(&self, bytes: &[u8], route: Option<Route>) -> bool {
+        let queue = &self.queue;
+        let mut state = queue.lock();
+        while !state.closed && state.records.len() >= queue.capacity {
+            match queue.policy {
+                OverflowPolicy::Block => state = queue.wait(state),
+                OverflowPolicy::DropNewest => {
+                    queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.records.pop_front();
+                    queue.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        if state.closed {
+            return false;
+        }
+
+        let route = route
+            .map(|(metadata, format)| (metadata.level(), metadata.target().to_owned(), format));
+        state.records.push_back((bytes.to_vec(), route));
+        queue.changed.notify_all();
+        true
+    }
+
+    /// Waits for the queued records to be written.
+    pub fn wait_idle(&self) {
+        let mut state = self.queue.lock();
+        while state.busy || !state.records.is_empty() {
+            state = self.queue.wait(state);
+        }
+    }
+
+    /// Writes the queued records and stops the thread.
+    pub fn shutdown(&self) {
+        self.queue.lock().closed = true;
+        self.queue.changed.notify_all();
+
+        let handle = self
+            .handle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records that failed to be written.
+    pub fn errors(&self) -> u64 {
+        self.queue.errors.load(Ordering::Relaxed)
+    }
+}
+
+// The thread would otherwise stay parked on the queue once the writer is gone
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Queue {
+    fn run(&self, inner: &BufferWriter) {
+        loop {
+            let (bytes, route) = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(record) = state.records.pop_front() {
+                        state.busy = true;
+                        self.changed.notify_all();
+                        break record;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = self.wait(state);
+                }
+            };
+
+            let metadata = route
+                .as_ref()
+                .map(|(level, target, _)| Metadata::builder().level(*level).target(target).build());
+            let format = route.as_ref().and_then(|(_, _, format)| *format);
+            if inner
+                .print(&bytes, metadata.as_ref().map(|metadata| (metadata, format)))
+                .is_err()
+            {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+
+            self.lock().busy = false;
+            self.changed.notify_all();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
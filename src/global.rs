@@ -0,0 +1,83 @@
+//! The global logger.
+//!
+//! The `log` crate accepts a global logger once per process, while a canister
+//! may initialize its logger again, in `post_upgrade` for instance. A static
+//! proxy is installed once instead, forwarding to the logger installed last.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+
+use arc_swap::ArcSwapOption;
+use log::{Log, Metadata, Record};
+
+use crate::{Error, Logger};
+
+static LOGGER: ArcSwapOption<Logger> = ArcSwapOption::const_empty();
+
+static PROXY: Once = Once::new();
+
+// Whether the proxy is the global logger of the `log` crate
+static PROXY_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+struct Proxy;
+
+impl Log for Proxy {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        LOGGER
+            .load()
+            .as_ref()
+            .map_or(false, |logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = LOGGER.load().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = LOGGER.load().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Makes `logger` the global logger, replacing the one installed before if
+/// any.
+///
+/// Fails if a global logger not built by this crate is installed.
+pub(crate) fn install(logger: Logger) -> Result<(), Error> {
+    PROXY.call_once(|| {
+        if log::set_logger(&Proxy).is_ok() {
+            PROXY_INSTALLED.store(true, Ordering::Release);
+        }
+    });
+    if !PROXY_INSTALLED.load(Ordering::Acquire) {
+        return Err(Error::AlreadyInitialized);
+    }
+
+    let logger = Arc::new(logger);
+    if let Some(previous) = LOGGER.swap(Some(logger.clone())) {
+        // The config of the previous logger must not change the max level
+        previous.detach();
+    }
+    logger.attach();
+    Ok(())
+}
+
+/// Whether the global logger was initialized by this crate.
+///
+/// Initializing it again replaces the logger, so this is mostly useful to
+/// initialize it only once, when the code doing it can run several times.
+///
+/// # Examples
+///
+/// ```
+/// if !ic_log::is_initialized() {
+///     ic_log::init();
+/// }
+/// assert!(ic_log::is_initialized());
+/// ```
+pub fn is_initialized() -> bool {
+    LOGGER.load().is_some()
+}
@@ -0,0 +1,89 @@
+//! A summary of the configuration of a built logger.
+//!
+//! It answers "why is nothing logged" from a diagnostics query, without
+//! exposing the internal types of the logger.
+
+use crate::fmt::writer::Writer;
+use crate::fmt::BuiltFormat;
+use crate::state::FilterState;
+
+/// The configuration of a logger, returned by [`LoggerConfig::info`].
+///
+/// It is serializable with the `serde` feature, and can be returned from a
+/// query with the `candid` feature.
+///
+/// [`LoggerConfig::info`]: struct.LoggerConfig.html#method.info
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "candid", derive(ic_cdk::export::candid::CandidType))]
+#[cfg_attr(feature = "candid", candid_path("ic_cdk::export::candid"))]
+#[non_exhaustive]
+pub struct LoggerInfo {
+    /// Whether the logger is enabled, see `LoggerConfig::set_enabled`.
+    pub enabled: bool,
+    /// The directives of the filter.
+    pub filter: String,
+    /// The kinds of the targets, like `stdout` or `pipe`, in order.
+    pub targets: Vec<String>,
+    /// The format of the records.
+    pub format: FormatInfo,
+    /// The buffering of the records.
+    pub buffer: BufferInfo,
+}
+
+/// The format of the records of a logger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "candid", derive(ic_cdk::export::candid::CandidType))]
+#[cfg_attr(feature = "candid", candid_path("ic_cdk::export::candid"))]
+#[non_exhaustive]
+pub struct FormatInfo {
+    /// `default`, `custom`, `serde` or the name of a built-in format.
+    pub kind: String,
+    /// Whether the default format writes the level.
+    pub level: bool,
+    /// Whether the default format writes the target.
+    pub target: bool,
+    /// Whether the default format writes the module path.
+    pub module_path: bool,
+    /// The indentation of the continuation lines of the default format.
+    pub indent: Option<usize>,
+    /// The suffix of the records of the default and serde formats.
+    pub suffix: String,
+}
+
+/// The buffering of the records of a logger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "candid", derive(ic_cdk::export::candid::CandidType))]
+#[cfg_attr(feature = "candid", candid_path("ic_cdk::export::candid"))]
+#[non_exhaustive]
+pub struct BufferInfo {
+    /// The initial capacity of the formatting buffer.
+    pub capacity: usize,
+    /// The capacity kept by the formatting buffer between records.
+    pub max_retained_bytes: usize,
+    /// The maximum size of a single print on wasm32.
+    pub max_chunk_bytes: usize,
+    /// The size of the batches, if the records are batched.
+    pub batch_bytes: Option<usize>,
+}
+
+pub(crate) fn collect(
+    filter: &FilterState,
+    writer: &Writer,
+    format: &BuiltFormat,
+    enabled: bool,
+) -> LoggerInfo {
+    LoggerInfo {
+        enabled,
+        filter: filter.load().to_string(),
+        targets: writer
+            .target_kinds()
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+        format: format.info(),
+        buffer: writer.buffer_info(),
+    }
+}
@@ -1,15 +1,95 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{cell::RefCell, io};
 
-use arc_swap::{ArcSwap, ArcSwapAny};
-use env_logger::filter::{Filter, self};
-use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use arc_swap::ArcSwap;
+#[cfg(feature = "ic")]
+use ic_cdk::export::Principal;
+use log::{Level, LevelFilter, Log, Metadata, Record};
 
+#[macro_use]
+mod macros;
+
+mod budget;
+#[cfg(feature = "ic")]
+mod caller;
+#[cfg(feature = "candid")]
+mod canister;
+pub mod context;
+#[cfg(any(all(target_arch = "wasm32", feature = "ic"), test))]
+mod cycles;
+mod dedup;
+mod error;
+mod filter;
+mod flush;
 pub mod fmt;
+mod global;
+mod info;
+#[cfg(feature = "http-outcall")]
+pub mod outcall;
+mod panic_hook;
 pub mod platform;
+pub mod prelude;
+mod rate_limit;
+mod recorder;
+mod sampling;
+mod scoped;
+mod settings;
+#[cfg(feature = "slog")]
+mod slog_drain;
+mod snapshot;
+mod state;
+mod stats;
+mod temporary;
+pub mod testing;
+#[cfg(feature = "tracing")]
+mod tracing_layer;
 
+use self::budget::CallBudget;
+#[cfg(all(target_arch = "wasm32", feature = "ic"))]
+use self::cycles::CyclesThrottle;
+use self::dedup::Dedup;
 use self::fmt::writer::{self, Writer};
-use self::fmt::{FormatFn, Formatter};
+use self::fmt::{BuiltFormat, ErrorFn, FormatFn, FormatKind, Formatter, RedactFn};
+use self::platform::{Clock, Platform, SharedPlatform};
+use self::rate_limit::RateLimiter;
+use self::recorder::{FlightRecorder, Recorded};
+use self::sampling::Sampler;
+use self::state::{FilterState, MetadataPredicate};
+use self::stats::Counters;
+use self::temporary::TemporaryFilter;
+
+/// The `log` crate used by `ic_log`, see the [`prelude`](prelude/index.html).
+pub use log;
+
+#[cfg(feature = "candid")]
+#[doc(hidden)]
+pub use self::canister::{__reject, __unguarded};
+pub use self::error::Error;
+pub use self::filter::{
+    validate_filters, FilterParseError, InvalidDirective, ParsedDirective, ParsedDirectives,
+};
+pub use self::flush::FlushGuard;
+#[cfg(feature = "async-writer")]
+pub use self::flush::WorkerGuard;
+pub use self::fmt::{OverflowPolicy, Target, TargetId, WriteStyle};
+pub use self::global::is_initialized;
+pub use self::info::{BufferInfo, FormatInfo, LoggerInfo};
+pub use self::panic_hook::install_panic_hook;
+pub use self::scoped::ScopedLogger;
+pub use self::settings::{init_log, LogSettings};
+#[cfg(feature = "slog")]
+pub use self::slog_drain::SlogDrain;
+pub use self::snapshot::FilterSnapshot;
+pub use self::stats::{LoggerStats, TargetStats};
+#[cfg(feature = "tracing")]
+pub use self::tracing_layer::TracingLayer;
+
+/// The default name for the environment variable to read filters from.
+pub const DEFAULT_FILTER_ENV: &str = "RUST_LOG";
 
 /// The env logger.
 ///
@@ -21,19 +101,38 @@ use self::fmt::{FormatFn, Formatter};
 /// default global logger.
 ///
 /// If you'd instead need access to the constructed `Logger`, you can use
-/// the associated [`Builder`] and install it with the
-/// [`log` crate][log-crate-url] directly.
+/// [`Builder::build_logger()`] and install it with the
+/// [`log` crate][log-crate-url] directly, possibly wrapped in another logger.
+///
+/// Clones of a logger share its filter, targets and statistics, so one can
+/// be installed while another is kept by the caller.
 ///
 /// [log-crate-url]: https://docs.rs/log/
 /// [`init()`]: fn.init.html
 /// [`try_init()`]: fn.try_init.html
 /// [`Builder::init()`]: struct.Builder.html#method.init
 /// [`Builder::try_init()`]: struct.Builder.html#method.try_init
-/// [`Builder`]: struct.Builder.html
+/// [`Builder::build_logger()`]: struct.Builder.html#method.build_logger
+#[derive(Clone)]
 pub struct Logger {
-    writer: Writer,
-    filter: Arc<ArcSwapAny<Arc<Filter>>>,
-    format: FormatFn,
+    writer: Arc<Writer>,
+    filter: Arc<FilterState>,
+    format: Arc<ArcSwap<BuiltFormat>>,
+    target_formats: Vec<FormatFn>,
+    clock: Arc<Clock>,
+    redact: Option<RedactFn>,
+    on_error: Option<ErrorFn>,
+    budget: Option<Arc<CallBudget>>,
+    dedup: Option<Arc<Dedup>>,
+    skip_empty_messages: bool,
+    rate_limiter: Arc<RateLimiter>,
+    sampler: Arc<Sampler>,
+    #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+    cycles: Option<Arc<CyclesThrottle>>,
+    recorder: Option<Arc<FlightRecorder>>,
+    counters: Arc<Counters>,
+    temporary: Arc<TemporaryFilter>,
+    enabled: Arc<AtomicBool>,
 }
 
 /// `Builder` acts as builder for initializing a `Logger`.
@@ -53,8 +152,9 @@ pub struct Logger {
 ///
 /// builder
 ///     .format(|buf, record| writeln!(buf, "{} - {}", record.level(), record.args()))
-///     .filter(None, LevelFilter::Info)
-///     .init();
+///     .filter(None, LevelFilter::Info);
+///
+/// builder.try_init_consuming().unwrap();
 ///
 /// error!("error message");
 /// info!("info message");
@@ -64,9 +164,49 @@ pub struct Builder {
     filter: filter::Builder,
     writer: writer::Builder,
     format: fmt::Builder,
+    target_formats: Vec<FormatFn>,
+    redact: Option<RedactFn>,
+    on_error: Option<ErrorFn>,
+    max_bytes_per_call: Option<usize>,
+    dedup_interval_ns: Option<u64>,
+    skip_empty_messages: bool,
+    rate_limits: Vec<(String, u32, u64)>,
+    sampling: Vec<(Level, u32)>,
+    min_cycles: Vec<(Level, u128)>,
+    flight_recorder: Option<(Level, usize, usize)>,
+    flight_recorder_trigger: Option<Level>,
+    filter_fns: Vec<MetadataPredicate>,
+    platform: Option<SharedPlatform>,
+    time_source: Option<fn() -> u64>,
     built: bool,
 }
 
+// A clone of a built builder can be built again.
+impl Clone for Builder {
+    fn clone(&self) -> Self {
+        Builder {
+            filter: self.filter.clone(),
+            writer: self.writer.clone(),
+            format: self.format.clone(),
+            target_formats: self.target_formats.clone(),
+            redact: self.redact,
+            on_error: self.on_error.clone(),
+            max_bytes_per_call: self.max_bytes_per_call,
+            dedup_interval_ns: self.dedup_interval_ns,
+            skip_empty_messages: self.skip_empty_messages,
+            rate_limits: self.rate_limits.clone(),
+            sampling: self.sampling.clone(),
+            min_cycles: self.min_cycles.clone(),
+            flight_recorder: self.flight_recorder,
+            flight_recorder_trigger: self.flight_recorder_trigger,
+            filter_fns: self.filter_fns.clone(),
+            platform: self.platform.clone(),
+            time_source: self.time_source,
+            built: false,
+        }
+    }
+}
+
 impl Builder {
     /// Initializes the log builder with defaults.
     ///
@@ -91,10 +231,65 @@ impl Builder {
     ///
     /// [`filter`]: #method.filter
     /// [`write_style`]: #method.write_style
+    /// [`from_env`]: #method.from_env
+    /// [`from_default_env`]: #method.from_default_env
     pub fn new() -> Builder {
         Default::default()
     }
 
+    /// Initializes the log builder from the directives of an environment variable.
+    ///
+    /// The variable is read on native targets only, wasm canisters have no
+    /// environment. An unset or empty variable leaves the defaults untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// let mut builder = Builder::from_env("MY_LOG");
+    /// builder.init();
+    /// ```
+    pub fn from_env(var_name: &str) -> Builder {
+        let mut builder = Builder::new();
+        builder.parse_env(var_name);
+        builder
+    }
+
+    /// Initializes the log builder from the `RUST_LOG` environment variable.
+    ///
+    /// See [`from_env`] for more details.
+    ///
+    /// [`from_env`]: #method.from_env
+    pub fn from_default_env() -> Builder {
+        Builder::from_env(DEFAULT_FILTER_ENV)
+    }
+
+    /// Applies the directives of an environment variable.
+    ///
+    /// The directives are parsed like [`parse_filters`], so they override the
+    /// filters configured before. The variable is read on native targets only,
+    /// and an unset or empty variable changes nothing.
+    ///
+    /// [`parse_filters`]: #method.parse_filters
+    pub fn parse_env(&mut self, var_name: &str) -> &mut Self {
+        if let Some(filters) = platform::env_var(var_name) {
+            if !filters.trim().is_empty() {
+                self.parse_filters(&filters);
+            }
+        }
+        self
+    }
+
+    /// Applies the directives of the `RUST_LOG` environment variable.
+    ///
+    /// See [`parse_env`] for more details.
+    ///
+    /// [`parse_env`]: #method.parse_env
+    pub fn parse_default_env(&mut self) -> &mut Self {
+        self.parse_env(DEFAULT_FILTER_ENV)
+    }
+
     /// Sets the format function for formatting the log output.
     ///
     /// This function is called on each record logged and should format the
@@ -105,6 +300,12 @@ impl Builder {
     /// to format and output without intermediate heap allocations. The default
     /// `ic_log` formatter takes advantage of this.
     ///
+    /// If the function panics on a native target, the panic is caught and the
+    /// record is replaced with `[FORMAT FAILED]` followed by its level and
+    /// target, and counted in [`LoggerStats::format_panics`]. On wasm32 a
+    /// panic traps the canister and can't be caught, so the format should
+    /// not unwrap the values of the records.
+    ///
     /// # Examples
     ///
     /// Use a custom format to write only the log message:
@@ -121,11 +322,54 @@ impl Builder {
     /// [`Formatter`]: fmt/struct.Formatter.html
     /// [`String`]: https://doc.rust-lang.org/stable/std/string/struct.String.html
     /// [`std::fmt`]: https://doc.rust-lang.org/std/fmt/index.html
-    pub fn format<F: 'static>(&mut self, format: F) -> &mut Self
+    /// [`LoggerStats::format_panics`]: struct.LoggerStats.html#structfield.format_panics
+    pub fn format<F>(&mut self, format: F) -> &mut Self
+    where
+        F: Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send + 'static,
+    {
+        self.format.custom_format = Some(Arc::new(format));
+        self.format.serde_format = None;
+        self
+    }
+
+    /// Sets a format that serializes a user-defined value for each record as JSON.
+    ///
+    /// The value returned by `format` is serialized with `serde_json` directly into
+    /// the [`Formatter`], followed by the suffix set with [`format_suffix`].
+    /// Serialization errors are handled like any other formatting error, and the
+    /// record is dropped.
+    ///
+    /// This method is only available with the `json` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// #[derive(serde::Serialize)]
+    /// struct Entry {
+    ///     level: &'static str,
+    ///     message: String,
+    /// }
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.format_serde(|record| Entry {
+    ///     level: record.level().as_str(),
+    ///     message: record.args().to_string(),
+    /// });
+    /// ```
+    ///
+    /// [`Formatter`]: fmt/struct.Formatter.html
+    /// [`format_suffix`]: #method.format_suffix
+    #[cfg(feature = "json")]
+    pub fn format_serde<F, T>(&mut self, format: F) -> &mut Self
     where
-        F: Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send,
+        F: Fn(&Record) -> T + Sync + Send + 'static,
+        T: serde::Serialize,
     {
-        self.format.custom_format = Some(Box::new(format));
+        self.format.serde_format = Some(fmt::json::serde_format(format));
+        self.format.custom_format = None;
         self
     }
 
@@ -139,300 +383,4373 @@ impl Builder {
 
     /// Whether or not to write the level in the default format.
     pub fn format_level(&mut self, write: bool) -> &mut Self {
-        self.format.format_level = write;
+        self.format.default_format.level(write);
         self
     }
 
     /// Whether or not to write the module path in the default format.
     pub fn format_module_path(&mut self, write: bool) -> &mut Self {
-        self.format.format_module_path = write;
+        self.format.default_format.module_path(write);
         self
     }
 
     /// Whether or not to write the target in the default format.
     pub fn format_target(&mut self, write: bool) -> &mut Self {
-        self.format.format_target = write;
+        self.format.default_format.target(write);
+        self
+    }
+
+    /// Configures the column width of the target in the default format.
+    ///
+    /// Shorter targets are padded with spaces and longer ones are truncated
+    /// with a trailing `…`, keeping messages visually aligned.
+    /// A value of `None` writes the target as is.
+    pub fn format_target_width(&mut self, width: Option<usize>) -> &mut Self {
+        self.format.default_format.target_width(width);
+        self
+    }
+
+    /// Whether or not to write the target when the module path is missing, and
+    /// vice versa, in the default format.
+    ///
+    /// This only applies when exactly one of [`format_module_path`] and
+    /// [`format_target`] is enabled. Defaults to `true`.
+    ///
+    /// [`format_module_path`]: #method.format_module_path
+    /// [`format_target`]: #method.format_target
+    pub fn format_origin_fallback(&mut self, fallback: bool) -> &mut Self {
+        self.format.default_format.origin_fallback(fallback);
+        self
+    }
+
+    /// Whether or not to write the name of the executing canister method in the default format.
+    ///
+    /// The method is written as `method=<name>`, or `method=-` outside of an
+    /// endpoint that recorded its name with [`context::set_method_name`], e.g.
+    /// in heartbeats, timers or `init`. This has no effect on native targets.
+    ///
+    /// [`context::set_method_name`]: context/fn.set_method_name.html
+    pub fn format_method_name(&mut self, write: bool) -> &mut Self {
+        self.format.default_format.method_name(write);
         self
     }
 
     /// Configures the amount of spaces to use to indent multiline log records.
     /// A value of `None` disables any kind of indentation.
     pub fn format_indent(&mut self, indent: Option<usize>) -> &mut Self {
-        self.format.format_indent = indent;
+        self.format.default_format.indent(indent);
         self
     }
 
     /// Configures the end of line suffix.
     pub fn format_suffix(&mut self, suffix: &'static str) -> &mut Self {
-        self.format.format_suffix = suffix;
+        self.format.default_format.suffix(suffix);
         self
     }
 
-    /// Adds a directive to the filter for a specific module.
+    /// Sets a redaction hook applied to every rendered record.
+    ///
+    /// The hook receives the fully formatted record, with both the built-in and
+    /// custom formats, and returns the text that is actually written. It can be
+    /// used to make sure that principals, emails or secrets never reach the logs.
+    /// Returning [`Cow::Borrowed`] keeps the record as formatted, so a changed
+    /// record must be returned as [`Cow::Owned`].
+    ///
+    /// If the hook panics the record is replaced by a fixed `[REDACTION FAILED]`
+    /// message. Note that on wasm32 a panic traps regardless.
     ///
     /// # Examples
     ///
-    /// Only include messages for info and above for logs in `path::to::module`:
+    /// Hide every digit:
     ///
     /// ```
+    /// use std::borrow::Cow;
     /// use ic_log::Builder;
-    /// use log::LevelFilter;
     ///
     /// let mut builder = Builder::new();
     ///
-    /// builder.filter_module("path::to::module", LevelFilter::Info);
+    /// builder.redact(|text| {
+    ///     if text.contains(|c: char| c.is_ascii_digit()) {
+    ///         Cow::Owned(text.replace(|c: char| c.is_ascii_digit(), "#"))
+    ///     } else {
+    ///         Cow::Borrowed(text)
+    ///     }
+    /// });
     /// ```
-    pub fn filter_module(&mut self, module: &str, level: LevelFilter) -> &mut Self {
-        self.filter.filter_module(module, level);
+    ///
+    /// [`Cow::Borrowed`]: https://doc.rust-lang.org/std/borrow/enum.Cow.html#variant.Borrowed
+    /// [`Cow::Owned`]: https://doc.rust-lang.org/std/borrow/enum.Cow.html#variant.Owned
+    pub fn redact(&mut self, redact: fn(&str) -> Cow<str>) -> &mut Self {
+        self.redact = Some(redact);
         self
     }
 
-    /// Adds a directive to the filter for all modules.
+    /// Sets the target for the log output.
+    ///
+    /// Records are written to `stdout` by default. On wasm32 `stdout` and
+    /// `stderr` both print with `ic_cdk::print`, while a [`Target::Pipe`] is
+    /// written to on every target.
     ///
     /// # Examples
     ///
-    /// Only include messages for info and above for logs globally:
+    /// Write log messages to `stderr`:
     ///
     /// ```
-    /// use ic_log::Builder;
-    /// use log::LevelFilter;
+    /// use ic_log::{Builder, Target};
     ///
     /// let mut builder = Builder::new();
     ///
-    /// builder.filter_level(LevelFilter::Info);
+    /// builder.target(Target::Stderr);
     /// ```
-    pub fn filter_level(&mut self, level: LevelFilter) -> &mut Self {
-        self.filter.filter_level(level);
+    ///
+    /// Write log messages to a file:
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let file = File::create("canister.log").unwrap();
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.target(Target::Pipe(Box::new(file)));
+    /// ```
+    ///
+    /// [`Target::Pipe`]: enum.Target.html#variant.Pipe
+    pub fn target(&mut self, target: Target) -> &mut Self {
+        self.writer.target(target);
         self
     }
 
-    /// Adds filters to the logger.
+    /// Adds a target for the log output, keeping the ones added before.
     ///
-    /// The given module (if any) will log at most the specified level provided.
-    /// If no module is provided then the filter will apply to all log messages.
+    /// Every record is written to all the targets in order. A target failing
+    /// doesn't prevent the record from being written to the next ones. Without
+    /// any target added, records are written to `stdout`.
     ///
     /// # Examples
     ///
-    /// Only include messages for info and above for logs in `path::to::module`:
+    /// Write log messages to `stdout` and to a file:
     ///
-    /// ```
-    /// use ic_log::Builder;
-    /// use log::LevelFilter;
+    /// ```no_run
+    /// use std::fs::File;
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let file = File::create("canister.log").unwrap();
     ///
     /// let mut builder = Builder::new();
     ///
-    /// builder.filter(Some("path::to::module"), LevelFilter::Info);
+    /// builder
+    ///     .add_target(Target::Stdout)
+    ///     .add_target(Target::Pipe(Box::new(file)));
     /// ```
-    pub fn filter(&mut self, module: Option<&str>, level: LevelFilter) -> &mut Self {
-        self.filter.filter(module, level);
+    pub fn add_target(&mut self, target: Target) -> &mut Self {
+        self.writer.add_target(target);
         self
     }
 
-    /// Parses the directives string in the same form as the `RUST_LOG`
-    /// environment variable.
+    /// Adds a target receiving all records, formatted with its own format.
     ///
-    /// See the module documentation for more details.
-    pub fn parse_filters(&mut self, filters: &str) -> &mut Self {
-        self.filter.parse(filters);
+    /// The other targets keep using the format set with [`format`], or the
+    /// default one. A record is formatted once per distinct format, whatever
+    /// the number of targets.
+    ///
+    /// # Examples
+    ///
+    /// Write plain messages to `stdout` and JSON-like lines to a file:
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Write;
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let file = File::create("canister.jsonl").unwrap();
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder
+    ///     .format(|buf, record| writeln!(buf, "{}", record.args()))
+    ///     .add_target(Target::Stdout)
+    ///     .add_target_with_format(Target::Pipe(Box::new(file)), |buf, record| {
+    ///         writeln!(buf, "{{\"level\":\"{}\",\"msg\":{:?}}}", record.level(), record.args())
+    ///     });
+    /// ```
+    ///
+    /// [`format`]: #method.format
+    pub fn add_target_with_format<F>(&mut self, target: Target, format: F) -> &mut Self
+    where
+        F: Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send + 'static,
+    {
+        self.writer
+            .add_target_with_format(target, self.target_formats.len());
+        self.target_formats.push(Arc::new(format));
         self
     }
 
-    /// Initializes the global logger with the built env logger.
+    /// Adds a target for the records of a range of levels.
     ///
-    /// This should be called early in the execution of a Rust program. Any log
-    /// events that occur before initialization will be ignored.
+    /// Levels are ordered from the most severe, so `..=Level::Warn` routes
+    /// errors and warnings, while `Level::Info..` routes all the other records.
+    /// A record is written to every target routed for its level, in the order
+    /// they were added. Targets added with [`add_target`] receive all records.
     ///
-    /// # Errors
+    /// # Examples
     ///
-    /// This function will fail if it is called more than once, or if another
-    /// library has already initialized a global logger.
-    pub fn try_init(&mut self) -> Result<LoggerConfig, SetLoggerError> {
-        let (logger, filter) = self.build();
-
-        let max_level = logger.filter();
-        log::set_boxed_logger(Box::new(logger))?;
-        log::set_max_level(max_level);
-        Ok(filter)
+    /// Write errors and warnings to `stderr` and the other records to `stdout`:
+    ///
+    /// ```
+    /// use ic_log::{Builder, Target};
+    /// use log::Level;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder
+    ///     .route(..=Level::Warn, Target::Stderr)
+    ///     .route(Level::Info.., Target::Stdout);
+    /// ```
+    ///
+    /// [`add_target`]: #method.add_target
+    pub fn route<R>(&mut self, levels: R, target: Target) -> &mut Self
+    where
+        R: std::ops::RangeBounds<log::Level>,
+    {
+        self.writer.route(levels, target);
+        self
     }
 
-    /// Initializes the global logger with the built env logger.
+    /// Sets whether or not styles will be written.
     ///
-    /// This should be called early in the execution of a Rust program. Any log
-    /// events that occur before initialization will be ignored.
+    /// With [`WriteStyle::Auto`], the default, styles are only written if
+    /// every target is a terminal, which requires the `auto-color` feature.
+    /// Styles are never written on wasm32.
     ///
-    /// # Panics
+    /// # Examples
     ///
-    /// This function will panic if it is called more than once, or if another
-    /// library has already initialized a global logger.
-    pub fn init(&mut self) {
-        self.try_init()
-            .expect("Builder::init should not be called after logger initialized");
+    /// Never write styles:
+    ///
+    /// ```
+    /// use ic_log::{Builder, WriteStyle};
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.write_style(WriteStyle::Never);
+    /// ```
+    ///
+    /// [`WriteStyle::Auto`]: enum.WriteStyle.html#variant.Auto
+    pub fn write_style(&mut self, write_style: WriteStyle) -> &mut Self {
+        self.writer.write_style(write_style);
+        self
     }
 
-    /// Build an env logger.
+    /// Parses the write style, either `auto`, `always` or `never`.
     ///
-    /// The returned logger implements the `Log` trait and can be installed manually
-    /// or nested within another logger.
-    pub fn build(&mut self) -> (Logger, LoggerConfig) {
-        assert!(!self.built, "attempt to re-use consumed builder");
-        self.built = true;
-
-        let filter = Arc::new(ArcSwap::from_pointee(self.filter.build()));
-
-        (Logger {
-            writer: self.writer.build(),
-            filter: filter.clone(),
-            format: self.format.build(),
-        }, LoggerConfig { filter })
+    /// Any other value is treated as `auto`, so an environment variable can be
+    /// passed as is. See [`write_style`] for more details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut builder = ic_log::Builder::new();
+    ///
+    /// if let Ok(style) = std::env::var("MY_LOG_STYLE") {
+    ///     builder.parse_write_style(&style);
+    /// }
+    /// ```
+    ///
+    /// [`write_style`]: #method.write_style
+    pub fn parse_write_style(&mut self, write_style: &str) -> &mut Self {
+        self.write_style(WriteStyle::parse(write_style))
     }
-}
-
-pub struct LoggerConfig {
-    filter: Arc<ArcSwapAny<Arc<Filter>>>
-}
-
-impl LoggerConfig {
 
-    // Updates the logger filter
-    pub fn update_filters(&self, filters: &str) {
-        let new_filter = filter::Builder::default().parse(filters).build();
-        let max_level = new_filter.filter();
-        self.filter.swap(Arc::new(new_filter));
-        log::set_max_level(max_level);
+    /// Sets whether or not the logger will be used in unit tests.
+    ///
+    /// If `is_test` is `true` then the logger will allow the testing framework to
+    /// capture log records printed to `stdout` and `stderr`, so they are only
+    /// shown for failing tests. Pipes and custom targets are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut builder = ic_log::Builder::new();
+    ///
+    /// builder.is_test(true);
+    /// ```
+    pub fn is_test(&mut self, is_test: bool) -> &mut Self {
+        self.writer.is_test(is_test);
+        self
     }
 
-}
-
-impl Logger {
-
-    /// Returns the maximum `LevelFilter` that this env logger instance is
-    /// configured to output.
-    pub fn filter(&self) -> LevelFilter {
-        self.filter.load().filter()
+    /// Sets whether the trailing newline of the records printed to `stdout` and
+    /// `stderr` is stripped.
+    ///
+    /// Every `ic_cdk::print` is already a separate line of the canister log, so
+    /// the newline ending a record would show up as a blank line. A single
+    /// trailing `\n` or `\r\n` is stripped from each print, the newlines inside
+    /// of a multi-line record are kept. Pipes and custom targets still receive
+    /// the whole record.
+    ///
+    /// Defaults to `true` on wasm32 and `false` on the other targets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut builder = ic_log::Builder::new();
+    ///
+    /// builder.trim_trailing_newline(false);
+    /// ```
+    pub fn trim_trailing_newline(&mut self, trim: bool) -> &mut Self {
+        self.writer.trim_trailing_newline(trim);
+        self
     }
 
-    /// Checks if this record matches the configured filter.
-    pub fn matches(&self, record: &Record) -> bool {
-        self.filter.load().matches(record)
+    /// Batches the records written to a target into prints of up to `max_bytes`.
+    ///
+    /// On the IC every `ic_cdk::print` has an overhead, and its output is
+    /// interleaved with the one of other canisters. With batching, records are
+    /// kept in a buffer of up to `max_bytes` until the logger is flushed, then
+    /// printed at once. A record is never split across two prints. Custom
+    /// targets still receive one record per call.
+    ///
+    /// The records that don't fit in a full batch are handled according to the
+    /// [`overflow_policy`], dropping them by default. The logger should then be
+    /// flushed at the end of every update call, for instance by keeping the
+    /// guard returned by [`LoggerConfig::flush_on_drop`]:
+    ///
+    /// ```
+    /// use ic_log::{Builder, LoggerConfig};
+    ///
+    /// fn update_call(config: &LoggerConfig) {
+    ///     let _flush = config.flush_on_drop();
+    ///
+    ///     log::info!("transfer started");
+    ///     log::info!("transfer completed");
+    /// }
+    ///
+    /// let (_logger, config) = Builder::new().batch(4096).build();
+    /// update_call(&config);
+    /// ```
+    ///
+    /// [`overflow_policy`]: #method.overflow_policy
+    /// [`LoggerConfig::flush_on_drop`]: struct.LoggerConfig.html#method.flush_on_drop
+    pub fn batch(&mut self, max_bytes: usize) -> &mut Self {
+        self.writer.batch(max_bytes);
+        self
     }
-}
 
-impl Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        self.filter.load().enabled(metadata)
+    /// Writes the records from a background thread, queuing up to `capacity`
+    /// records.
+    ///
+    /// Logging calls then don't wait for the targets, which is useful with
+    /// slow pipes like files or sockets. The records that don't fit in a full
+    /// queue are handled according to the [`overflow_policy`], and the errors
+    /// of the targets are counted in [`LoggerStats::write_errors`] without
+    /// being reported to [`on_error`]. The guard returned by
+    /// [`try_init_with_guard`], or by [`LoggerConfig::worker_guard`], writes
+    /// the queued records before the program exits. The thread is also stopped
+    /// once the logger and all its configurations are dropped.
+    ///
+    /// On wasm32 there are no threads and the records are written synchronously.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// let (_config, _guard) = Builder::new().non_blocking(1024).try_init_with_guard().unwrap();
+    ///
+    /// log::error!("written from the background thread");
+    /// ```
+    ///
+    /// [`overflow_policy`]: #method.overflow_policy
+    /// [`on_error`]: #method.on_error
+    /// [`LoggerStats::write_errors`]: struct.LoggerStats.html#structfield.write_errors
+    /// [`try_init_with_guard`]: #method.try_init_with_guard
+    /// [`LoggerConfig::worker_guard`]: struct.LoggerConfig.html#method.worker_guard
+    #[cfg(feature = "async-writer")]
+    pub fn non_blocking(&mut self, capacity: usize) -> &mut Self {
+        self.writer.non_blocking(capacity);
+        self
     }
 
-    fn log(&self, record: &Record) {
-        if self.matches(record) {
-            // Log records are written to a thread-local buffer before being printed
-            // to the terminal. We clear these buffers afterwards, but they aren't shrunk
-            // so will always at least have capacity for the largest log record formatted
-            // on that thread.
-            //
-            // If multiple `Logger`s are used by the same threads then the thread-local
-            // formatter might have different color support. If this is the case the
-            // formatter and its buffer are discarded and recreated.
-
-            thread_local! {
-                static FORMATTER: RefCell<Option<Formatter>> = RefCell::new(None);
-            }
-
-            let print = |formatter: &mut Formatter, record: &Record| {
-                let _ =
-                    (self.format)(formatter, record).and_then(|_| formatter.print(&self.writer));
-
-                // Always clear the buffer afterwards
-                formatter.clear();
-            };
-
-            let printed = FORMATTER
-                .try_with(|tl_buf| {
-                    match tl_buf.try_borrow_mut() {
-                        // There are no active borrows of the buffer
-                        Ok(mut tl_buf) => match *tl_buf {
-                            // We have a previously set formatter
-                            Some(ref mut formatter) => {
-                                print(formatter, record);
-                            }
-                            // We don't have a previously set formatter
-                            None => {
-                                let mut formatter = Formatter::new(&self.writer);
-                                print(&mut formatter, record);
-
-                                *tl_buf = Some(formatter);
-                            }
-                        },
-                        // There's already an active borrow of the buffer (due to re-entrancy)
-                        Err(_) => {
-                            print(&mut Formatter::new(&self.writer), record);
-                        }
-                    }
-                })
-                .is_ok();
+    /// Sets what to do with the records that don't fit in a full batch, or in
+    /// the queue of the background thread.
+    ///
+    /// With [`OverflowPolicy::Block`] a full queue makes the logging call wait
+    /// for the background thread. The default is [`OverflowPolicy::DropNewest`].
+    /// Dropped records are counted in [`LoggerStats::overflow_dropped`], and a
+    /// summary line is printed after the batch when it's flushed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::{Builder, OverflowPolicy};
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.batch(4096).overflow_policy(OverflowPolicy::Block);
+    /// ```
+    ///
+    /// [`OverflowPolicy::Block`]: enum.OverflowPolicy.html#variant.Block
+    /// [`OverflowPolicy::DropNewest`]: enum.OverflowPolicy.html#variant.DropNewest
+    /// [`LoggerStats::overflow_dropped`]: struct.LoggerStats.html#structfield.overflow_dropped
+    pub fn overflow_policy(&mut self, overflow_policy: OverflowPolicy) -> &mut Self {
+        self.writer.overflow_policy(overflow_policy);
+        self
+    }
 
-            if !printed {
-                // The thread-local storage was not available (because its
-                // destructor has already run). Create a new single-use
-                // Formatter on the stack for this call.
-                print(&mut Formatter::new(&self.writer), record);
-            }
+    /// Sets a function called when a record fails to be formatted or written.
+    ///
+    /// The failures are also counted in [`LoggerStats::write_errors`]. If the
+    /// function logs records itself, their failures are counted but don't call
+    /// it again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use ic_log::Builder;
+    ///
+    /// static LOG_FAILED: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.on_error(|_| LOG_FAILED.store(true, Ordering::Relaxed));
+    /// ```
+    ///
+    /// [`LoggerStats::write_errors`]: struct.LoggerStats.html#structfield.write_errors
+    pub fn on_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the maximum size of a single `ic_cdk::print`.
+    ///
+    /// The replica truncates long debug prints, so on wasm32 longer records are
+    /// printed as several chunks prefixed with a `(part i/n) ` marker. Chunks
+    /// end after a newline when possible, and never in the middle of a UTF-8
+    /// character. The default is 8 KiB. Native targets aren't affected.
+    pub fn max_chunk_bytes(&mut self, max_chunk_bytes: usize) -> &mut Self {
+        self.writer.max_chunk_bytes(max_chunk_bytes);
+        self
+    }
+
+    /// Sets the platform providing the time of the records and printing the
+    /// records written to `stdout` and `stderr`.
+    ///
+    /// The platform is only used by the built logger, the functions of the
+    /// [`platform`] module and the other loggers aren't affected. By default
+    /// the IC system API is used on wasm32 with the `ic` feature, and the
+    /// standard library everywhere else. See [`Platform`].
+    ///
+    /// [`platform`]: platform/index.html
+    /// [`Platform`]: platform/trait.Platform.html
+    pub fn platform<P>(&mut self, platform: P) -> &mut Self
+    where
+        P: Platform + 'static,
+    {
+        let platform = SharedPlatform::new(platform);
+        self.writer.platform(platform.clone());
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Sets the time of the records, in nanoseconds since the epoch.
+    ///
+    /// The time source replaces the time of the platform in every stage of
+    /// the logger: the timestamps of the default format and of the
+    /// [`Formatter`], the expiry of the temporary filters, the collapsing of
+    /// duplicates, the rate limits and, on wasm32, the start of a new call for
+    /// the [`max_bytes_per_call`] budget. It can be changed at runtime with
+    /// [`LoggerConfig::set_time_source`], for example to simulate a clock jump.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// fn epoch() -> u64 {
+    ///     0
+    /// }
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.time_source(epoch);
+    /// ```
+    ///
+    /// [`Formatter`]: fmt/struct.Formatter.html
+    /// [`max_bytes_per_call`]: #method.max_bytes_per_call
+    /// [`LoggerConfig::set_time_source`]: struct.LoggerConfig.html#method.set_time_source
+    pub fn time_source(&mut self, time_source: fn() -> u64) -> &mut Self {
+        self.time_source = Some(time_source);
+        self
+    }
+
+    /// Sets the initial capacity of the formatting buffer.
+    ///
+    /// The buffer grows as needed, so by default it starts empty. Setting the
+    /// capacity to the usual size of a record avoids reallocating it during the
+    /// first records logged from a thread. The buffer is also shrunk back to
+    /// this capacity after a record larger than [`max_retained_buffer_bytes`].
+    ///
+    /// [`max_retained_buffer_bytes`]: #method.max_retained_buffer_bytes
+    pub fn buffer_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.writer.buffer_capacity(capacity);
+        self
+    }
+
+    /// Sets the capacity kept by the formatting buffer between records.
+    ///
+    /// Records are formatted into a buffer reused by every record logged from
+    /// the same thread. A record larger than this grows the buffer, which is
+    /// shrunk back once the record is printed, so a single large record
+    /// doesn't permanently cost canister memory. The default is 8 KiB.
+    pub fn max_retained_buffer_bytes(&mut self, max_bytes: usize) -> &mut Self {
+        self.writer.max_retained_bytes(max_bytes);
+        self
+    }
+
+    /// Limits the amount of formatted bytes emitted during a single call.
+    ///
+    /// A record is charged once for the bytes of all its formats, see
+    /// [`add_target_with_format`], and is either printed whole or dropped. Once
+    /// a record doesn't fit in the budget, the following records of the call
+    /// are silently dropped. When the next call starts, a single `log output
+    /// truncated, N records suppressed` record is logged at the `Warn` level
+    /// with the `ic_log` target, ahead of the first record of the call and
+    /// without being charged. On wasm32 a new call starts at the first record
+    /// with a new `ic_cdk::api::time()`; a new call can also be started
+    /// explicitly with [`LoggerConfig::reset_call_budget`].
+    ///
+    /// The number of suppressed records is available through [`LoggerConfig::stats`].
+    ///
+    /// [`add_target_with_format`]: #method.add_target_with_format
+    /// [`LoggerConfig::reset_call_budget`]: struct.LoggerConfig.html#method.reset_call_budget
+    /// [`LoggerConfig::stats`]: struct.LoggerConfig.html#method.stats
+    pub fn max_bytes_per_call(&mut self, max_bytes: usize) -> &mut Self {
+        self.max_bytes_per_call = Some(max_bytes);
+        self
+    }
+
+    /// Collapses consecutive duplicate records.
+    ///
+    /// A record with the same level, target and message as the previous one
+    /// is dropped and counted in [`LoggerStats::duplicates_suppressed`]. Once a
+    /// different record arrives, a single `last message repeated N times` line
+    /// is logged before it. While the same record keeps repeating, that line is
+    /// also logged every `max_interval_ns` nanoseconds.
+    ///
+    /// Only a hash of the previous record is kept. Duplicates are not collapsed
+    /// by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// // report the repetitions at least once a minute
+    /// builder.collapse_duplicates(60 * 1_000_000_000);
+    /// ```
+    ///
+    /// [`LoggerStats::duplicates_suppressed`]: struct.LoggerStats.html#structfield.duplicates_suppressed
+    pub fn collapse_duplicates(&mut self, max_interval_ns: u64) -> &mut Self {
+        self.dedup_interval_ns = Some(max_interval_ns);
+        self
+    }
+
+    /// Drops the records whose message is empty or only made of whitespace.
+    ///
+    /// Some crates log records with an empty message, which are printed as a
+    /// line with only the header of the record. The message is rendered
+    /// without allocating to check it, after the filters and before the
+    /// records reach the targets or the [flight recorder], and the dropped
+    /// records are counted in [`LoggerStats::empty_skipped`].
+    ///
+    /// The records with an empty message are kept by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut builder = ic_log::Builder::new();
+    ///
+    /// builder.skip_empty_messages(true);
+    /// ```
+    ///
+    /// [flight recorder]: #method.flight_recorder
+    /// [`LoggerStats::empty_skipped`]: struct.LoggerStats.html#structfield.empty_skipped
+    pub fn skip_empty_messages(&mut self, skip: bool) -> &mut Self {
+        self.skip_empty_messages = skip;
+        self
+    }
+
+    /// Limits the records of the modules starting with `module_prefix` to
+    /// `max_records` per `period_ns` nanoseconds.
+    ///
+    /// The limit is a token bucket: up to `max_records` records can be logged
+    /// in a burst, then the bucket refills continuously over the period. It is
+    /// checked after the level filter, using the bucket with the longest prefix
+    /// of the record target. Suppressed records are counted in
+    /// [`LoggerStats::rate_limited`] and per prefix in
+    /// [`LoggerConfig::rate_limited`], and a warning with their number is
+    /// logged once the next record of the prefix is let through.
+    ///
+    /// The limits can be changed at runtime with
+    /// [`LoggerConfig::set_rate_limit`].
+    ///
+    /// # Examples
+    ///
+    /// Log at most 10 records per second from the `ledger` module:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.rate_limit("ledger", 10, 1_000_000_000);
+    /// ```
+    ///
+    /// [`LoggerStats::rate_limited`]: struct.LoggerStats.html#structfield.rate_limited
+    /// [`LoggerConfig::rate_limited`]: struct.LoggerConfig.html#method.rate_limited
+    /// [`LoggerConfig::set_rate_limit`]: struct.LoggerConfig.html#method.set_rate_limit
+    pub fn rate_limit(
+        &mut self,
+        module_prefix: &str,
+        max_records: u32,
+        period_ns: u64,
+    ) -> &mut Self {
+        self.rate_limits
+            .push((module_prefix.to_owned(), max_records, period_ns));
+        self
+    }
+
+    /// Keeps only one in `one_in` records of `level`.
+    ///
+    /// The records are counted rather than drawn at random, so the first of
+    /// every `one_in` records passing the filter is kept and the logs are
+    /// reproducible. Sampled out records are counted in
+    /// [`LoggerStats::sampled_out`]. By default all records are kept, which is
+    /// also what a `one_in` of 0 or 1 does.
+    ///
+    /// The sampling can be changed at runtime with
+    /// [`LoggerConfig::set_sampling`].
+    ///
+    /// # Examples
+    ///
+    /// Keep all warnings and errors, but only one in 100 debug records:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::Level;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.sample(Level::Debug, 100);
+    /// ```
+    ///
+    /// [`LoggerStats::sampled_out`]: struct.LoggerStats.html#structfield.sampled_out
+    /// [`LoggerConfig::set_sampling`]: struct.LoggerConfig.html#method.set_sampling
+    pub fn sample(&mut self, level: Level, one_in: u32) -> &mut Self {
+        self.sampling.push((level, one_in));
+        self
+    }
+
+    /// Drops the records of `level` and the less severe ones while the cycles
+    /// balance of the canister is under `threshold`.
+    ///
+    /// Printing records costs cycles, which a canister close to freezing
+    /// can't spare. The balance is checked after the filter and the sampling,
+    /// and read once per context recorded with [`context::set_method_name`],
+    /// or for every record outside of one. The dropped records are counted in
+    /// [`LoggerStats::cycles_throttled`], and a single warning is logged when
+    /// the balance goes under a threshold, ahead of the first record checked.
+    /// Errors are always kept. Several levels can be given different
+    /// thresholds.
+    ///
+    /// This only applies on wasm32 with the `ic` feature, the thresholds are
+    /// ignored on the other targets.
+    ///
+    /// # Examples
+    ///
+    /// Drop the debug records under 1T cycles, and the info ones under 100B:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::Level;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder
+    ///     .min_cycles_for_level(Level::Debug, 1_000_000_000_000)
+    ///     .min_cycles_for_level(Level::Info, 100_000_000_000);
+    /// ```
+    ///
+    /// [`LoggerStats::cycles_throttled`]: struct.LoggerStats.html#structfield.cycles_throttled
+    /// [`context::set_method_name`]: context/fn.set_method_name.html
+    pub fn min_cycles_for_level(&mut self, level: Level, threshold: u128) -> &mut Self {
+        self.min_cycles.push((level, threshold));
+        self
+    }
+
+    /// Keeps the records less severe than `threshold` in memory, writing them
+    /// only when an error is logged.
+    ///
+    /// These records are formatted into a ring of up to `max_records` records
+    /// and `max_bytes` bytes, the oldest ones being evicted first. When a record
+    /// at or above the [trigger level], `Error` by default, is logged, the ring
+    /// is written to the targets ahead of it, then emptied. The records still
+    /// have to pass the filter, so it must allow the levels to keep.
+    ///
+    /// The records are kept with the format set with [`format`], and written
+    /// to the targets routed for their level only once dumped.
+    ///
+    /// # Examples
+    ///
+    /// Keep the last 100 debug records and write them before the next error:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::{Level, LevelFilter};
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder
+    ///     .filter_level(LevelFilter::Debug)
+    ///     .flight_recorder(Level::Info, 100, 64 * 1024);
+    /// ```
+    ///
+    /// [trigger level]: #method.flight_recorder_trigger
+    /// [`format`]: #method.format
+    pub fn flight_recorder(
+        &mut self,
+        threshold: Level,
+        max_records: usize,
+        max_bytes: usize,
+    ) -> &mut Self {
+        self.flight_recorder = Some((threshold, max_records, max_bytes));
+        self
+    }
+
+    /// Sets the level at or above which a record writes the records kept by
+    /// the [flight recorder] ahead of it.
+    ///
+    /// Defaults to `Error`.
+    ///
+    /// [flight recorder]: #method.flight_recorder
+    pub fn flight_recorder_trigger(&mut self, level: Level) -> &mut Self {
+        self.flight_recorder_trigger = Some(level);
+        self
+    }
+
+    /// Adds a directive to the filter for a specific module.
+    ///
+    /// # Examples
+    ///
+    /// Only include messages for info and above for logs in `path::to::module`:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::LevelFilter;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.filter_module("path::to::module", LevelFilter::Info);
+    /// ```
+    pub fn filter_module(&mut self, module: &str, level: LevelFilter) -> &mut Self {
+        self.filter.filter_module(module, level);
+        self
+    }
+
+    /// Adds a directive to the filter for all modules.
+    ///
+    /// # Examples
+    ///
+    /// Only include messages for info and above for logs globally:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::LevelFilter;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.filter_level(LevelFilter::Info);
+    /// ```
+    pub fn filter_level(&mut self, level: LevelFilter) -> &mut Self {
+        self.filter.filter_level(level);
+        self
+    }
+
+    /// Adds filters to the logger.
+    ///
+    /// The given module (if any) will log at most the specified level provided.
+    /// If no module is provided then the filter will apply to all log messages.
+    ///
+    /// # Examples
+    ///
+    /// Only include messages for info and above for logs in `path::to::module`:
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::LevelFilter;
+    ///
+    /// let mut builder = Builder::new();
+    ///
+    /// builder.filter(Some("path::to::module"), LevelFilter::Info);
+    /// ```
+    pub fn filter(&mut self, module: Option<&str>, level: LevelFilter) -> &mut Self {
+        self.filter.filter(module, level);
+        self
+    }
+
+    /// Adds a predicate on the record metadata, checked after the directives.
+    ///
+    /// Records for which the predicate returns `false` are suppressed and
+    /// counted in [`LoggerStats::filter_fn_suppressed`]. When called several
+    /// times, all the predicates must return `true`. The predicates can be
+    /// replaced at runtime with [`LoggerConfig::set_filter_fn`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use ic_log::Builder;
+    ///
+    /// static MAINTENANCE: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let mut builder = Builder::new();
+    /// builder.filter_fn(|metadata| {
+    ///     let syncing = metadata.target().starts_with("my_canister::sync");
+    ///     !(MAINTENANCE.load(Ordering::Relaxed) && syncing)
+    /// });
+    /// ```
+    ///
+    /// [`LoggerStats::filter_fn_suppressed`]: struct.LoggerStats.html#structfield.filter_fn_suppressed
+    /// [`LoggerConfig::set_filter_fn`]: struct.LoggerConfig.html#method.set_filter_fn
+    pub fn filter_fn<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Metadata) -> bool + Send + Sync + 'static,
+    {
+        self.filter_fns.push(Arc::new(f));
+        self
+    }
+
+    /// Parses the directives string in the same form as the `RUST_LOG`
+    /// environment variable.
+    ///
+    /// Directives can be followed by `/filter` to only keep records whose
+    /// message matches `filter`: a regular expression with the `regex` feature,
+    /// a plain substring otherwise.
+    ///
+    /// See the module documentation for more details.
+    pub fn parse_filters(&mut self, filters: &str) -> &mut Self {
+        self.filter.parse(filters);
+        self
+    }
+
+    /// Parses the directives string like [`parse_filters`], but fails instead
+    /// of skipping invalid directives.
+    ///
+    /// Strict parsing also rejects bare words that are not a level, like
+    /// `infoo`: use `module=trace` to enable all the levels of a module.
+    ///
+    /// # Errors
+    ///
+    /// The returned [`FilterParseError`] lists every invalid directive with its
+    /// position. The builder is left unchanged on failure.
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    ///
+    /// let err = Builder::new().try_parse_filters("infoo,=debug").unwrap_err();
+    /// assert_eq!(2, err.invalid_directives().len());
+    /// ```
+    ///
+    /// [`parse_filters`]: #method.parse_filters
+    /// [`FilterParseError`]: struct.FilterParseError.html
+    pub fn try_parse_filters(&mut self, filters: &str) -> Result<&mut Self, FilterParseError> {
+        self.filter.try_parse(filters)?;
+        Ok(self)
+    }
+
+    /// Initializes the global logger with the built env logger.
+    ///
+    /// This should be called early in the execution of a Rust program. Any log
+    /// events that occur before initialization will be ignored.
+    ///
+    /// # Errors
+    ///
+    /// If the global logger was already initialized by this crate, the logger
+    /// is replaced by the new one, and the previous config no longer has any
+    /// effect on the global logger.
+    ///
+    /// This function will fail with [`Error::AlreadyInitialized`] if another
+    /// library has already initialized a global logger, and with
+    /// [`Error::AlreadyBuilt`] if the builder was already built.
+    ///
+    /// [`Error::AlreadyInitialized`]: enum.Error.html#variant.AlreadyInitialized
+    /// [`Error::AlreadyBuilt`]: enum.Error.html#variant.AlreadyBuilt
+    pub fn try_init(&mut self) -> Result<LoggerConfig, Error> {
+        Self::install(self.build_checked()?)
+    }
+
+    /// Initializes the global logger with the built env logger, returning the
+    /// guard of its background writer thread.
+    ///
+    /// Dropping the [`WorkerGuard`] writes the queued records and stops the
+    /// thread, so it should be kept until the end of `main`. See
+    /// [`non_blocking`].
+    ///
+    /// # Errors
+    ///
+    /// This function fails like [`try_init`].
+    ///
+    /// [`WorkerGuard`]: struct.WorkerGuard.html
+    /// [`non_blocking`]: #method.non_blocking
+    /// [`try_init`]: #method.try_init
+    #[cfg(feature = "async-writer")]
+    pub fn try_init_with_guard(&mut self) -> Result<(LoggerConfig, WorkerGuard), Error> {
+        let config = self.try_init()?;
+        let guard = config.worker_guard();
+        Ok((config, guard))
+    }
+
+    /// Initializes the global logger with the built env logger, consuming the
+    /// builder.
+    ///
+    /// Unlike [`try_init`], it can't be called on a builder already built.
+    ///
+    /// # Errors
+    ///
+    /// This function will fail if the builder was already built, or if another
+    /// library has already initialized a global logger.
+    ///
+    /// [`try_init`]: #method.try_init
+    pub fn try_init_consuming(self) -> Result<LoggerConfig, Error> {
+        Self::install(self.try_build()?)
+    }
+
+    fn install((logger, config): (Logger, LoggerConfig)) -> Result<LoggerConfig, Error> {
+        global::install(logger)?;
+        testing::register(&config.writer);
+        Ok(config)
+    }
+
+    /// Initializes the global logger with the built env logger.
+    ///
+    /// This should be called early in the execution of a Rust program. Any log
+    /// events that occur before initialization will be ignored.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the builder was already built, or if another
+    /// library has already initialized a global logger.
+    pub fn init(&mut self) {
+        self.try_init()
+            .expect("Builder::init failed to initialize the global logger");
+    }
+
+    /// Build an env logger, consuming the builder.
+    ///
+    /// Unlike [`build`], the builder can't be used afterwards. To build several
+    /// similar loggers, clone a configured builder before building each one.
+    /// The targets are shared by the clones, so records of all the loggers are
+    /// written to the same pipes and custom functions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    /// use ic_log::Builder;
+    ///
+    /// let mut builder = Builder::new();
+    /// builder.filter_level(LevelFilter::Info);
+    ///
+    /// let (first, _) = builder.clone().build_consuming();
+    /// let (second, _) = builder.build_consuming();
+    /// ```
+    ///
+    /// [`build`]: #method.build
+    pub fn build_consuming(mut self) -> (Logger, LoggerConfig) {
+        self.build()
+    }
+
+    /// Build an env logger, consuming the builder.
+    ///
+    /// Helpers can take the builder by value and build it with this method,
+    /// so a builder can't be built twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBuilt`] if the builder was already built
+    /// with [`build`] or [`try_init`] before being moved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    /// use ic_log::Builder;
+    ///
+    /// fn quiet(mut builder: Builder) -> ic_log::Logger {
+    ///     builder.filter_level(LevelFilter::Error);
+    ///     builder.try_build().unwrap().0
+    /// }
+    ///
+    /// let logger = quiet(Builder::new());
+    /// assert_eq!(LevelFilter::Error, logger.filter());
+    /// ```
+    ///
+    /// [`Error::AlreadyBuilt`]: enum.Error.html#variant.AlreadyBuilt
+    /// [`build`]: #method.build
+    /// [`try_init`]: #method.try_init
+    pub fn try_build(mut self) -> Result<(Logger, LoggerConfig), Error> {
+        self.build_checked()
+    }
+
+    /// Build an env logger, to be installed manually.
+    ///
+    /// This is the same as [`build`]. The logger can be nested within another
+    /// logger, and a clone of it kept to log records directly.
+    ///
+    /// # Examples
+    ///
+    /// Install a logger forwarding the records to an ic_log logger:
+    ///
+    /// ```
+    /// use log::{Log, Metadata, Record};
+    ///
+    /// struct Forward(ic_log::Logger);
+    ///
+    /// impl Log for Forward {
+    ///     fn enabled(&self, metadata: &Metadata) -> bool {
+    ///         self.0.enabled(metadata)
+    ///     }
+    ///
+    ///     fn log(&self, record: &Record) {
+    ///         // forward to another subsystem here
+    ///         self.0.log(record)
+    ///     }
+    ///
+    ///     fn flush(&self) {
+    ///         self.0.flush()
+    ///     }
+    /// }
+    ///
+    /// let (logger, config) = ic_log::Builder::new().build_logger();
+    /// log::set_boxed_logger(Box::new(Forward(logger.clone()))).unwrap();
+    /// log::set_max_level(config.max_level());
+    /// // the config of a nested logger leaves the global max level alone
+    /// config.on_filter_change(|_, max_level| log::set_max_level(max_level));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the builder was already built.
+    ///
+    /// [`build`]: #method.build
+    pub fn build_logger(&mut self) -> (Logger, LoggerConfig) {
+        self.build()
+    }
+
+    /// Build an env logger.
+    ///
+    /// The returned logger implements the `Log` trait and can be installed manually
+    /// or nested within another logger.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the builder was already built.
+    pub fn build(&mut self) -> (Logger, LoggerConfig) {
+        self.build_checked().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn build_checked(&mut self) -> Result<(Logger, LoggerConfig), Error> {
+        if self.built {
+            return Err(Error::AlreadyBuilt);
+        }
+        self.built = true;
+
+        let filter_fns = self.filter_fns.clone();
+        let filter = Arc::new(FilterState::new(self.filter.build(), filter_fns));
+        let writer = Arc::new(self.writer.build());
+        let budget = self
+            .max_bytes_per_call
+            .map(|max| Arc::new(CallBudget::new(max)));
+        let format = Arc::new(ArcSwap::from_pointee(self.format.build_described()));
+        let clock = Arc::new(Clock::new(self.time_source, self.platform.clone()));
+        let counters = Arc::new(Counters::default());
+        let temporary = Arc::new(TemporaryFilter::new(clock.clone()));
+        let enabled = Arc::new(AtomicBool::new(true));
+        let rate_limiter = Arc::new(RateLimiter::new(clock.clone()));
+        for (prefix, max_records, period_ns) in &self.rate_limits {
+            rate_limiter.set(prefix, *max_records, *period_ns);
+        }
+        let sampler = Arc::new(Sampler::default());
+        for &(level, one_in) in &self.sampling {
+            sampler.set(level, one_in);
+        }
+        let recorder = self
+            .flight_recorder
+            .map(|(threshold, max_records, max_bytes)| {
+                let trigger = self.flight_recorder_trigger.unwrap_or(Level::Error);
+                Arc::new(FlightRecorder::new(
+                    threshold,
+                    trigger,
+                    max_records,
+                    max_bytes,
+                ))
+            });
+
+        Ok((
+            Logger {
+                writer: writer.clone(),
+                filter: filter.clone(),
+                format: format.clone(),
+                clock: clock.clone(),
+                target_formats: self.target_formats.clone(),
+                redact: self.redact,
+                on_error: self.on_error.clone(),
+                budget: budget.clone(),
+                dedup: self
+                    .dedup_interval_ns
+                    .map(|max_interval_ns| Arc::new(Dedup::new(max_interval_ns, clock.clone()))),
+                skip_empty_messages: self.skip_empty_messages,
+                rate_limiter: rate_limiter.clone(),
+                sampler: sampler.clone(),
+                #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+                cycles: Some(&self.min_cycles)
+                    .filter(|min_cycles| !min_cycles.is_empty())
+                    .map(|min_cycles| Arc::new(CyclesThrottle::new(min_cycles.clone()))),
+                recorder: recorder.clone(),
+                counters: counters.clone(),
+                temporary: temporary.clone(),
+                enabled: enabled.clone(),
+            },
+            LoggerConfig {
+                filter,
+                writer,
+                format,
+                clock,
+                budget,
+                rate_limiter,
+                sampler,
+                recorder,
+                counters,
+                temporary,
+                enabled,
+                active: Arc::new(AtomicBool::new(true)),
+            },
+        ))
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+thread_local! {
+    // The formatter reused by the records logged from the current thread.
+    static FORMATTER: RefCell<Option<Formatter>> = const { RefCell::new(None) };
+}
+
+// The formatter reused by all the records. Without the atomics target
+// feature a wasm32 module has a single thread, like a canister, so a plain
+// static skips the thread-local machinery.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+static FORMATTER: SingleThreaded = SingleThreaded(RefCell::new(None));
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+struct SingleThreaded(RefCell<Option<Formatter>>);
+
+// SAFETY: there are no other threads to share the formatter with.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Sync for SingleThreaded {}
+
+// Calls `f` with the reused formatter, or returns `None` if the thread-local
+// storage is not available anymore.
+#[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+#[inline]
+fn with_formatter<R>(f: impl FnOnce(&RefCell<Option<Formatter>>) -> R) -> Option<R> {
+    FORMATTER.try_with(f).ok()
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+#[inline]
+fn with_formatter<R>(f: impl FnOnce(&RefCell<Option<Formatter>>) -> R) -> Option<R> {
+    Some(f(&FORMATTER.0))
+}
+
+/// A handle to change the configuration of a built logger at runtime.
+///
+/// It is `Send` and `Sync`, and its clones all change the same logger, so
+/// it can be kept in a `thread_local` and handed to several modules.
+///
+/// The changes of the filter only update the global `log::max_level` while
+/// the logger is the global logger installed by this crate. Otherwise the
+/// logger is nested, or not installed at all, and [`max_level`] returns the
+/// level to apply.
+///
+/// [`max_level`]: #method.max_level
+///
+/// ```
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<ic_log::LoggerConfig>();
+/// ```
+#[derive(Clone)]
+pub struct LoggerConfig {
+    filter: Arc<FilterState>,
+    writer: Arc<Writer>,
+    format: Arc<ArcSwap<BuiltFormat>>,
+    clock: Arc<Clock>,
+    budget: Option<Arc<CallBudget>>,
+    rate_limiter: Arc<RateLimiter>,
+    sampler: Arc<Sampler>,
+    recorder: Option<Arc<FlightRecorder>>,
+    counters: Arc<Counters>,
+    temporary: Arc<TemporaryFilter>,
+    enabled: Arc<AtomicBool>,
+    active: Arc<AtomicBool>,
+}
+
+impl LoggerConfig {
+    /// Updates the logger filter.
+    ///
+    /// The directives are parsed strictly, like [`Builder::try_parse_filters`].
+    ///
+    /// # Errors
+    ///
+    /// If any directive is invalid, [`Error::InvalidFilter`] is returned and
+    /// both the previous filter and the global max level are left untouched.
+    ///
+    /// The filter and the global max level, if the logger is the global
+    /// logger, are updated together: concurrent updates are serialized, so the
+    /// max level always matches the filter installed last.
+    ///
+    /// This ends the temporary filter set with [`update_filters_for`], if any.
+    ///
+    /// [`Builder::try_parse_filters`]: struct.Builder.html#method.try_parse_filters
+    /// [`update_filters_for`]: #method.update_filters_for
+    /// [`Error::InvalidFilter`]: enum.Error.html#variant.InvalidFilter
+    pub fn update_filters(&self, filters: &str) -> Result<(), Error> {
+        let new_filter = filter::Builder::default().try_parse(filters)?.build();
+        self.filter.update(|state| {
+            self.temporary.cancel();
+            state.store(Arc::new(new_filter));
+        });
+        Ok(())
+    }
+
+    /// Parses a directives string without applying it, like [`validate_filters`].
+    ///
+    /// [`validate_filters`]: fn.validate_filters.html
+    pub fn validate(&self, filters: &str) -> Result<ParsedDirectives, FilterParseError> {
+        validate_filters(filters)
+    }
+
+    /// Merges directives into the logger filter, rather than replacing it.
+    ///
+    /// The new directives win over the current ones for the same module, and a
+    /// bare level replaces the default level. The message filter is replaced
+    /// only if the directives have one.
+    ///
+    /// ```
+    /// # let (_, config) = ic_log::Builder::new().parse_filters("warn").build();
+    /// config.merge_filters("my_canister::ledger=trace").unwrap();
+    /// assert_eq!("warn,my_canister::ledger=trace", config.current_filter_string());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Like [`update_filters`], an invalid directive returns
+    /// [`Error::InvalidFilter`] and leaves everything untouched.
+    ///
+    /// [`update_filters`]: #method.update_filters
+    /// [`Error::InvalidFilter`]: enum.Error.html#variant.InvalidFilter
+    pub fn merge_filters(&self, filters: &str) -> Result<(), Error> {
+        let mut directives = filter::Builder::new();
+        directives.try_parse(filters)?;
+        self.modify_filter(|builder| builder.merge(directives));
+        Ok(())
+    }
+
+    /// Updates the logger filter for `duration_ns` nanoseconds only.
+    ///
+    /// The first record logged after the expiry restores the previous filter
+    /// and the global max level. Calling it again while a temporary filter is
+    /// active replaces the temporary filter and its expiry, but the filter
+    /// restored is still the one in place before the first call.
+    ///
+    /// ```
+    /// # let (_, config) = ic_log::Builder::new().build();
+    /// // trace for the next hour
+    /// config.update_filters_for("trace", 3_600 * 1_000_000_000).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Like [`update_filters`], an invalid directive returns
+    /// [`Error::InvalidFilter`] and leaves everything untouched.
+    ///
+    /// [`update_filters`]: #method.update_filters
+    /// [`Error::InvalidFilter`]: enum.Error.html#variant.InvalidFilter
+    pub fn update_filters_for(&self, filters: &str, duration_ns: u64) -> Result<(), Error> {
+        let new_filter = filter::Builder::default().try_parse(filters)?.build();
+        self.filter.update(|state| {
+            let previous = state.store(Arc::new(new_filter));
+            self.temporary.begin(previous, duration_ns);
+        });
+        Ok(())
+    }
+
+    /// Ends the temporary filter set with [`update_filters_for`] right away,
+    /// restoring the previous filter.
+    ///
+    /// Returns `false` if there was no temporary filter.
+    ///
+    /// [`update_filters_for`]: #method.update_filters_for
+    pub fn cancel_override(&self) -> bool {
+        self.filter.update(|state| match self.temporary.cancel() {
+            Some(previous) => {
+                state.store(previous);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Sets the level of a module, keeping the other directives.
+    ///
+    /// ```
+    /// use ic_log::Builder;
+    /// use log::LevelFilter;
+    ///
+    /// let (_, config) = Builder::new().parse_filters("warn,my_canister=info").build();
+    /// config.set_module_level("my_canister::ledger", LevelFilter::Trace);
+    ///
+    /// assert_eq!(
+    ///     "warn,my_canister=info,my_canister::ledger=trace",
+    ///     config.current_filter_string()
+    /// );
+    /// ```
+    pub fn set_module_level(&self, module: &str, level: LevelFilter) {
+        self.modify_filter(|builder| builder.filter_module(module, level));
+    }
+
+    /// Sets the level of the modules without a directive of their own, keeping
+    /// the other directives.
+    pub fn set_default_level(&self, level: LevelFilter) {
+        self.modify_filter(|builder| builder.filter_level(level));
+    }
+
+    /// Removes the directive of a module, which then falls back to the directive
+    /// of its closest parent or to the default level.
+    pub fn remove_module(&self, module: &str) {
+        self.modify_filter(|builder| builder.remove_module(module));
+    }
+
+    // Derives a new filter from the current one and swaps it in, serialized with
+    // the other updates so concurrent modifications are not lost.
+    fn modify_filter<F>(&self, f: F)
+    where
+        F: FnOnce(&mut filter::Builder) -> &mut filter::Builder,
+    {
+        self.filter
+            .modify(|current| f(&mut current.to_builder()).build());
+    }
+
+    /// Overrides the level of the records logged while serving calls from
+    /// `caller`, replacing the directives of the filter.
+    ///
+    /// The override only applies to the messages whose caller was recorded
+    /// with [`context::record_caller`], so it never traps in heartbeats or
+    /// timers. It has no effect on native targets.
+    ///
+    /// ```
+    /// use ic_cdk::export::Principal;
+    /// use log::LevelFilter;
+    ///
+    /// let (_, config) = ic_log::Builder::new().build();
+    /// let user = Principal::from_text("2vxsx-fae").unwrap();
+    ///
+    /// config.set_caller_level(user, LevelFilter::Trace);
+    /// // ...
+    /// config.remove_caller_level(&user);
+    /// ```
+    ///
+    /// This method is only available with the `ic` feature.
+    ///
+    /// [`context::record_caller`]: context/fn.record_caller.html
+    #[cfg(feature = "ic")]
+    pub fn set_caller_level(&self, caller: Principal, level: LevelFilter) {
+        self.filter.set_caller_level(caller, level);
+    }
+
+    /// Removes the level override of `caller`, if any.
+    #[cfg(feature = "ic")]
+    pub fn remove_caller_level(&self, caller: &Principal) {
+        self.filter.remove_caller_level(caller);
+    }
+
+    /// Returns the most verbose level enabled for a module or target by the
+    /// current filter, picking the directive the same way as when matching a
+    /// record.
+    ///
+    /// For a level range directive, the most verbose level of the range is
+    /// returned. Caller overrides are not taken into account.
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    ///
+    /// let (_, config) = ic_log::Builder::new().parse_filters("warn,my_canister=debug").build();
+    ///
+    /// assert_eq!(LevelFilter::Debug, config.effective_level("my_canister::ledger"));
+    /// assert_eq!(LevelFilter::Warn, config.effective_level("candid"));
+    /// ```
+    pub fn effective_level(&self, module_or_target: &str) -> LevelFilter {
+        self.filter.load().effective_level(module_or_target)
+    }
+
+    /// Takes a snapshot of the current filter, to [`restore`] it later.
+    ///
+    /// ```
+    /// # let (_, config) = ic_log::Builder::new().parse_filters("warn").build();
+    /// let snapshot = config.snapshot();
+    /// config.update_filters("trace").unwrap();
+    /// // ...
+    /// config.restore(snapshot);
+    /// assert_eq!("warn", config.current_filter_string());
+    /// ```
+    ///
+    /// [`restore`]: #method.restore
+    pub fn snapshot(&self) -> FilterSnapshot {
+        FilterSnapshot::new(self.filter.load_full())
+    }
+
+    /// Puts back the filter of a snapshot, along with the global max level.
+    ///
+    /// Like [`update_filters`], this ends the temporary filter, if any.
+    ///
+    /// [`update_filters`]: #method.update_filters
+    pub fn restore(&self, snapshot: FilterSnapshot) {
+        self.filter.update(|state| {
+            self.temporary.cancel();
+            state.store(snapshot.filter);
+        });
+    }
+
+    /// Returns the directives string of the current filter, e.g.
+    /// `warn,my_canister::ledger=debug`.
+    ///
+    /// The string is canonical: the default level comes first, followed by the
+    /// module directives sorted by module and the message filter, if any.
+    /// Passing it to [`update_filters`] produces an equivalent filter.
+    ///
+    /// [`update_filters`]: #method.update_filters
+    pub fn current_filter_string(&self) -> String {
+        self.filter.load().to_string()
+    }
+
+    /// Returns the maximum `LevelFilter` that the current filter and caller
+    /// overrides are configured to output.
+    pub fn max_level(&self) -> LevelFilter {
+        self.filter.max_level()
+    }
+
+    /// Registers a callback invoked after every change of the filter or of the
+    /// caller overrides, e.g. by [`update_filters`] or [`set_module_level`].
+    ///
+    /// The callback receives the new directives string, as returned by
+    /// [`current_filter_string`], and the new max level. It is invoked once the
+    /// change is complete, so it can update the filter itself without
+    /// deadlocking. A panic in a callback is caught on native targets and
+    /// doesn't prevent the other callbacks from running.
+    ///
+    /// ```
+    /// # let (_, config) = ic_log::Builder::new().build();
+    /// config.on_filter_change(|directives, max_level| {
+    ///     println!("log filter changed to {} (max level {})", directives, max_level);
+    /// });
+    /// ```
+    ///
+    /// [`update_filters`]: #method.update_filters
+    /// [`set_module_level`]: #method.set_module_level
+    /// [`current_filter_string`]: #method.current_filter_string
+    pub fn on_filter_change<F>(&self, f: F)
+    where
+        F: Fn(&str, LevelFilter) + Send + Sync + 'static,
+    {
+        self.filter.on_change(Arc::new(f));
+    }
+
+    /// Replaces the predicates added with [`Builder::filter_fn`] with `f`.
+    ///
+    /// [`Builder::filter_fn`]: struct.Builder.html#method.filter_fn
+    pub fn set_filter_fn<F>(&self, f: F)
+    where
+        F: Fn(&Metadata) -> bool + Send + Sync + 'static,
+    {
+        self.filter.set_predicates(vec![Arc::new(f)]);
+    }
+
+    /// Removes the predicates added with [`Builder::filter_fn`].
+    ///
+    /// [`Builder::filter_fn`]: struct.Builder.html#method.filter_fn
+    pub fn clear_filter_fns(&self) {
+        self.filter.set_predicates(Vec::new());
+    }
+
+    /// Turns logging off or back on, leaving the filter untouched.
+    ///
+    /// This is cheaper than updating the filter to `off` and back, and
+    /// re-enabling restores the previous behavior right away. While disabled,
+    /// the records matching the filter are counted in
+    /// [`LoggerStats::disabled_suppressed`].
+    ///
+    /// Logging can't be turned back on after a [`shutdown`].
+    ///
+    /// [`LoggerStats::disabled_suppressed`]: struct.LoggerStats.html#structfield.disabled_suppressed
+    /// [`shutdown`]: #method.shutdown
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled
+            .store(enabled && self.is_active(), Ordering::SeqCst);
+        // A concurrent shutdown must not be undone
+        if !self.is_active() {
+            self.enabled.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns `false` if logging was turned off with [`set_enabled`].
+    ///
+    /// [`set_enabled`]: #method.set_enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Tears the logger down for good.
+    ///
+    /// The batched records are written and the targets flushed, the
+    /// background writer thread is stopped, and the records kept by the
+    /// [flight recorder] are released. Logging is then turned off like with
+    /// [`set_enabled`], except it can't be turned back on: the records are
+    /// counted in [`LoggerStats::disabled_suppressed`] and dropped. Logging
+    /// after the shutdown is safe, from any clone of the logger.
+    ///
+    /// The `log` crate can't remove the global logger, so a logger installed
+    /// with [`init`] stays registered, and [`is_initialized`] still returns
+    /// `true`. Its `log::max_level` is turned off so the `log!` macros skip the
+    /// records right away, until another logger is installed with [`init`],
+    /// replacing it. The configs of a replaced logger don't change the max
+    /// level, so they can be shut down anytime.
+    ///
+    /// Shutting down again only flushes the targets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::log::{self, LevelFilter};
+    ///
+    /// let config = ic_log::init();
+    /// config.shutdown().unwrap();
+    ///
+    /// assert!(!config.is_active());
+    /// assert_eq!(LevelFilter::Off, log::max_level());
+    /// log::error!("silently dropped");
+    /// ```
+    ///
+    /// [flight recorder]: struct.Builder.html#method.flight_recorder
+    /// [`set_enabled`]: #method.set_enabled
+    /// [`LoggerStats::disabled_suppressed`]: struct.LoggerStats.html#structfield.disabled_suppressed
+    /// [`init`]: fn.init.html
+    /// [`is_initialized`]: fn.is_initialized.html
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.active.store(false, Ordering::SeqCst);
+        self.enabled.store(false, Ordering::SeqCst);
+        self.filter.release();
+        if let Some(recorder) = &self.recorder {
+            drop(recorder.take());
+        }
+
+        #[cfg(feature = "async-writer")]
+        return self.writer.shutdown();
+        #[cfg(not(feature = "async-writer"))]
+        self.writer.flush()
+    }
+
+    /// Returns `false` once the logger was shut down with [`shutdown`].
+    ///
+    /// [`shutdown`]: #method.shutdown
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Starts a new call for the [`Builder::max_bytes_per_call`] budget.
+    ///
+    /// The records suppressed during the previous call are reported ahead of
+    /// the next record.
+    ///
+    /// [`Builder::max_bytes_per_call`]: struct.Builder.html#method.max_bytes_per_call
+    pub fn reset_call_budget(&self) {
+        if let Some(budget) = &self.budget {
+            budget.reset();
+        }
+    }
+
+    /// Returns a snapshot of the logger statistics.
+    ///
+    /// The counters are read without locking, so the snapshot is not a
+    /// transaction, see [`LoggerStats`].
+    ///
+    /// [`LoggerStats`]: struct.LoggerStats.html
+    pub fn stats(&self) -> LoggerStats {
+        let stats = self.counters.snapshot();
+        LoggerStats {
+            target_panics: self.writer.panics(),
+            overflow_dropped: self.writer.dropped(),
+            write_errors: stats.write_errors + self.writer.errors(),
+            ..stats
+        }
+    }
+
+    /// Returns a snapshot of the statistics of every target, keyed by a label
+    /// made of the kind of the target and its id, like `stdout#0` or `pipe#2`.
+    ///
+    /// With the `serde` feature the map can be returned from a canister query.
+    pub fn writer_stats(&self) -> BTreeMap<String, TargetStats> {
+        self.writer.target_stats().into_iter().collect()
+    }
+
+    /// Limits the records of the modules starting with `module_prefix` to
+    /// `max_records` per `period_ns` nanoseconds, replacing the previous limit
+    /// of the prefix if any.
+    ///
+    /// See [`Builder::rate_limit`].
+    ///
+    /// [`Builder::rate_limit`]: struct.Builder.html#method.rate_limit
+    pub fn set_rate_limit(&self, module_prefix: &str, max_records: u32, period_ns: u64) {
+        self.rate_limiter.set(module_prefix, max_records, period_ns);
+    }
+
+    /// Removes the rate limit of `module_prefix`, returning `false` if there
+    /// was none.
+    pub fn remove_rate_limit(&self, module_prefix: &str) -> bool {
+        self.rate_limiter.remove(module_prefix)
+    }
+
+    /// Returns the number of records suppressed by the rate limit of every
+    /// module prefix.
+    pub fn rate_limited(&self) -> BTreeMap<String, u64> {
+        self.rate_limiter.suppressed()
+    }
+
+    /// Keeps only one in `one_in` records of `level`, restarting its count.
+    ///
+    /// See [`Builder::sample`].
+    ///
+    /// [`Builder::sample`]: struct.Builder.html#method.sample
+    pub fn set_sampling(&self, level: Level, one_in: u32) {
+        self.sampler.set(level, one_in);
+    }
+
+    /// Replaces the format of the records.
+    ///
+    /// The records being logged keep the format they started with. The
+    /// formats of the targets added with [`Builder::add_target_with_format`]
+    /// are not replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let config = ic_log::init();
+    ///
+    /// config.set_format(|buf, record| writeln!(buf, "[{}] {}", record.level(), record.args()));
+    /// log::info!("in the new format");
+    /// ```
+    ///
+    /// [`Builder::add_target_with_format`]: struct.Builder.html#method.add_target_with_format
+    pub fn set_format<F>(&self, format: F)
+    where
+        F: Fn(&mut Formatter, &Record) -> io::Result<()> + Sync + Send + 'static,
+    {
+        let options = self.format.load().options.clone();
+        self.format.store(Arc::new(BuiltFormat {
+            format: Arc::new(format),
+            kind: "custom",
+            options,
+        }));
+    }
+
+    /// Replaces the format of the records with a built-in format.
+    ///
+    /// See [`set_format`].
+    ///
+    /// [`set_format`]: #method.set_format
+    pub fn set_builtin_format(&self, kind: FormatKind) {
+        self.format.store(Arc::new(kind.build()));
+    }
+
+    /// Replaces the time source of the records, `None` restoring the time of
+    /// the platform.
+    ///
+    /// See [`Builder::time_source`].
+    ///
+    /// [`Builder::time_source`]: struct.Builder.html#method.time_source
+    pub fn set_time_source(&self, time_source: Option<fn() -> u64>) {
+        self.clock.set_time_source(time_source);
+    }
+
+    /// Returns a summary of the configuration of the logger.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config = ic_log::init();
+    ///
+    /// let info = config.info();
+    /// assert_eq!(vec!["stdout"], info.targets);
+    /// assert_eq!("default", info.format.kind);
+    /// ```
+    pub fn info(&self) -> LoggerInfo {
+        let enabled = self.enabled.load(Ordering::Relaxed);
+        info::collect(&self.filter, &self.writer, &self.format.load(), enabled)
+    }
+
+    /// Returns the capacity of the formatting buffer of the current thread,
+    /// or zero if nothing was logged from it yet.
+    ///
+    /// See [`Builder::max_retained_buffer_bytes`].
+    ///
+    /// [`Builder::max_retained_buffer_bytes`]: struct.Builder.html#method.max_retained_buffer_bytes
+    pub fn retained_buffer_bytes(&self) -> usize {
+        with_formatter(|formatter| match formatter.try_borrow() {
+            Ok(formatter) => formatter.as_ref().map_or(0, Formatter::capacity),
+            Err(_) => 0,
+        })
+        .unwrap_or(0)
+    }
+
+    /// Flushes the logger.
+    ///
+    /// The batched records are written, see [`Builder::batch`], and the pipe
+    /// targets are flushed. Records printed with `ic_cdk::print` are never
+    /// buffered, so there is nothing to do for them.
+    ///
+    /// This is the same as calling `log::logger().flush()` once the logger is
+    /// installed, except the error is returned instead of being reported to
+    /// [`Builder::on_error`].
+    ///
+    /// [`Builder::batch`]: struct.Builder.html#method.batch
+    /// [`Builder::on_error`]: struct.Builder.html#method.on_error
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Returns a guard printing the batched records when dropped.
+    ///
+    /// See [`Builder::batch`].
+    ///
+    /// [`Builder::batch`]: struct.Builder.html#method.batch
+    pub fn flush_on_drop(&self) -> FlushGuard {
+        FlushGuard::new(self.writer.clone())
+    }
+
+    /// Returns a guard stopping the background writer thread when dropped.
+    ///
+    /// See [`Builder::non_blocking`].
+    ///
+    /// [`Builder::non_blocking`]: struct.Builder.html#method.non_blocking
+    #[cfg(feature = "async-writer")]
+    pub fn worker_guard(&self) -> WorkerGuard {
+        WorkerGuard::new(self.writer.clone())
+    }
+
+    /// Returns the number of targets the records are written to.
+    pub fn target_count(&self) -> usize {
+        self.writer.target_count()
+    }
+
+    /// Replaces all the targets of the logger with `target`, returning its id.
+    ///
+    /// The change is atomic: every record is written either to the previous
+    /// targets or to the new one. The previous targets are flushed once they
+    /// are replaced, and the error is returned if they fail to.
+    ///
+    /// Styles are decided when the logger is built, and aren't changed by the
+    /// new targets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let (_logger, config) = Builder::new().build();
+    ///
+    /// config.set_target(Target::Stderr).unwrap();
+    /// ```
+    pub fn set_target(&self, target: Target) -> io::Result<TargetId> {
+        self.writer.set_target(target)
+    }
+
+    /// Adds a target receiving all the records, returning its id.
+    ///
+    /// The records are batched if the logger was built with [`Builder::batch`].
+    ///
+    /// # Examples
+    ///
+    /// Print the records with `ic_cdk::print` while debugging:
+    ///
+    /// ```
+    /// use ic_log::{Builder, Target};
+    ///
+    /// let (_logger, config) = Builder::new().target(Target::custom(|_| {})).build();
+    ///
+    /// let debugging = config.add_target(Target::Stdout);
+    /// // ...
+    /// config.remove_target(debugging).unwrap();
+    /// ```
+    ///
+    /// [`Builder::batch`]: struct.Builder.html#method.batch
+    pub fn add_target(&self, target: Target) -> TargetId {
+        self.writer.add_target(target)
+    }
+
+    /// Removes a target, returning `false` if there was none with this id.
+    ///
+    /// The target is flushed once removed, and the error is returned if it
+    /// fails to.
+    pub fn remove_target(&self, id: TargetId) -> io::Result<bool> {
+        self.writer.remove_target(id)
+    }
+
+    /// Returns the ids of the targets, in the order the records are written
+    /// to them.
+    pub fn target_ids(&self) -> Vec<TargetId> {
+        self.writer.target_ids()
+    }
+}
+
+impl Logger {
+    /// Returns a summary of the configuration of the logger.
+    ///
+    /// See [`LoggerConfig::info`].
+    ///
+    /// [`LoggerConfig::info`]: struct.LoggerConfig.html#method.info
+    pub fn info(&self) -> LoggerInfo {
+        let enabled = self.enabled.load(Ordering::Relaxed);
+        info::collect(&self.filter, &self.writer, &self.format.load(), enabled)
+    }
+
+    /// Returns the maximum `LevelFilter` that this env logger instance is
+    /// configured to output.
+    pub fn filter(&self) -> LevelFilter {
+        self.filter.max_level()
+    }
+
+    /// Checks if this record matches the configured filter.
+    pub fn matches(&self, record: &Record) -> bool {
+        self.matches_directives(record) && self.filter.allows(record.metadata())
+    }
+
+    // Counts the error and reports it to the error function, unless the error
+    // comes from a record logged by the error function itself.
+    fn report_error(&self, error: &io::Error) {
+        thread_local! {
+            static REPORTING: Cell<bool> = const { Cell::new(false) };
+        }
+
+        self.counters.write_errors.fetch_add(1, Ordering::Relaxed);
+        if let Some(on_error) = &self.on_error {
+            let _ = REPORTING.try_with(|reporting| {
+                if !reporting.replace(true) {
+                    on_error(error);
+                    reporting.set(false);
+                }
+            });
+        }
+    }
+
+    // Formats the record, counting the panics of the format. The record is
+    // then replaced with an error record, which is still written.
+    fn format(
+        &self,
+        format: &FormatFn,
+        formatter: &mut Formatter,
+        record: &Record,
+    ) -> io::Result<()> {
+        formatter.format(format, record).unwrap_or_else(|| {
+            self.counters.format_panics.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+
+    // Lets the config change the global max level, which is set right away.
+    pub(crate) fn attach(&self) {
+        self.filter.attach();
+    }
+
+    // Stops the config from changing the global max level.
+    pub(crate) fn detach(&self) {
+        self.filter.detach();
+    }
+
+    // Checks the record against the directives or the caller override only.
+    fn matches_directives(&self, record: &Record) -> bool {
+        match self.filter.caller_level() {
+            Some(level) => record.level() <= level,
+            None => self.filter.load().matches(record),
+        }
+    }
+
+    // Writes the records kept by the flight recorder to the targets.
+    fn dump(&self, recorder: &FlightRecorder) {
+        let mut formatter = Formatter::new(&self.writer);
+        for recorded in recorder.take() {
+            let metadata = Metadata::builder()
+                .level(recorded.level)
+                .target(&recorded.target)
+                .build();
+            let result = formatter.write_all(&recorded.bytes).and_then(|_| {
+                self.counters.records_logged.fetch_add(1, Ordering::Relaxed);
+                formatter.print(&self.writer, Some((&metadata, None)))
+            });
+            if let Err(e) = result {
+                self.report_error(&e);
+            }
+            formatter.clear();
+        }
+    }
+
+    // Writes the record if it is within the budget, reporting the records
+    // suppressed during the previous call first.
+    fn write(&self, record: &Record) {
+        // The records kept by the flight recorder aren't charged
+        let budget = self.budget.as_deref().filter(|_| match &self.recorder {
+            Some(recorder) => !recorder.records(record.level()),
+            None => true,
+        });
+        if let Some(budget) = budget {
+            let admission = budget.begin_record(&self.clock, &self.counters);
+            // The summary of the previous call isn't charged to the new one
+            if let Some(suppressed) = admission.ended {
+                self.write_record(
+                    &Record::builder()
+                        .args(format_args!(
+                            "log output truncated, {} records suppressed",
+                            suppressed
+                        ))
+                        .level(Level::Warn)
+                        .target("ic_log")
+                        .build(),
+                    None,
+                );
+            }
+            if !admission.allowed {
+                return;
+            }
+        }
+        self.write_record(record, budget);
+    }
+
+    // Formats the record and writes it to the targets, charging it to
+    // `budget` if any, or keeps it in the flight recorder.
+    fn write_record(&self, record: &Record, budget: Option<&CallBudget>) {
+        // The same format is used for the whole record, even if it is replaced
+        let format = self.format.load_full();
+        let recorder = self
+            .recorder
+            .as_ref()
+            .filter(|recorder| recorder.records(record.level()));
+
+        // Log records are written to a thread-local buffer before being printed
+        // to the terminal. We clear these buffers afterwards, shrinking them
+        // back to `max_retained_buffer_bytes` after a larger record.
+        //
+        // If multiple `Logger`s are used by the same threads then the thread-local
+        // formatter might have different color support. If this is the case the
+        // formatter and its buffer are discarded and recreated.
+
+        let print = |formatter: &mut Formatter, record: &Record| {
+            formatter.start_record(&self.clock);
+            if let Some(recorder) = recorder {
+                let result = self.format(&format.format, formatter, record).map(|_| {
+                    if let Some(redact) = self.redact {
+                        formatter.redact(redact);
+                    }
+                    recorder.push(Recorded {
+                        level: record.level(),
+                        target: record.target().to_owned(),
+                        bytes: formatter.to_vec(),
+                    });
+                });
+                if let Err(e) = result {
+                    self.report_error(&e);
+                }
+                formatter.clear();
+                return;
+            }
+
+            // With several formats, the outputs of all of them are charged to
+            // the budget at once before any of them is printed
+            let buffered = budget.filter(|_| !self.target_formats.is_empty());
+            let mut pending = Vec::new();
+            let mut logged = false;
+            for (index, format) in self.formats(&format.format, record.level()) {
+                let result = self.format(format, formatter, record).and_then(|_| {
+                    if let Some(redact) = self.redact {
+                        formatter.redact(redact);
+                    }
+
+                    if buffered.is_some() {
+                        pending.push((index, formatter.to_vec()));
+                        return Ok(());
+                    }
+                    if let Some(budget) = budget {
+                        if !budget.consume(formatter.len(), &self.counters) {
+                            return Ok(());
+                        }
+                    }
+                    logged = true;
+                    formatter.print(&self.writer, Some((record.metadata(), index)))
+                });
+                if let Err(e) = result {
+                    self.report_error(&e);
+                }
+
+                // Always clear the buffer afterwards
+                formatter.clear();
+            }
+            if let Some(budget) = buffered {
+                let len = pending.iter().map(|(_, bytes)| bytes.len()).sum();
+                if !pending.is_empty() && budget.consume(len, &self.counters) {
+                    logged = true;
+                    for (index, bytes) in pending {
+                        let result = formatter.write_all(&bytes).and_then(|_| {
+                            formatter.print(&self.writer, Some((record.metadata(), index)))
+                        });
+                        if let Err(e) = result {
+                            self.report_error(&e);
+                        }
+                        formatter.clear();
+                    }
+                }
+            }
+            if logged {
+                self.counters.records_logged.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+
+        let printed = with_formatter(|tl_buf| {
+            match tl_buf.try_borrow_mut() {
+                // There are no active borrows of the buffer
+                Ok(mut tl_buf) => match *tl_buf {
+                    // We have a previously set formatter
+                    Some(ref mut formatter)
+                        if formatter.is_colored() == self.writer.is_colored() =>
+                    {
+                        print(formatter, record);
+                    }
+                    // The previous formatter has a different color support
+                    Some(_) => {
+                        let mut formatter = Formatter::new(&self.writer);
+                        print(&mut formatter, record);
+
+                        *tl_buf = Some(formatter);
+                    }
+                    // We don't have a previously set formatter
+                    None => {
+                        let mut formatter = Formatter::new(&self.writer);
+                        print(&mut formatter, record);
+
+                        *tl_buf = Some(formatter);
+                    }
+                },
+                // There's already an active borrow of the buffer (due to re-entrancy)
+                Err(_) => {
+                    print(&mut Formatter::new(&self.writer), record);
+                }
+            }
+        })
+        .is_some();
+
+        if !printed {
+            // The thread-local storage was not available (because its
+            // destructor has already run). Create a new single-use
+            // Formatter on the stack for this call.
+            print(&mut Formatter::new(&self.writer), record);
+        }
+    }
+
+    // The formats of the targets records of `level` are routed to, with their
+    // index in `target_formats`, or `None` for the format of the logger.
+    fn formats<'a>(
+        &'a self,
+        format: &'a FormatFn,
+        level: Level,
+    ) -> impl Iterator<Item = (Option<usize>, &'a FormatFn)> {
+        let target_formats = self.target_formats.iter().enumerate();
+        std::iter::once((None, format))
+            .chain(target_formats.map(|(index, format)| (Some(index), format)))
+            .filter(move |(index, _)| self.writer.accepts_format(level, *index))
+    }
+}
+
+impl Logger {
+    // Checks the metadata against this logger, ignoring the scoped logger.
+    pub(crate) fn accepts(&self, metadata: &Metadata) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) || self.filter.is_off() {
+            return false;
+        }
+
+        let enabled = match self.filter.caller_level() {
+            Some(level) => metadata.level() <= level,
+            None => self.filter.load().enabled(metadata),
+        };
+        enabled && self.filter.allows(metadata)
+    }
+
+    // Logs the record with this logger, ignoring the scoped logger.
+    pub(crate) fn log_record(&self, record: &Record) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            if self.matches(record) {
+                self.counters
+                    .disabled_suppressed
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+
+        if self.temporary.is_due() {
+            self.filter.update(|state| {
+                if let Some(previous) = self.temporary.expired() {
+                    state.store(previous);
+                }
+            });
+        }
+
+        if self.filter.is_off() {
+            return;
+        }
+
+        if self.matches_directives(record) {
+            if !self.filter.allows(record.metadata()) {
+                self.counters
+                    .filter_fn_suppressed
+                    .fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            // Skip formatting records no target is routed for
+            if !self.writer.accepts(record.level()) {
+                return;
+            }
+
+            if self.skip_empty_messages && is_blank(record.args()) {
+                self.counters.empty_skipped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            if !self.sampler.keep(record.level()) {
+                self.counters.sampled_out.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            #[cfg(all(target_arch = "wasm32", feature = "ic"))]
+            if let Some(cycles) = &self.cycles {
+                let balance = context::cycles_balance();
+                let admission = cycles.admit(record.level(), balance);
+                if let Some(threshold) = admission.engaged {
+                    self.write(
+                        &Record::builder()
+                            .args(format_args!(
+                                "the cycles balance {} is under {}, records are throttled",
+                                balance, threshold
+                            ))
+                            .level(Level::Warn)
+                            .target("ic_log")
+                            .build(),
+                    );
+                }
+                if !admission.allowed {
+                    self.counters
+                        .cycles_throttled
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+
+            let admission = self.rate_limiter.admit(record.target());
+            if let Some((prefix, suppressed)) = admission.ended {
+                self.write(
+                    &Record::builder()
+                        .args(format_args!(
+                            "{} records of `{}` suppressed by the rate limit",
+                            suppressed, prefix
+                        ))
+                        .level(Level::Warn)
+                        .target(&prefix)
+                        .build(),
+                );
+            }
+            if !admission.allowed {
+                self.counters.rate_limited.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            if let Some(dedup) = &self.dedup {
+                let verdict = dedup.check(record);
+                if let Some(repeated) = verdict.repeated {
+                    self.write(
+                        &Record::builder()
+                            .args(format_args!(
+                                "last message repeated {} times",
+                                repeated.count
+                            ))
+                            .level(repeated.level)
+                            .target(&repeated.target)
+                            .build(),
+                    );
+                }
+                if verdict.duplicate {
+                    self.counters
+                        .duplicates_suppressed
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+
+            if let Some(recorder) = &self.recorder {
+                if recorder.triggers(record.level()) {
+                    self.dump(recorder);
+                }
+            }
+
+            self.write(record);
+        }
+    }
+}
+
+// Whether the message is empty or only made of whitespace, rendering it
+// until a visible character is found.
+fn is_blank(args: &std::fmt::Arguments) -> bool {
+    struct Blank;
+
+    impl std::fmt::Write for Blank {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            if s.trim().is_empty() {
+                Ok(())
+            } else {
+                Err(std::fmt::Error)
+            }
+        }
+    }
+
+    match args.as_str() {
+        Some(message) => message.trim().is_empty(),
+        None => std::fmt::write(&mut Blank, *args).is_ok(),
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match scoped::current() {
+            Some(scoped) => scoped.accepts(metadata),
+            None => self.accepts(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        match scoped::current() {
+            Some(scoped) => scoped.log_record(record),
+            None => self.log_record(record),
+        }
+    }
+
+    fn flush(&self) {
+        if let Err(e) = self.writer.flush() {
+            self.report_error(&e);
+        }
+    }
+}
+
+/// Attempts to initialize the global logger, logging records at `info` and
+/// above with the default format.
+///
+/// The records are printed with `ic_cdk::print` on wasm32 and to `stdout`
+/// elsewhere. This is the same as `Builder::new().filter_level(LevelFilter::Info).try_init()`.
+///
+/// # Examples
+///
+/// ```
+/// let config = ic_log::try_init().unwrap();
+///
+/// log::info!("canister initialized");
+/// config.update_filters("debug").unwrap();
+/// ```
+///
+/// # Errors
+///
+/// This function will fail if another library has already initialized a
+/// global logger. Calling it again replaces the logger installed before.
+pub fn try_init() -> Result<LoggerConfig, Error> {
+    Builder::new().filter_level(LevelFilter::Info).try_init()
+}
+
+/// Initializes the global logger, logging records at `info` and above with
+/// the default format.
+///
+/// See [`try_init`].
+///
+/// # Examples
+///
+/// ```
+/// let config = ic_log::init();
+///
+/// log::info!("canister initialized");
+/// ```
+///
+/// # Panics
+///
+/// This function will panic if another library has already initialized a
+/// global logger.
+///
+/// [`try_init`]: fn.try_init.html
+pub fn init() -> LoggerConfig {
+    try_init().expect("ic_log::init should not be called after another logger was installed")
+}
+
+/// Initializes the global logger, logging records at `level` and above with
+/// the default format.
+///
+/// See [`try_init`].
+///
+/// # Examples
+///
+/// ```
+/// use log::LevelFilter;
+///
+/// let config = ic_log::init_with_level(LevelFilter::Debug);
+///
+/// log::debug!("canister initialized");
+/// assert_eq!(LevelFilter::Debug, config.max_level());
+/// ```
+///
+/// # Panics
+///
+/// This function will panic if another library has already initialized a
+/// global logger.
+///
+/// [`try_init`]: fn.try_init.html
+pub fn init_with_level(level: LevelFilter) -> LoggerConfig {
+    Builder::new()
+        .filter_level(level)
+        .try_init()
+        .expect("ic_log::init_with_level should not be called after another logger was installed")
+}
+
+mod std_fmt_impls {
+    use super::*;
+    use std::fmt;
+
+    impl fmt::Debug for Logger {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("Logger")
+                .field("filter", &*self.filter.load())
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for LoggerConfig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("LoggerConfig")
+                .field("filter", &self.current_filter_string())
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for Builder {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            if self.built {
+                f.debug_struct("Logger").field("built", &true).finish()
+            } else {
+                f.debug_struct("Logger")
+                    .field("filter", &self.filter)
+                    .field("writer", &self.writer)
+                    .finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Write;
+
+    use log::*;
+
+    use super::*;
+
+    /// A pipe appending to a shared buffer.
+    struct Shared(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn update_filter_at_runtime() {
+        let config = Builder::default()
+            .filter_level(LevelFilter::Debug)
+            .is_test(true)
+            .try_init()
+            .unwrap();
+
+        debug!("This one should be printed");
+        info!("This one should be printed");
+
+        config.update_filters("error").unwrap();
+
+        debug!("This one should NOT be printed");
+        info!("This one should NOT be printed");
+
+        config.update_filters("info").unwrap();
+
+        debug!("This one should NOT be printed");
+        info!("This one should be printed");
+    }
+
+    #[test]
+    fn an_off_filter_rejects_all_the_records() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .parse_filters("off")
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let metadata = Metadata::builder().level(Level::Error).build();
+        let log = |message| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Error)
+                    .build(),
+            )
+        };
+
+        assert!(!logger.enabled(&metadata));
+        log("dropped");
+        config.update_filters("debug").unwrap();
+        assert!(logger.enabled(&metadata));
+        log("kept");
+        config.update_filters("off").unwrap();
+        assert!(!logger.enabled(&metadata));
+        log("dropped");
+
+        assert_eq!(
+            "kept\n",
+            String::from_utf8(file.lock().unwrap().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn update_filters_keeps_previous_filter_on_invalid_directive() {
+        let (logger, config) = Builder::default().parse_filters("info").build();
+
+        let err = match config.update_filters("trace,banana") {
+            Err(Error::InvalidFilter(err)) => err,
+            result => panic!("unexpected {:?}", result),
+        };
+
+        assert_eq!("banana", err.invalid_directives()[0].directive());
+        assert_eq!(LevelFilter::Info, logger.filter());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn update_filters_keeps_previous_filter_on_invalid_regex() {
+        let (logger, config) = Builder::default().parse_filters("info/^kept$").build();
+
+        assert!(config.update_filters("trace/(unclosed").is_err());
+
+        let record = |level, args| Record::builder().args(args).level(level).build();
+        assert_eq!(LevelFilter::Info, logger.filter());
+        assert!(logger.matches(&record(Level::Info, format_args!("kept"))));
+        assert!(!logger.matches(&record(Level::Info, format_args!("not kept"))));
+        assert!(!logger.matches(&record(Level::Trace, format_args!("kept"))));
+    }
+
+    #[test]
+    fn current_filter_string_round_trips() {
+        let (logger, config) = Builder::default().build();
+        assert_eq!("error", config.current_filter_string());
+
+        for spec in [
+            "warn,my_canister::ledger=debug",
+            "my_canister=INFO,  off ,a::b::c=trace,a=warn/needle",
+            "crate1=,crate2=trace",
+            "debug,-candid,candid::de=warn",
+            "info,target:audit=debug,audit=warn",
+            "only:warn,crate1=debug..=info",
+        ] {
+            config
+                .update_filters(spec)
+                .unwrap_or_else(|e| panic!("{}: {}", spec, e));
+            let canonical = config.current_filter_string();
+
+            let (other, other_config) = Builder::default().build();
+            other_config.update_filters(&canonical).unwrap();
+            assert_eq!(canonical, other_config.current_filter_string());
+            assert_eq!(logger.filter(), other.filter());
+            assert_eq!(config.max_level(), other_config.max_level());
+        }
+
+        config
+            .update_filters("my_canister=INFO,  off ,a::b::c=trace,a=warn/needle")
+            .unwrap();
+        assert_eq!(
+            "off,a=warn,a::b::c=trace,my_canister=info/needle",
+            config.current_filter_string()
+        );
+        assert_eq!(LevelFilter::Trace, config.max_level());
+    }
+
+    #[test]
+    fn modify_filter_interleaved_with_update_filters() {
+        let (logger, config) = Builder::default().parse_filters("info").build();
+
+        config.set_module_level("crate1", LevelFilter::Trace);
+        config.set_module_level("crate2", LevelFilter::Warn);
+        assert_eq!(
+            "info,crate1=trace,crate2=warn",
+            config.current_filter_string()
+        );
+        assert_eq!(LevelFilter::Trace, logger.filter());
+
+        config.update_filters("error,crate3=debug/needle").unwrap();
+        config.set_default_level(LevelFilter::Warn);
+        config.set_module_level("crate3", LevelFilter::Info);
+        assert_eq!("warn,crate3=info/needle", config.current_filter_string());
+
+        config.remove_module("crate3");
+        config.remove_module("missing");
+        assert_eq!("warn/needle", config.current_filter_string());
+        assert_eq!(LevelFilter::Warn, logger.filter());
+
+        config.update_filters("crate1=debug").unwrap();
+        config.set_default_level(LevelFilter::Off);
+        assert_eq!("off,crate1=debug", config.current_filter_string());
+
+        config.update_filters("debug,-crate1").unwrap();
+        config.set_module_level("crate1::mod1", LevelFilter::Info);
+        assert_eq!(
+            "debug,-crate1,crate1::mod1=info",
+            config.current_filter_string()
+        );
+        config.remove_module("crate1");
+        assert_eq!("debug,crate1::mod1=info", config.current_filter_string());
+    }
+
+    #[test]
+    #[cfg(feature = "ic")]
+    fn caller_levels_raise_max_level_only() {
+        let (logger, config) = Builder::default().parse_filters("warn").build();
+        let user = Principal::from_slice(&[1]);
+
+        config.set_caller_level(user, LevelFilter::Trace);
+        assert_eq!(LevelFilter::Trace, config.max_level());
+        assert_eq!(LevelFilter::Trace, logger.filter());
+        assert_eq!("warn", config.current_filter_string());
+
+        // no caller is recorded on native targets, so the filter applies
+        let record = Record::builder()
+            .args(format_args!("m"))
+            .level(Level::Debug)
+            .build();
+        assert!(!logger.matches(&record));
+
+        config.remove_caller_level(&user);
+        assert_eq!(LevelFilter::Warn, config.max_level());
+    }
+
+    #[test]
+    fn a_clock_jump_of_the_time_source_expires_the_temporary_filter() {
+        fn start() -> u64 {
+            1_000
+        }
+        fn later() -> u64 {
+            1_000_000
+        }
+
+        let (logger, config) = Builder::default()
+            .parse_filters("warn")
+            .time_source(start)
+            .build();
+        config.update_filters_for("debug", 500).unwrap();
+
+        config.set_time_source(Some(later));
+        logger.log(
+            &Record::builder()
+                .args(format_args!("m"))
+                .level(Level::Warn)
+                .build(),
+        );
+        assert_eq!("warn", config.current_filter_string());
+    }
+
+    #[test]
+    fn temporary_filter_expires_on_next_record() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static NOW: AtomicU64 = AtomicU64::new(1_000);
+        fn clock() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+
+        let (logger, config) = Builder::default()
+            .parse_filters("warn")
+            .time_source(clock)
+            .build();
+        let record = Record::builder()
+            .args(format_args!("m"))
+            .level(Level::Warn)
+            .build();
+
+        let result = config.update_filters_for("nope", 500);
+        assert!(matches!(result, Err(Error::InvalidFilter(_))));
+        config.update_filters_for("trace", 500).unwrap();
+        config.update_filters_for("debug", 500).unwrap();
+        assert_eq!("debug", config.current_filter_string());
+
+        NOW.store(1_499, Ordering::SeqCst);
+        logger.log(&record);
+        assert_eq!("debug", config.current_filter_string());
+
+        NOW.store(1_500, Ordering::SeqCst);
+        logger.log(&record);
+        assert_eq!("warn", config.current_filter_string());
+        assert_eq!(LevelFilter::Warn, config.max_level());
+
+        // cancelled explicitly
+        config.update_filters_for("trace", 500).unwrap();
+        assert!(config.cancel_override());
+        assert!(!config.cancel_override());
+        assert_eq!("warn", config.current_filter_string());
+
+        // replaced by a permanent filter
+        config.update_filters_for("trace", 500).unwrap();
+        config.update_filters("info").unwrap();
+        NOW.store(5_000, Ordering::SeqCst);
+        logger.log(&record);
+        assert_eq!("info", config.current_filter_string());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let (logger, config) = Builder::default().parse_filters("warn,crate1=info").build();
+
+        let snapshot = config.snapshot();
+        config.update_filters("trace").unwrap();
+        config.set_module_level("crate2", LevelFilter::Debug);
+        assert_eq!(LevelFilter::Trace, logger.filter());
+
+        config.restore(snapshot.clone());
+        assert_eq!("warn,crate1=info", config.current_filter_string());
+        assert_eq!(LevelFilter::Info, config.max_level());
+        assert_eq!(snapshot.max_level(), config.max_level());
+
+        let rebuilt = FilterSnapshot::from_directives(&snapshot.directives()).unwrap();
+        config.update_filters("off").unwrap();
+        config.restore(rebuilt);
+        assert_eq!("warn,crate1=info", config.current_filter_string());
+    }
+
+    #[test]
+    fn set_enabled_suppresses_and_counts_records() {
+        let (logger, config) = Builder::default().parse_filters("info").build();
+        let record = |level| {
+            Record::builder()
+                .args(format_args!("m"))
+                .level(level)
+                .build()
+        };
+
+        config.set_enabled(false);
+        assert!(!config.is_enabled());
+        assert!(!logger.enabled(record(Level::Error).metadata()));
+        logger.log(&record(Level::Info));
+        logger.log(&record(Level::Error));
+        logger.log(&record(Level::Debug));
+        assert_eq!(2, config.stats().disabled_suppressed);
+        assert_eq!("info", config.current_filter_string());
+
+        config.set_enabled(true);
+        assert!(logger.enabled(record(Level::Info).metadata()));
+        logger.log(&record(Level::Info));
+        assert_eq!(2, config.stats().disabled_suppressed);
+    }
+
+    #[test]
+    fn shutdown_flushes_and_drops_the_later_records() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .flight_recorder(Level::Info, 10, 1024)
+            .batch(1024)
+            .build();
+        let log = |level, message| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .build(),
+            )
+        };
+
+        log(Level::Info, "batched");
+        log(Level::Debug, "recorded");
+        assert!(config.is_active());
+        config.shutdown().unwrap();
+        assert_eq!("batched\n", String::from_utf8_lossy(&file.lock().unwrap()));
+        assert!(config.recorder.as_ref().unwrap().take().is_empty());
+
+        config.clone().set_enabled(true);
+        assert!(!config.is_active());
+        assert!(!config.is_enabled());
+        log(Level::Error, "dropped");
+        log(Level::Trace, "filtered");
+        logger.flush();
+        config.shutdown().unwrap();
+        assert_eq!("batched\n", String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(1, config.stats().disabled_suppressed);
+        assert_eq!(1, config.stats().records_logged);
+    }
+
+    #[test]
+    fn effective_level_agrees_with_enabled() {
+        let (logger, config) = Builder::default()
+            .parse_filters(
+                "info,crate1=debug,crate1::mod1=off,svc_*=trace,-svc_tmp,target:audit=warn",
+            )
+            .build();
+
+        let targets = [
+            "other",
+            "crate1",
+            "crate1::mod2",
+            "crate1::mod1",
+            "crate1::mod1::sub",
+            "svc_users",
+            "svc_tmp",
+            "audit",
+            "audit::sub",
+        ];
+        let levels = [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ];
+        for target in targets {
+            let effective = config.effective_level(target);
+            for level in levels {
+                let metadata = Metadata::builder().level(level).target(target).build();
+                assert_eq!(
+                    level <= effective,
+                    logger.enabled(&metadata),
+                    "{} at {}",
+                    target,
+                    level
+                );
+            }
+        }
+        assert_eq!(LevelFilter::Off, config.effective_level("crate1::mod1"));
+        assert_eq!(LevelFilter::Warn, config.effective_level("audit"));
+        assert_eq!(LevelFilter::Info, config.effective_level("audit::sub"));
+    }
+
+    #[test]
+    fn filter_change_callbacks() {
+        use std::sync::Mutex;
+
+        let (_, config) = Builder::default().parse_filters("warn").build();
+        let config = Arc::new(config);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+
+        config.on_filter_change(|_, _| panic!("contained"));
+        {
+            let changes = changes.clone();
+            config.on_filter_change(move |directives, level| {
+                changes
+                    .lock()
+                    .unwrap()
+                    .push((directives.to_string(), level));
+            });
+        }
+        {
+            // updating from a callback must not deadlock
+            let config_ref = Arc::downgrade(&config);
+            config.on_filter_change(move |directives, _| {
+                if directives == "error" {
+                    if let Some(config) = config_ref.upgrade() {
+                        config.update_filters("info").unwrap();
+                    }
+                }
+            });
+        }
+
+        assert!(config.update_filters("infoo").is_err());
+        config.update_filters("debug").unwrap();
+        config.set_module_level("crate1", LevelFilter::Trace);
+        config.update_filters("error").unwrap();
+
+        assert_eq!(
+            vec![
+                ("debug".to_string(), LevelFilter::Debug),
+                ("debug,crate1=trace".to_string(), LevelFilter::Trace),
+                ("error".to_string(), LevelFilter::Error),
+                ("info".to_string(), LevelFilter::Info),
+            ],
+            *changes.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_env_overrides_earlier_filters() {
+        std::env::set_var("IC_LOG_TEST_PARSE_ENV", "crate1=trace,warn");
+        std::env::set_var("IC_LOG_TEST_EMPTY_ENV", " ");
+
+        let (_, config) = Builder::new()
+            .filter_level(LevelFilter::Info)
+            .filter_module("crate1", LevelFilter::Error)
+            .filter_module("crate2", LevelFilter::Debug)
+            .parse_env("IC_LOG_TEST_PARSE_ENV")
+            .build();
+        assert_eq!(
+            "warn,crate1=trace,crate2=debug",
+            config.current_filter_string()
+        );
+
+        let (_, config) = Builder::new()
+            .filter_level(LevelFilter::Info)
+            .parse_env("IC_LOG_TEST_EMPTY_ENV")
+            .parse_env("IC_LOG_TEST_UNSET_ENV")
+            .build();
+        assert_eq!("info", config.current_filter_string());
+
+        let (_, config) = Builder::from_env("IC_LOG_TEST_PARSE_ENV").build();
+        assert_eq!("warn,crate1=trace", config.current_filter_string());
+    }
+
+    #[test]
+    fn target_selects_the_output_stream() {
+        // The records are written by a child test process, so its real streams
+        // can be captured instead of the ones of the test harness.
+        if let Some(target) = std::env::var_os("IC_LOG_TEST_TARGET") {
+            let target = match target.to_str() {
+                Some("stderr") => Target::Stderr,
+                Some("void") => Target::Void,
+                _ => Target::Stdout,
+            };
+            let (logger, config) = Builder::new()
+                .target(target)
+                .format(|buf, record| writeln!(buf, "<{}>", record.args()))
+                .build();
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("to target"))
+                    .level(Level::Error)
+                    .build(),
+            );
+            assert_eq!(1, config.stats().records_logged);
+            return;
+        }
+
+        let cases = [
+            ("stdout", true, false),
+            ("stderr", false, true),
+            ("void", false, false),
+        ];
+        for (target, expected_stdout, expected_stderr) in cases {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "tests::target_selects_the_output_stream",
+                    "--exact",
+                    "--nocapture",
+                ])
+                .env("IC_LOG_TEST_TARGET", target)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert_eq!(
+                expected_stdout,
+                stdout.contains("<to target>"),
+                "{}",
+                target
+            );
+            assert_eq!(
+                expected_stderr,
+                stderr.contains("<to target>"),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn is_test_output_is_captured() {
+        // The records are written by a child test process, so its output can be
+        // checked after the test harness captured it or not.
+        if let Some(is_test) = std::env::var_os("IC_LOG_TEST_IS_TEST") {
+            let (logger, _) = Builder::new()
+                .is_test(is_test == "true")
+                .format(|buf, record| writeln!(buf, "<{}>", record.args()))
+                .build();
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("maybe captured"))
+                    .level(Level::Error)
+                    .build(),
+            );
+            return;
+        }
+
+        for (is_test, expected_shown) in [("true", false), ("false", true)] {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["tests::is_test_output_is_captured", "--exact"])
+                .env("IC_LOG_TEST_IS_TEST", is_test)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert_eq!(
+                expected_shown,
+                stdout.contains("<maybe captured>"),
+                "{}",
+                is_test
+            );
+        }
+    }
+
+    #[test]
+    fn pipe_target_receives_whole_records() {
+        #[derive(Clone, Default)]
+        struct Pipe(Arc<std::sync::Mutex<Vec<Vec<u8>>>>);
+
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().push(buf.to_vec());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let pipe = Pipe::default();
+        let (logger, _) = Builder::new()
+            .target(Target::Pipe(Box::new(pipe.clone())))
+            .format(|buf, record| {
+                write!(buf, "{}", record.level())?;
+                writeln!(buf, " {}", record.args())
+            })
+            .build();
+
+        for args in [format_args!("first"), format_args!("second")] {
+            logger.log(&Record::builder().args(args).level(Level::Error).build());
+        }
+
+        let writes = pipe.0.lock().unwrap();
+        assert_eq!(
+            vec![b"ERROR first\n".to_vec(), b"ERROR second\n".to_vec()],
+            *writes
+        );
+    }
+
+    #[test]
+    fn records_fan_out_to_all_targets() {
+        struct Failing;
+
+        impl Write for Failing {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "failing target"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let first = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let second = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .add_target(Target::Pipe(Box::new(Shared(first.clone()))))
+            .add_target(Target::Pipe(Box::new(Failing)))
+            .add_target(Target::Pipe(Box::new(Shared(second.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        assert_eq!(3, config.target_count());
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("fan out"))
+                .level(Level::Error)
+                .build(),
+        );
+
+        assert_eq!(b"fan out\n".to_vec(), *first.lock().unwrap());
+        assert_eq!(*first.lock().unwrap(), *second.lock().unwrap());
+
+        let (_, config) = Builder::new()
+            .add_target(Target::Stderr)
+            .target(Target::Stdout)
+            .build();
+        assert_eq!(1, config.target_count());
+        assert_eq!(1, Builder::new().build().1.target_count());
+    }
+
+    #[test]
+    fn records_are_routed_by_level() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let others = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let all = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, _) = Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .route(
+                ..=Level::Warn,
+                Target::Pipe(Box::new(Shared(errors.clone()))),
+            )
+            .route(
+                Level::Info..=Level::Debug,
+                Target::Pipe(Box::new(Shared(others.clone()))),
+            )
+            .add_target(Target::Pipe(Box::new(Shared(all.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.level()))
+            .build();
+
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!(""))
+                    .level(level)
+                    .build(),
+            );
+        }
+
+        let text = |buf: &Arc<std::sync::Mutex<Vec<u8>>>| {
+            String::from_utf8(buf.lock().unwrap().clone()).unwrap()
+        };
+        assert_eq!("ERROR\nWARN\n", text(&errors));
+        assert_eq!("INFO\nDEBUG\n", text(&others));
+        assert_eq!("ERROR\nWARN\nINFO\nDEBUG\nTRACE\n", text(&all));
+    }
+
+    #[test]
+    fn custom_target_receives_records_and_panics_are_counted() {
+        let records = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = records.clone();
+        let (logger, config) = Builder::new()
+            .add_target(Target::custom(|bytes| {
+                if bytes.starts_with(b"panic") {
+                    panic!("custom target");
+                }
+            }))
+            .add_target(Target::custom(move |bytes| {
+                sink.lock().unwrap().push(bytes.to_vec())
+            }))
+            .format(|buf, record| {
+                write!(buf, "{}", record.args())?;
+                writeln!(buf, " {}", record.level())
+            })
+            .build();
+
+        for args in [
+            format_args!("first"),
+            format_args!("panic"),
+            format_args!("last"),
+        ] {
+            logger.log(&Record::builder().args(args).level(Level::Error).build());
+        }
+
+        let expected = vec![
+            b"first ERROR\n".to_vec(),
+            b"panic ERROR\n".to_vec(),
+            b"last ERROR\n".to_vec(),
+        ];
+        assert_eq!(expected, *records.lock().unwrap());
+        assert_eq!(1, config.stats().target_panics);
+    }
+
+    #[test]
+    fn format_panics_are_contained_and_counted() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .format(|buf, record| {
+                let message = record.args().to_string();
+                write!(buf, "partial ")?;
+                let amount: u64 = message.parse().unwrap();
+                writeln!(buf, "{}", amount)
+            })
+            .build();
+
+        for args in [
+            format_args!("1"),
+            format_args!("malformed"),
+            format_args!("3"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(args)
+                    .level(Level::Error)
+                    .target("t")
+                    .build(),
+            );
         }
+
+        let expected = "partial 1\n[FORMAT FAILED] ERROR t\npartial 3\n";
+        assert_eq!(expected, String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(1, config.stats().format_panics);
+        assert_eq!(3, config.stats().records_logged);
     }
 
-    fn flush(&self) {}
-}
+    #[test]
+    fn batched_records_are_printed_together() {
+        #[derive(Clone, Default)]
+        struct Prints(Arc<std::sync::Mutex<Vec<String>>>);
 
+        impl Write for Prints {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(buf.to_vec()).unwrap());
+                Ok(buf.len())
+            }
 
-mod std_fmt_impls {
-    use super::*;
-    use std::fmt;
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
 
-    impl fmt::Debug for Logger {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            f.debug_struct("Logger")
-                .field("filter", &self.filter)
-                .finish()
+        let prints = Prints::default();
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(prints.clone())))
+            .batch(16)
+            .overflow_policy(OverflowPolicy::Block)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let log = |args| logger.log(&Record::builder().args(args).level(Level::Error).build());
+
+        log(format_args!("first"));
+        log(format_args!("second"));
+        assert!(prints.0.lock().unwrap().is_empty());
+
+        // doesn't fit with the pending records
+        log(format_args!("third"));
+        assert_eq!(vec!["first\nsecond\n"], *prints.0.lock().unwrap());
+
+        // larger than the batch
+        log(format_args!("a longer fourth record"));
+        assert_eq!(
+            vec!["first\nsecond\n", "third\n", "a longer fourth record\n"],
+            *prints.0.lock().unwrap()
+        );
+
+        log(format_args!("fifth"));
+        drop(config.flush_on_drop());
+        assert_eq!("fifth\n", prints.0.lock().unwrap()[3]);
+
+        log(format_args!("sixth"));
+        logger.flush();
+        assert_eq!(vec!["sixth\n"], prints.0.lock().unwrap()[4..]);
+    }
+
+    #[test]
+    fn flush_guards_print_the_batch_when_dropped() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .batch(1024)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let log = |args| logger.log(&Record::builder().args(args).level(Level::Error).build());
+
+        let outer = config.flush_on_drop();
+        {
+            let _inner = config.flush_on_drop();
+            log(format_args!("inner"));
+            assert!(file.lock().unwrap().is_empty());
         }
+        assert_eq!(b"inner\n", &file.lock().unwrap()[..]);
+
+        log(format_args!("outer"));
+        assert_eq!(b"inner\n", &file.lock().unwrap()[..]);
+        drop(outer);
+        assert_eq!(b"inner\nouter\n", &file.lock().unwrap()[..]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = config.flush_on_drop();
+            log(format_args!("unwinding"));
+            panic!("update failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(b"inner\nouter\nunwinding\n", &file.lock().unwrap()[..]);
     }
 
-    impl fmt::Debug for Builder {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            if self.built {
-                f.debug_struct("Logger").field("built", &true).finish()
-            } else {
-                f.debug_struct("Logger")
-                    .field("filter", &self.filter)
-                    .field("writer", &self.writer)
-                    .finish()
+    #[test]
+    fn full_batches_drop_records_by_default() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .batch(8)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+
+        for args in [
+            format_args!("one"),
+            format_args!("two"),
+            format_args!("three"),
+        ] {
+            logger.log(&Record::builder().args(args).level(Level::Error).build());
+        }
+        assert!(file.lock().unwrap().is_empty());
+
+        config.flush().unwrap();
+        let expected = "one\ntwo\nlog batch overflowed, 1 records dropped\n";
+        assert_eq!(expected.as_bytes(), &file.lock().unwrap()[..]);
+        assert_eq!(1, config.stats().overflow_dropped);
+    }
+
+    #[test]
+    fn on_error_reports_failed_writes() {
+        struct Failing;
+
+        impl Write for Failing {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
             }
         }
+
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let logger = Arc::new(std::sync::Mutex::new(None::<Arc<Logger>>));
+        let inner = logger.clone();
+        let (built, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Failing)))
+            .on_error(move |e| {
+                reported.lock().unwrap().push(e.to_string());
+                // logging from the error function must not recurse
+                let logger = inner.lock().unwrap().clone();
+                if let Some(logger) = logger {
+                    logger.log(&Record::builder().args(format_args!("nested")).build());
+                }
+            })
+            .filter_level(LevelFilter::Info)
+            .build();
+        let record = Record::builder()
+            .args(format_args!("lost"))
+            .level(Level::Error)
+            .build();
+
+        built.log(&record);
+        assert_eq!(vec!["target 0: disk full"], *errors.lock().unwrap());
+        assert_eq!(1, config.stats().write_errors);
+
+        let built = Arc::new(built);
+        *logger.lock().unwrap() = Some(built.clone());
+        built.log(&record);
+        assert_eq!(2, errors.lock().unwrap().len());
+        assert_eq!(3, config.stats().write_errors);
+        assert_eq!(3, config.writer_stats()["pipe#0"].failed_writes);
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn concurrent_stats_add_up() {
+        let (logger, config) = Builder::new()
+            .target(Target::Void)
+            .filter_level(LevelFilter::Info)
+            .filter_fn(|metadata| metadata.target() != "dropped")
+            .build();
+        let logger = Arc::new(logger);
 
-    use log::*;
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let logger = logger.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let target = if i % 4 == 0 { "dropped" } else { "kept" };
+                        logger.log(
+                            &Record::builder()
+                                .args(format_args!("{}", i))
+                                .level(Level::Info)
+                                .target(target)
+                                .build(),
+                        );
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
 
-    use super::*;
+        let stats = config.stats();
+        assert_eq!(6000, stats.records_logged);
+        assert_eq!(2000, stats.filter_fn_suppressed);
+        assert_eq!(6000, config.writer_stats()["void#0"].records_written);
+    }
 
     #[test]
-    fn update_filter_at_runtime() {
-        let config = Builder::default().filter_level(LevelFilter::Debug).try_init().unwrap();
-        
-        debug!("This one should be printed");
-        info!("This one should be printed");
+    fn writer_stats_are_counted_per_target() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Void)
+            .route(
+                Level::Error..=Level::Error,
+                Target::Pipe(Box::new(Shared(file))),
+            )
+            .filter_level(LevelFilter::Info)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
 
-        config.update_filters("error");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("a"))
+                .level(Level::Info)
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("bc"))
+                .level(Level::Error)
+                .build(),
+        );
 
-        debug!("This one should NOT be printed");
-        info!("This one should NOT be printed");
+        let stats = config.writer_stats();
+        let void = TargetStats {
+            records_written: 2,
+            bytes_written: 5,
+            failed_writes: 0,
+        };
+        let pipe = TargetStats {
+            records_written: 1,
+            bytes_written: 3,
+            failed_writes: 0,
+        };
+        assert_eq!(vec!["pipe#1", "void#0"], stats.keys().collect::<Vec<_>>());
+        assert_eq!(void, stats["void#0"]);
+        assert_eq!(pipe, stats["pipe#1"]);
 
-        config.update_filters("info");
+        #[cfg(feature = "json")]
+        assert_eq!(
+            r#"{"records_written":1,"bytes_written":3,"failed_writes":0}"#,
+            serde_json::to_string(&stats["pipe#1"]).unwrap()
+        );
+    }
 
-        debug!("This one should NOT be printed");
-        info!("This one should be printed");
+    #[test]
+    fn flight_recorder_is_dumped_ahead_of_errors() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .flight_recorder(Level::Info, 2, 1024)
+            .build();
+
+        let log = |level, message| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .build(),
+            )
+        };
+        log(Level::Debug, "evicted");
+        log(Level::Info, "info");
+        log(Level::Debug, "a");
+        log(Level::Debug, "b");
+        assert_eq!("info\n", String::from_utf8_lossy(&file.lock().unwrap()));
+
+        log(Level::Error, "boom");
+        log(Level::Debug, "c");
+        log(Level::Error, "again");
+
+        let expected = "info\na\nb\nboom\nc\nagain\n";
+        assert_eq!(expected, String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(6, config.stats().records_logged);
+    }
+
+    #[test]
+    fn empty_messages_are_skipped() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "<{}>", record.args()))
+            .flight_recorder(Level::Info, 10, 1024)
+            .skip_empty_messages(true)
+            .build();
+        let log = |level, args: std::fmt::Arguments<'_>| {
+            logger.log(&Record::builder().args(args).level(level).build())
+        };
+
+        log(Level::Debug, format_args!(""));
+        log(Level::Debug, format_args!("{}", "recorded"));
+        log(Level::Warn, format_args!(""));
+        log(Level::Warn, format_args!(" \n"));
+        log(Level::Warn, format_args!("{}{}", "", "\t"));
+        log(Level::Error, format_args!("{}{}", " ", "x"));
+
+        assert_eq!(
+            "<recorded>\n< x>\n",
+            String::from_utf8_lossy(&file.lock().unwrap())
+        );
+        assert_eq!(4, config.stats().empty_skipped);
+    }
+
+    #[test]
+    fn records_logged_while_dumping_are_written() {
+        use std::sync::{Mutex, Weak};
+
+        let logger_slot: Arc<Mutex<Weak<Logger>>> = Arc::default();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let (slot, target_written) = (logger_slot.clone(), written.clone());
+        let target = Target::custom(move |bytes| {
+            let message = String::from_utf8_lossy(bytes).trim_end().to_owned();
+            target_written.lock().unwrap().push(message.clone());
+            if message == "context" {
+                let logger = slot.lock().unwrap().upgrade().unwrap();
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("nested"))
+                        .level(Level::Error)
+                        .build(),
+                );
+            }
+        });
+        let (logger, _) = Builder::new()
+            .target(target)
+            .filter_level(LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .flight_recorder(Level::Info, 10, 1024)
+            .build();
+        let logger = Arc::new(logger);
+        *logger_slot.lock().unwrap() = Arc::downgrade(&logger);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("context"))
+                .level(Level::Debug)
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("boom"))
+                .level(Level::Error)
+                .build(),
+        );
+
+        assert_eq!(vec!["context", "nested", "boom"], *written.lock().unwrap());
+    }
+
+    #[test]
+    fn debug_records_are_sampled() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .sample(Level::Debug, 3)
+            .build();
+
+        let log = |level, message| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .build(),
+            )
+        };
+        for i in 0..7 {
+            log(Level::Debug, i);
+            log(Level::Warn, i + 10);
+        }
+        config.set_sampling(Level::Debug, 1);
+        log(Level::Debug, 7);
+        log(Level::Debug, 8);
+
+        let expected = "0\n10\n11\n12\n3\n13\n14\n15\n6\n16\n7\n8\n";
+        assert_eq!(expected, String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(4, config.stats().sampled_out);
+    }
+
+    #[test]
+    fn chatty_modules_are_rate_limited() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .format(|buf, record| writeln!(buf, "{} {}", record.target(), record.args()))
+            .rate_limit("chatty", 2, 100)
+            .time_source(clock)
+            .build();
+
+        let log = |target, message| {
+            let mut record = Record::builder();
+            record.target(target).level(Level::Error);
+            logger.log(&record.args(format_args!("{}", message)).build())
+        };
+        for i in 0..4 {
+            log("chatty::inner", i);
+            log("quiet", i);
+        }
+        // Half of the period refills one token
+        NOW.store(50, Ordering::SeqCst);
+        log("chatty", 4);
+        log("chatty", 5);
+
+        let expected = "chatty::inner 0\nquiet 0\n\
+                        chatty::inner 1\nquiet 1\n\
+                        quiet 2\nquiet 3\n\
+                        chatty 2 records of `chatty` suppressed by the rate limit\n\
+                        chatty 4\n";
+        assert_eq!(expected, String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(3, config.stats().rate_limited);
+        assert_eq!(Some(&3), config.rate_limited().get("chatty"));
+
+        config.set_rate_limit("chatty", 0, 100);
+        NOW.store(1_000, Ordering::SeqCst);
+        log("chatty", 6);
+        assert!(config.remove_rate_limit("chatty"));
+        log("chatty", 7);
+        assert!(String::from_utf8_lossy(&file.lock().unwrap()).ends_with("chatty 7\n"));
+    }
+
+    #[test]
+    fn consecutive_duplicates_are_collapsed() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Warn)
+            .format(|buf, record| writeln!(buf, "{} {}", record.level(), record.args()))
+            .collapse_duplicates(u64::MAX)
+            .build();
+
+        let log = |level, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .build(),
+            )
+        };
+        log(Level::Error, "retry");
+        log(Level::Error, "retry");
+        log(Level::Error, "retry");
+        log(Level::Warn, "retry");
+        log(Level::Error, "done");
+        log(Level::Error, "retry");
+        log(Level::Error, "retry");
+        log(Level::Error, "done");
+
+        let expected = "ERROR retry\n\
+                        ERROR last message repeated 2 times\n\
+                        WARN retry\n\
+                        ERROR done\n\
+                        ERROR retry\n\
+                        ERROR last message repeated 1 times\n\
+                        ERROR done\n";
+        assert_eq!(expected, String::from_utf8_lossy(&file.lock().unwrap()));
+        assert_eq!(3, config.stats().duplicates_suppressed);
+    }
+
+    #[test]
+    fn targets_receive_the_record_in_their_own_format() {
+        let plain = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let json = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let formatted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let calls = formatted.clone();
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(plain.clone()))))
+            .add_target(Target::Void)
+            .add_target_with_format(
+                Target::Pipe(Box::new(Shared(json.clone()))),
+                |buf, record| {
+                    let (level, args) = (record.level(), record.args());
+                    writeln!(buf, "{{\"level\":\"{}\",\"msg\":\"{}\"}}", level, args)
+                },
+            )
+            .format(move |buf, record| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                writeln!(buf, "{}", record.args())
+            })
+            .build();
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Error)
+                .build(),
+        );
+
+        assert_eq!(b"hello\n", &plain.lock().unwrap()[..]);
+        assert_eq!(
+            &b"{\"level\":\"ERROR\",\"msg\":\"hello\"}\n"[..],
+            &json.lock().unwrap()[..]
+        );
+        assert_eq!(1, formatted.load(Ordering::Relaxed));
+        assert_eq!(1, config.stats().records_logged);
+    }
+
+    #[test]
+    fn write_style_controls_the_styles() {
+        let write = |style: &str| {
+            let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let (logger, _) = Builder::new()
+                .target(Target::Pipe(Box::new(Shared(file.clone()))))
+                .parse_write_style(style)
+                .build();
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("styled"))
+                    .level(Level::Error)
+                    .build(),
+            );
+            let written = file.lock().unwrap().clone();
+            String::from_utf8(written).unwrap()
+        };
+        // the timestamp is written between the opening brace and the level
+        let is_styled = |written: &str| {
+            written.starts_with("\x1b[90m[\x1b[0m")
+                && written.ends_with(" \x1b[1;31mERROR\x1b[0m\x1b[90m]\x1b[0m styled\n")
+        };
+        let is_plain = |written: &str| {
+            written.starts_with('[')
+                && written.ends_with(" ERROR] styled\n")
+                && !written.contains('\x1b')
+        };
+
+        assert!(is_styled(&write("always")));
+        assert!(is_plain(&write("never")));
+        // pipes are never terminals
+        assert!(is_plain(&write("auto")));
+        assert!(is_plain(&write("sometimes")));
+    }
+
+    #[test]
+    fn large_records_do_not_inflate_the_buffer() {
+        let (logger, config) = Builder::new()
+            .target(Target::Void)
+            .max_retained_buffer_bytes(1024)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let huge = "x".repeat(100 * 1024);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", huge))
+                .level(Level::Error)
+                .build(),
+        );
+        assert!(config.retained_buffer_bytes() <= 1024);
+        assert_eq!(1, config.stats().records_logged);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("small"))
+                .level(Level::Error)
+                .build(),
+        );
+        assert!(config.retained_buffer_bytes() > 0);
+        assert!(config.retained_buffer_bytes() <= 1024);
+    }
+
+    #[test]
+    fn buffer_capacity_is_allocated_upfront() {
+        let (logger, config) = Builder::new()
+            .target(Target::Void)
+            .buffer_capacity(512)
+            .max_retained_buffer_bytes(1024)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+
+        // run on a new thread, so the buffer is created by this logger
+        std::thread::spawn(move || {
+            let log = |message: &str| {
+                let args = format_args!("{}", message);
+                logger.log(&Record::builder().args(args).level(Level::Error).build());
+            };
+
+            log(&"x".repeat(300));
+            assert_eq!(512, config.retained_buffer_bytes());
+            log(&"x".repeat(500));
+            assert_eq!(512, config.retained_buffer_bytes());
+
+            log(&"x".repeat(100 * 1024));
+            assert_eq!(512, config.retained_buffer_bytes());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn built_builders_can_be_cloned_and_built_again() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut builder = Builder::new();
+        builder
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Info)
+            .filter_fn(|metadata| metadata.target() != "hidden")
+            .format(|buf, record| writeln!(buf, "[{}]", record.args()));
+        let (first, _) = builder.build();
+        let (second, _) = builder.clone().build();
+
+        for logger in [&first, &second] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("a"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("b"))
+                    .level(Level::Info)
+                    .target("hidden")
+                    .build(),
+            );
+        }
+
+        assert_eq!(b"[a]\n[a]\n", &file.lock().unwrap()[..]);
+        assert_eq!(LevelFilter::Info, second.filter());
+    }
+
+    #[test]
+    fn consuming_builders_report_reuse() {
+        let mut builder = Builder::new();
+        builder.filter_level(LevelFilter::Warn);
+        let (logger, _) = builder.clone().try_build().unwrap();
+        assert_eq!(LevelFilter::Warn, logger.filter());
+
+        builder.build();
+        let err = match builder.try_build() {
+            Ok(_) => panic!("built twice"),
+            Err(err) => err,
+        };
+        assert!(matches!(err, Error::AlreadyBuilt));
+        assert_eq!("attempt to re-use consumed builder", err.to_string());
+    }
+
+    #[test]
+    fn wrapping_loggers_delegate_to_cloned_loggers() {
+        struct Wrapper {
+            inner: Logger,
+            forwarded: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl Log for Wrapper {
+            fn enabled(&self, metadata: &Metadata) -> bool {
+                self.inner.enabled(metadata)
+            }
+
+            fn log(&self, record: &Record) {
+                self.forwarded
+                    .lock()
+                    .unwrap()
+                    .push(record.args().to_string());
+                self.inner.log(record)
+            }
+
+            fn flush(&self) {}
+        }
+
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build_logger();
+        let wrapper = Wrapper {
+            inner: logger.clone(),
+            forwarded: Default::default(),
+        };
+
+        wrapper.log(
+            &Record::builder()
+                .args(format_args!("wrapped"))
+                .level(Level::Error)
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("direct"))
+                .level(Level::Error)
+                .build(),
+        );
+
+        assert_eq!(vec!["wrapped"], *wrapper.forwarded.lock().unwrap());
+        assert_eq!(b"wrapped\ndirect\n", &file.lock().unwrap()[..]);
+        assert_eq!(2, config.stats().records_logged);
+    }
+
+    #[test]
+    fn scoped_loggers_receive_the_records_of_the_global_logger() {
+        let global = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, _) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(global.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build_logger();
+        let scope = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (scoped, _) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(scope.clone()))))
+            .format(|buf, record| writeln!(buf, "scoped {}", record.args()))
+            .build_logger();
+        let scoped = ScopedLogger::new(scoped);
+        let log = |logger: &Logger, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Error)
+                    .build(),
+            )
+        };
+
+        log(&logger, "before");
+        scoped.with_scope(|_| log(&logger, "inside"));
+        log(&logger, "after");
+
+        assert_eq!(b"before\nafter\n", &global.lock().unwrap()[..]);
+        assert_eq!(b"scoped inside\n", &scope.lock().unwrap()[..]);
+    }
+
+    #[test]
+    fn formats_can_be_replaced_at_runtime() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let log = |message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Error)
+                    .build(),
+            )
+        };
+
+        log("first");
+        config.set_format(|buf, record| writeln!(buf, "<{}>", record.args()));
+        log("second");
+        config.set_builtin_format(FormatKind::Default);
+        log("third");
+
+        let written = String::from_utf8(file.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(["first", "<second>"], lines[..2]);
+        assert!(lines[2].starts_with('[') && lines[2].ends_with("ERROR] third"));
+    }
+
+    #[test]
+    fn timestamps_use_the_time_source() {
+        fn fixed() -> u64 {
+            1_500_000_000
+        }
+        fn later() -> u64 {
+            61_000_000_000
+        }
+
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .write_style(WriteStyle::Never)
+            .time_source(fixed)
+            .build();
+        let record = Record::builder()
+            .args(format_args!("tick"))
+            .level(Level::Error)
+            .build();
+
+        logger.log(&record);
+        config.set_time_source(Some(later));
+        logger.log(&record);
+
+        #[cfg(feature = "humantime")]
+        let expected = "[1970-01-01T00:00:01.500000000Z ERROR] tick\n\
+                        [1970-01-01T00:01:01.000000000Z ERROR] tick\n";
+        #[cfg(not(feature = "humantime"))]
+        let expected = "[1500000000 ERROR] tick\n[61000000000 ERROR] tick\n";
+        assert_eq!(
+            expected,
+            String::from_utf8(file.lock().unwrap().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn the_clock_is_read_once_per_record() {
+        static READS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        fn counting() -> u64 {
+            READS.fetch_add(1, Ordering::Relaxed) as u64
+        }
+
+        let (logger, _config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(Default::default()))))
+            .add_target_with_format(Target::Void, |buf, record| {
+                writeln!(buf, "{} {}", buf.timestamp_nanos(), record.args())
+            })
+            .format(|buf, record| {
+                let (first, second) = (buf.timestamp_nanos(), buf.timestamp_nanos());
+                writeln!(buf, "{} {} {}", first, second, record.args())
+            })
+            .time_source(counting)
+            .build();
+        let record = Record::builder()
+            .args(format_args!("tick"))
+            .level(Level::Error)
+            .build();
+
+        logger.log(&record);
+        logger.log(&record);
+
+        assert_eq!(2, READS.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn timestamps_saturate_outside_of_the_u64_range() {
+        use std::time::{Duration, SystemTime};
+
+        fn before_epoch() -> u64 {
+            platform::nanos_since_epoch(SystemTime::UNIX_EPOCH - Duration::from_secs(1))
+        }
+        fn after_2554() -> u64 {
+            let years = Duration::from_secs(1000 * 366 * 24 * 3600);
+            platform::nanos_since_epoch(SystemTime::UNIX_EPOCH + years)
+        }
+
+        assert_eq!(0, before_epoch());
+        assert_eq!(u64::MAX, after_2554());
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_millis(1500);
+        assert_eq!(1_500_000_000, platform::nanos_since_epoch(epoch));
+
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for time_source in [before_epoch as fn() -> u64, after_2554] {
+            let (logger, _config) = Builder::new()
+                .target(Target::Pipe(Box::new(Shared(file.clone()))))
+                .write_style(WriteStyle::Never)
+                .format_target(false)
+                .time_source(time_source)
+                .build();
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("tick"))
+                    .level(Level::Error)
+                    .build(),
+            );
+        }
+
+        #[cfg(feature = "humantime")]
+        let expected = "[1970-01-01T00:00:00.000000000Z ERROR] tick\n\
+                        [2554-07-21T23:34:33.709551615Z ERROR] tick\n";
+        #[cfg(not(feature = "humantime"))]
+        let expected = "[0 ERROR] tick\n[18446744073709551615 ERROR] tick\n";
+        assert_eq!(
+            expected,
+            String::from_utf8(file.lock().unwrap().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn printed_text_borrows_valid_utf8() {
+        assert!(matches!(
+            platform::text(b"ok\n"),
+            std::borrow::Cow::Borrowed("ok\n")
+        ));
+        assert_eq!("ok\u{fffd}\n", platform::text(b"ok\xff\n"));
+    }
+
+    #[test]
+    fn info_matches_the_builder_settings() {
+        let (logger, config) = Builder::new()
+            .parse_filters("warn,crate1=debug")
+            .target(Target::Stderr)
+            .add_target(Target::Void)
+            .format_level(false)
+            .format_module_path(true)
+            .format_indent(None)
+            .format_suffix("\r\n")
+            .buffer_capacity(256)
+            .max_retained_buffer_bytes(1024)
+            .batch(4096)
+            .build();
+
+        let info = config.info();
+        assert!(info.enabled);
+        assert_eq!(config.current_filter_string(), info.filter);
+        assert_eq!(vec!["stderr", "void"], info.targets);
+        assert_eq!(
+            FormatInfo {
+                kind: "default".to_owned(),
+                level: false,
+                target: true,
+                module_path: true,
+                indent: None,
+                suffix: "\r\n".to_owned(),
+            },
+            info.format
+        );
+        assert_eq!(
+            BufferInfo {
+                capacity: 256,
+                max_retained_bytes: 1024,
+                max_chunk_bytes: fmt::writer::DEFAULT_MAX_CHUNK_BYTES,
+                batch_bytes: Some(4096),
+            },
+            info.buffer
+        );
+        assert_eq!(info, logger.info());
+
+        config.set_format(|buf, record| writeln!(buf, "{}", record.args()));
+        config.set_enabled(false);
+        assert_eq!("custom", config.info().format.kind);
+        assert!(!logger.info().enabled);
+    }
+
+    #[test]
+    fn cloned_configs_change_the_same_logger() {
+        let (logger, config) = Builder::new().parse_filters("warn").build();
+        let clone = config.clone();
+
+        clone.update_filters("info,ledger=debug").unwrap();
+
+        assert_eq!("info,ledger=debug", config.current_filter_string());
+        assert_eq!(LevelFilter::Debug, logger.filter());
+        assert_eq!(
+            r#"LoggerConfig { filter: "info,ledger=debug" }"#,
+            format!("{:?}", config)
+        );
+    }
+
+    #[test]
+    fn cloned_builders_build_independent_loggers() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut builder = Builder::new();
+        builder
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .filter_level(LevelFilter::Info)
+            .format(|buf, record| writeln!(buf, "{}", record.args()));
+
+        let (first, first_config) = builder.clone().build_consuming();
+        let (second, second_config) = builder.build_consuming();
+        second_config.update_filters("error").unwrap();
+
+        let log = |logger: &Logger, level, message: &str| {
+            let args = format_args!("{}", message);
+            logger.log(&Record::builder().args(args).level(level).build());
+        };
+        log(&first, Level::Info, "first");
+        log(&second, Level::Info, "dropped");
+        log(&second, Level::Error, "second");
+
+        assert_eq!(b"first\nsecond\n", &file.lock().unwrap()[..]);
+        assert_eq!(1, first_config.stats().records_logged);
+        assert_eq!(1, second_config.stats().records_logged);
+        assert_eq!(LevelFilter::Info, first.filter());
+    }
+
+    #[test]
+    fn flush_reaches_buffering_pipes() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(io::BufWriter::new(Shared(
+                file.clone(),
+            )))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let log = |args| logger.log(&Record::builder().args(args).level(Level::Error).build());
+
+        log(format_args!("first"));
+        assert!(file.lock().unwrap().is_empty());
+        logger.flush();
+        assert_eq!(b"first\n".to_vec(), *file.lock().unwrap());
+
+        log(format_args!("second"));
+        assert_eq!(b"first\n".to_vec(), *file.lock().unwrap());
+        config.flush().unwrap();
+        assert_eq!(b"first\nsecond\n".to_vec(), *file.lock().unwrap());
+    }
+
+    #[cfg(feature = "async-writer")]
+    #[test]
+    fn worker_guard_writes_all_queued_records() {
+        struct Slow(Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Slow(file.clone()))))
+            .non_blocking(8)
+            .overflow_policy(OverflowPolicy::Block)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let guard = config.worker_guard();
+
+        for i in 0..50 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", i))
+                    .level(Level::Error)
+                    .build(),
+            );
+        }
+
+        drop(guard);
+        let expected: String = (0..50).map(|i| format!("{}\n", i)).collect();
+        assert_eq!(expected.as_bytes(), &file.lock().unwrap()[..]);
+
+        // written synchronously after the shutdown
+        logger.log(
+            &Record::builder()
+                .args(format_args!("late"))
+                .level(Level::Error)
+                .build(),
+        );
+        assert!(file.lock().unwrap().ends_with(b"late\n"));
+        let expected = LoggerStats {
+            records_logged: 51,
+            ..LoggerStats::default()
+        };
+        assert_eq!(expected, config.stats());
+    }
+
+    #[test]
+    fn targets_are_swapped_at_runtime() {
+        let store = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let debug = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::new()
+            .target(Target::Pipe(Box::new(Shared(store.clone()))))
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .build();
+        let logger = Arc::new(logger);
+        let log = |logger: &Logger, args| {
+            logger.log(&Record::builder().args(args).level(Level::Error).build())
+        };
+        let store_id = config.target_ids()[0];
 
+        log(&logger, format_args!("one"));
+        let debug_id = config.add_target(Target::Pipe(Box::new(Shared(debug.clone()))));
+        assert_eq!(vec![store_id, debug_id], config.target_ids());
+        log(&logger, format_args!("two"));
+        assert!(config.remove_target(debug_id).unwrap());
+        assert!(!config.remove_target(debug_id).unwrap());
+        log(&logger, format_args!("three"));
+
+        assert_eq!(b"one\ntwo\nthree\n".to_vec(), *store.lock().unwrap());
+        assert_eq!(b"two\n".to_vec(), *debug.lock().unwrap());
+
+        // records logged while swapping go whole to the previous or new targets
+        let writer = {
+            let logger = logger.clone();
+            std::thread::spawn(move || {
+                for _ in 0..500 {
+                    log(&logger, format_args!("concurrent"));
+                }
+            })
+        };
+        for _ in 0..50 {
+            let id = config.add_target(Target::Pipe(Box::new(Shared(debug.clone()))));
+            config.remove_target(id).unwrap();
+        }
+        writer.join().unwrap();
+
+        let stored = String::from_utf8(store.lock().unwrap().clone()).unwrap();
+        assert_eq!(500, stored.matches("concurrent\n").count());
+        let debugged = String::from_utf8(debug.lock().unwrap().clone()).unwrap();
+        assert!(debugged
+            .split_terminator('\n')
+            .skip(1)
+            .all(|line| line == "concurrent"));
+
+        let id = config
+            .set_target(Target::Pipe(Box::new(Shared(debug.clone()))))
+            .unwrap();
+        assert_eq!(vec![id], config.target_ids());
+    }
+
+    #[test]
+    fn merge_filters_into_current_directives() {
+        let (_, config) = Builder::default()
+            .parse_filters("warn,crate1=info,crate2=debug/needle")
+            .build();
+
+        config.merge_filters("crate1=trace,crate3=error").unwrap();
+        assert_eq!(
+            "warn,crate1=trace,crate2=debug,crate3=error/needle",
+            config.current_filter_string()
+        );
+
+        config.merge_filters("off,-crate2/haystack").unwrap();
+        assert_eq!(
+            "off,crate1=trace,-crate2,crate3=error/haystack",
+            config.current_filter_string()
+        );
+        assert_eq!(LevelFilter::Trace, config.max_level());
+
+        let result = config.merge_filters("crate4=trace,infoo");
+        assert!(matches!(result, Err(Error::InvalidFilter(_))));
+        assert_eq!(
+            "off,crate1=trace,-crate2,crate3=error/haystack",
+            config.current_filter_string()
+        );
+
+        config.update_filters("info").unwrap();
+        assert_eq!("info", config.current_filter_string());
+    }
+
+    #[test]
+    fn filter_fns_are_and_ed_and_replaceable() {
+        let (logger, config) = Builder::default()
+            .parse_filters("info")
+            .filter_fn(|metadata| metadata.target() != "crate1")
+            .filter_fn(|metadata| metadata.target() != "crate2")
+            .build();
+        let record = |target, level| {
+            Record::builder()
+                .args(format_args!("m"))
+                .target(target)
+                .level(level)
+                .build()
+        };
+
+        assert!(logger.enabled(record("crate3", Level::Info).metadata()));
+        assert!(!logger.enabled(record("crate1", Level::Info).metadata()));
+        assert!(!logger.matches(&record("crate2", Level::Info)));
+
+        logger.log(&record("crate1", Level::Info));
+        logger.log(&record("crate2", Level::Info));
+        logger.log(&record("crate2", Level::Debug));
+        logger.log(&record("crate3", Level::Info));
+        assert_eq!(2, config.stats().filter_fn_suppressed);
+
+        config.set_filter_fn(|metadata| metadata.target() == "crate1");
+        assert!(logger.matches(&record("crate1", Level::Info)));
+        assert!(!logger.matches(&record("crate2", Level::Info)));
+        assert!(!logger.matches(&record("crate1", Level::Debug)));
+
+        config.clear_filter_fns();
+        assert!(logger.matches(&record("crate2", Level::Info)));
+    }
+
+    #[test]
+    fn max_bytes_per_call_suppresses_records() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::default()
+            .filter_level(LevelFilter::Info)
+            .format(|buf, record| writeln!(buf, "{} {}", record.level(), record.args()))
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .max_bytes_per_call(12)
+            .build();
+
+        let record = |args| Record::builder().args(args).level(Level::Info).build();
+
+        // 11 bytes for each record, the second one doesn't fit
+        logger.log(&record(format_args!("12345")));
+        logger.log(&record(format_args!("12345")));
+        logger.log(&record(format_args!("12345")));
+        assert_eq!(2, config.stats().budget_suppressed);
+        assert_eq!(
+            "INFO 12345\n",
+            String::from_utf8_lossy(&file.lock().unwrap())
+        );
+
+        config.reset_call_budget();
+
+        // The summary goes over the budget of the new call
+        logger.log(&record(format_args!("12345")));
+        assert_eq!(2, config.stats().budget_suppressed);
+        assert_eq!(
+            "INFO 12345\n\
+             WARN log output truncated, 2 records suppressed\n\
+             INFO 12345\n",
+            String::from_utf8_lossy(&file.lock().unwrap())
+        );
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn max_bytes_per_call_charges_every_format_once() {
+        let file = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let json = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (logger, config) = Builder::default()
+            .filter_level(LevelFilter::Info)
+            .format(|buf, record| writeln!(buf, "{}", record.args()))
+            .target(Target::Pipe(Box::new(Shared(file.clone()))))
+            .add_target_with_format(
+                Target::Pipe(Box::new(Shared(json.clone()))),
+                |buf, record| writeln!(buf, "[{}]", record.args()),
+            )
+            .max_bytes_per_call(20)
+            .build();
+
+        let record = |args| Record::builder().args(args).level(Level::Info).build();
+
+        // 6 + 8 bytes for each record, the second one doesn't fit in 20 bytes
+        logger.log(&record(format_args!("12345")));
+        logger.log(&record(format_args!("12345")));
+        assert_eq!(1, config.stats().budget_suppressed);
+        assert_eq!(1, config.stats().records_logged);
+
+        // The summary is written with the format of every target
+        config.reset_call_budget();
+        logger.log(&record(format_args!("6")));
+        assert_eq!(
+            "12345\nlog output truncated, 1 records suppressed\n6\n",
+            String::from_utf8_lossy(&file.lock().unwrap())
+        );
+        assert_eq!(
+            "[12345]\n[log output truncated, 1 records suppressed]\n[6]\n",
+            String::from_utf8_lossy(&json.lock().unwrap())
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Macros limiting how often a call site logs.
+
+/// Logs a record the first time the call site is reached only.
+///
+/// It takes the same arguments as [`log!`], and the record goes through the
+/// filter and the format of the logger like any other. The call site is marked
+/// as reached even if the record is filtered out.
+///
+/// ```
+/// use ic_log::log::Level;
+///
+/// for attempt in 0..3 {
+///     ic_log::log_once!(Level::Warn, "the ledger is slow, attempt {}", attempt);
+/// }
+/// ```
+///
+/// [`log!`]: https://docs.rs/log/*/log/macro.log.html
+#[macro_export]
+macro_rules! log_once {
+    (target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        static LOGGED: ::std::sync::atomic::AtomicBool =
+            ::std::sync::atomic::AtomicBool::new(false);
+        if !LOGGED.swap(true, ::std::sync::atomic::Ordering::Relaxed) {
+            $crate::log::log!(target: $target, $lvl, $($arg)+);
+        }
+    }};
+    ($lvl:expr, $($arg:tt)+) => {{
+        static LOGGED: ::std::sync::atomic::AtomicBool =
+            ::std::sync::atomic::AtomicBool::new(false);
+        if !LOGGED.swap(true, ::std::sync::atomic::Ordering::Relaxed) {
+            $crate::log::log!($lvl, $($arg)+);
+        }
+    }};
+}
+
+/// Logs a record every `n` times the call site is reached, starting with the
+/// first one.
+///
+/// It takes `n` followed by the same arguments as [`log!`]. The call site is
+/// counted even if the record is filtered out. An `n` of 0 is the same as 1.
+///
+/// ```
+/// use ic_log::log::Level;
+///
+/// for request in 0..1000 {
+///     // logs the requests 0, 100, 200...
+///     ic_log::log_every_n!(100, Level::Debug, "handling request {}", request);
+/// }
+/// ```
+///
+/// [`log!`]: https://docs.rs/log/*/log/macro.log.html
+#[macro_export]
+macro_rules! log_every_n {
+    ($n:expr, target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        static COUNT: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        let n = ::std::cmp::max($n as u64, 1);
+        if COUNT.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed) % n == 0 {
+            $crate::log::log!(target: $target, $lvl, $($arg)+);
+        }
+    }};
+    ($n:expr, $lvl:expr, $($arg:tt)+) => {{
+        static COUNT: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        let n = ::std::cmp::max($n as u64, 1);
+        if COUNT.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed) % n == 0 {
+            $crate::log::log!($lvl, $($arg)+);
+        }
+    }};
+}
@@ -0,0 +1,405 @@
+//! A sink shipping the records to an external collector with HTTPS outcalls.
+//!
+//! The records are accumulated in the canister memory, then posted as JSON
+//! arrays of strings when the sink is flushed, usually from a timer:
+//!
+//! ```no_run
+//! use ic_log::outcall::HttpSink;
+//! use ic_log::Builder;
+//!
+//! let sink = HttpSink::builder("https://logs.example.com/ingest")
+//!     .header("Authorization", "Bearer secret")
+//!     .build();
+//!
+//! Builder::new().add_target(sink.target()).init();
+//!
+//! // from a timer or an update endpoint
+//! ic_cdk::spawn(async move { sink.flush().await });
+//! ```
+//!
+//! Outcalls are only available on wasm32. On native targets flushing fails
+//! with an error reported to the error hook.
+//!
+//! Requires the `http-outcall` feature.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, TransformContext,
+};
+
+use crate::fmt::ErrorFn;
+use crate::Target;
+
+/// The maximum size of an outcall request body.
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// A snapshot of the statistics of an [`HttpSink`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HttpSinkStats {
+    /// Records posted to the collector.
+    pub records_shipped: u64,
+    /// Records dropped because the sink was full, or because they didn't fit
+    /// in a request body.
+    pub records_dropped: u64,
+    /// Outcalls that failed, dropping their records.
+    pub failed_outcalls: u64,
+    /// Cycles attached to the outcalls.
+    pub cycles_spent: u128,
+}
+
+/// Builds an [`HttpSink`].
+pub struct HttpSinkBuilder {
+    url: String,
+    headers: Vec<HttpHeader>,
+    max_pending_bytes: usize,
+    max_response_bytes: u64,
+    transform: Option<TransformContext>,
+    on_error: Option<ErrorFn>,
+    built: bool,
+}
+
+impl HttpSinkBuilder {
+    /// Adds a header to the requests, like an `Authorization` header.
+    pub fn header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers.push(HttpHeader {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        });
+        self
+    }
+
+    /// Sets the maximum bytes of the records waiting to be shipped, 1 MiB by
+    /// default. The new records are dropped once it is reached.
+    pub fn max_pending_bytes(&mut self, max_bytes: usize) -> &mut Self {
+        self.max_pending_bytes = max_bytes;
+        self
+    }
+
+    /// Sets the maximum size of the collector response, 4 KiB by default.
+    ///
+    /// The cycles paid for an outcall grow with this limit.
+    pub fn max_response_bytes(&mut self, max_bytes: u64) -> &mut Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// Sets the function transforming the collector responses, so the
+    /// replicas can reach a consensus on them.
+    pub fn transform(&mut self, transform: TransformContext) -> &mut Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Sets a function called with the error of every failed outcall.
+    ///
+    /// Errors are never trapped on: the records of a failed outcall are
+    /// dropped and counted in [`HttpSinkStats::failed_outcalls`].
+    ///
+    /// [`HttpSinkStats::failed_outcalls`]: struct.HttpSinkStats.html#structfield.failed_outcalls
+    pub fn on_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Builds the sink.
+    pub fn build(&mut self) -> HttpSink {
+        assert!(!self.built, "attempt to re-use consumed builder");
+        self.built = true;
+
+        HttpSink {
+            inner: Arc::new(Inner {
+                url: std::mem::take(&mut self.url),
+                headers: std::mem::take(&mut self.headers),
+                max_pending_bytes: self.max_pending_bytes,
+                max_response_bytes: self.max_response_bytes,
+                transform: self.transform.take(),
+                on_error: self.on_error.take(),
+                pending: Mutex::new(Pending::default()),
+                records_shipped: AtomicU64::new(0),
+                records_dropped: AtomicU64::new(0),
+                failed_outcalls: AtomicU64::new(0),
+                cycles_spent: Mutex::new(0),
+            }),
+        }
+    }
+}
+
+/// Accumulates the records written to its [`target`], and ships them to a
+/// collector when flushed.
+///
+/// Cloning the sink returns a handle to the same records.
+///
+/// [`target`]: #method.target
+#[derive(Clone)]
+pub struct HttpSink {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    url: String,
+    headers: Vec<HttpHeader>,
+    max_pending_bytes: usize,
+    max_response_bytes: u64,
+    transform: Option<TransformContext>,
+    on_error: Option<ErrorFn>,
+    pending: Mutex<Pending>,
+    records_shipped: AtomicU64,
+    records_dropped: AtomicU64,
+    failed_outcalls: AtomicU64,
+    cycles_spent: Mutex<u128>,
+}
+
+#[derive(Default)]
+struct Pending {
+    records: Vec<String>,
+    bytes: usize,
+}
+
+impl HttpSink {
+    /// Starts building a sink posting the records to `url`.
+    pub fn builder(url: &str) -> HttpSinkBuilder {
+        HttpSinkBuilder {
+            url: url.to_owned(),
+            headers: Vec::new(),
+            max_pending_bytes: 1024 * 1024,
+            max_response_bytes: 4 * 1024,
+            transform: None,
+            on_error: None,
+            built: false,
+        }
+    }
+
+    /// Returns a target adding the records to this sink.
+    pub fn target(&self) -> Target {
+        let inner = self.inner.clone();
+        Target::custom(move |bytes| inner.push(bytes))
+    }
+
+    /// Returns a snapshot of the sink statistics.
+    pub fn stats(&self) -> HttpSinkStats {
+        let inner = &self.inner;
+        HttpSinkStats {
+            records_shipped: inner.records_shipped.load(Ordering::Relaxed),
+            records_dropped: inner.records_dropped.load(Ordering::Relaxed),
+            failed_outcalls: inner.failed_outcalls.load(Ordering::Relaxed),
+            cycles_spent: *inner
+                .cycles_spent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Ships the accumulated records, splitting them into as many outcalls
+    /// as needed to respect the request body limit.
+    ///
+    /// The records logged while the outcalls are in flight are shipped by the
+    /// next flush.
+    pub async fn flush(&self) {
+        let inner = &self.inner;
+        for (count, body) in inner.take_batches() {
+            let request = inner.request(body);
+            let cycles = required_cycles(&request);
+            *inner
+                .cycles_spent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) += cycles;
+
+            match send(request, cycles).await {
+                Ok(()) => {
+                    inner.records_shipped.fetch_add(count, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    inner.failed_outcalls.fetch_add(1, Ordering::Relaxed);
+                    inner.records_dropped.fetch_add(count, Ordering::Relaxed);
+                    if let Some(on_error) = &inner.on_error {
+                        on_error(&e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn push(&self, bytes: &[u8]) {
+        let record = String::from_utf8_lossy(bytes);
+        let record = record.strip_suffix('\n').unwrap_or(&record);
+
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if pending.bytes + record.len() > self.max_pending_bytes {
+            self.records_dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        pending.bytes += record.len();
+        pending.records.push(record.to_owned());
+    }
+
+    /// Takes the pending records, encoded into request bodies with the number
+    /// of records they contain.
+    fn take_batches(&self) -> Vec<(u64, Vec<u8>)> {
+        let records = {
+            let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            pending.bytes = 0;
+            std::mem::take(&mut pending.records)
+        };
+
+        let (batches, dropped) = batches(&records, MAX_BODY_BYTES);
+        self.records_dropped.fetch_add(dropped, Ordering::Relaxed);
+        batches
+    }
+
+    fn request(&self, body: Vec<u8>) -> CanisterHttpRequestArgument {
+        let mut headers = vec![HttpHeader {
+            name: "Content-Type".to_owned(),
+            value: "application/json".to_owned(),
+        }];
+        headers.extend(self.headers.iter().cloned());
+
+        CanisterHttpRequestArgument {
+            url: self.url.clone(),
+            max_response_bytes: Some(self.max_response_bytes),
+            method: HttpMethod::POST,
+            headers,
+            body: Some(body),
+            transform: self.transform.clone(),
+        }
+    }
+}
+
+/// Encodes the records into JSON arrays of at most `max_bytes`, returning
+/// the arrays with the number of records they contain, and the number of
+/// records too large to fit in any array.
+fn batches(records: &[String], max_bytes: usize) -> (Vec<(u64, Vec<u8>)>, u64) {
+    let mut batches = Vec::new();
+    let mut dropped = 0;
+    let mut body = Vec::new();
+    let mut count = 0;
+
+    for record in records {
+        let encoded = serde_json::to_string(record).unwrap_or_default();
+        // the brackets of the array, and the comma before the record
+        if encoded.len() + 2 > max_bytes {
+            dropped += 1;
+            continue;
+        }
+        if count > 0 && body.len() + encoded.len() + 2 > max_bytes {
+            body.push(b']');
+            batches.push((count, std::mem::take(&mut body)));
+            count = 0;
+        }
+        body.push(if count == 0 { b'[' } else { b',' });
+        body.extend_from_slice(encoded.as_bytes());
+        count += 1;
+    }
+
+    if count > 0 {
+        body.push(b']');
+        batches.push((count, body));
+    }
+    (batches, dropped)
+}
+
+/// The cycles to attach to an outcall on a 13-node subnet.
+fn required_cycles(request: &CanisterHttpRequestArgument) -> u128 {
+    let arg_len = ic_cdk::export::candid::encode_one(request.clone()).map_or(0, |arg| arg.len());
+    let max_response_bytes = request.max_response_bytes.unwrap_or(2 * 1024 * 1024);
+    // 12 is the length of the "http_request" method name
+    400_000_000 + 100_000 * (arg_len as u128 + 12 + max_response_bytes as u128)
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn send(request: CanisterHttpRequestArgument, cycles: u128) -> io::Result<()> {
+    use ic_cdk::api::management_canister::http_request::http_request_with_cycles;
+
+    let (response,) = http_request_with_cycles(request, cycles)
+        .await
+        .map_err(|(code, e)| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("http outcall failed: {:?} {}", code, e),
+            )
+        })?;
+
+    if response.status >= 200u32 && response.status < 300u32 {
+        Ok(())
+    } else {
+        let message = format!("http outcall returned status {}", response.status);
+        Err(io::Error::new(io::ErrorKind::Other, message))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn send(_: CanisterHttpRequestArgument, _: u128) -> io::Result<()> {
+    let message = "http outcalls are only available on wasm32";
+    Err(io::Error::new(io::ErrorKind::Unsupported, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(records: &[&str]) -> Vec<String> {
+        records.iter().map(|record| record.to_string()).collect()
+    }
+
+    #[test]
+    fn records_are_split_into_bodies() {
+        let (batches, dropped) = batches(&records(&["a", "b\"", "c"]), 12);
+
+        let expected = vec![(2, br#"["a","b\""]"#.to_vec()), (1, br#"["c"]"#.to_vec())];
+        assert_eq!(expected, batches);
+        assert_eq!(0, dropped);
+    }
+
+    #[test]
+    fn records_larger_than_a_body_are_dropped() {
+        let (batches, dropped) = batches(&records(&["a", "too large", "b"]), 9);
+
+        assert_eq!(vec![(2, br#"["a","b"]"#.to_vec())], batches);
+        assert_eq!(1, dropped);
+    }
+
+    #[test]
+    fn the_target_accumulates_records_up_to_the_limit() {
+        let sink = HttpSink::builder("https://logs.example.com")
+            .max_pending_bytes(8)
+            .build();
+        let target = match sink.target() {
+            Target::Custom(f) => f,
+            _ => unreachable!(),
+        };
+
+        target(b"first\n");
+        target(b"second\n");
+
+        assert_eq!(
+            vec![(1, br#"["first"]"#.to_vec())],
+            sink.inner.take_batches()
+        );
+        assert_eq!(1, sink.stats().records_dropped);
+        assert!(sink.inner.take_batches().is_empty());
+    }
+
+    #[test]
+    fn requests_post_json_with_the_headers() {
+        let sink = HttpSink::builder("https://logs.example.com")
+            .header("Authorization", "Bearer secret")
+            .build();
+
+        let request = sink.inner.request(b"[]".to_vec());
+        assert_eq!(HttpMethod::POST, request.method);
+        let headers: Vec<_> = request
+            .headers
+            .iter()
+            .map(|header| header.name.as_str())
+            .collect();
+        assert_eq!(vec!["Content-Type", "Authorization"], headers);
+        assert!(required_cycles(&request) > 400_000_000 + 100_000 * 4 * 1024);
+    }
+}
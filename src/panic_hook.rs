@@ -0,0 +1,63 @@
+//! Logging of the panics.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, Location};
+
+thread_local! {
+    // Set while a panic is logged, so a panic of the logger isn't logged again
+    static LOGGING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Logs the panics as `error` records with the `panic` target, before calling
+/// the panic hook set previously.
+///
+/// The record contains the panic message and location, and on native targets
+/// with the `backtrace` feature the backtrace. On wasm32 it is logged before
+/// the canister traps, so the panic reaches the targets of the logger.
+///
+/// # Examples
+///
+/// ```
+/// ic_log::init();
+/// ic_log::install_panic_hook();
+/// ```
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log_panic(info.payload(), info.location());
+        previous(info);
+    }));
+}
+
+fn log_panic(payload: &(dyn Any + Send), location: Option<&Location>) {
+    let logging = LOGGING
+        .try_with(|logging| logging.replace(true))
+        .unwrap_or(true);
+    if logging {
+        return;
+    }
+
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => *message,
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message,
+            None => "Box<dyn Any>",
+        },
+    };
+    let location = location.map_or_else(|| "<unknown>".to_owned(), ToString::to_string);
+
+    #[cfg(all(feature = "backtrace", not(target_arch = "wasm32")))]
+    log::error!(target: "panic", "panicked at '{}', {}\n{}", message, location, backtrace());
+    #[cfg(not(all(feature = "backtrace", not(target_arch = "wasm32"))))]
+    log::error!(target: "panic", "panicked at '{}', {}", message, location);
+
+    let _ = LOGGING.try_with(|logging| logging.set(false));
+}
+
+// `Backtrace` requires Rust 1.65, which the `backtrace` feature documents
+#[cfg(all(feature = "backtrace", not(target_arch = "wasm32")))]
+#[allow(clippy::incompatible_msrv)]
+fn backtrace() -> std::backtrace::Backtrace {
+    std::backtrace::Backtrace::force_capture()
+}
@@ -1,47 +1,245 @@
-use std::time::SystemTime;
+//! The system services used by the logger.
+//!
+//! A logger uses the platform set with [`Builder::platform`], or by default
+//! [`IcPlatform`] on wasm32 with the `ic` feature and [`StdPlatform`]
+//! everywhere else. The functions of this module always use the default
+//! platform.
+//!
+//! [`Builder::platform`]: ../struct.Builder.html#method.platform
 
-/// returns the timestamp in nanoseconds
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwapOption;
+
+/// The clock and the output of a platform.
+///
+/// # Examples
+///
+/// Log from a wasm32 environment other than the IC:
+///
+/// ```
+/// use ic_log::platform::Platform;
+///
+/// struct Host;
+///
+/// impl Platform for Host {
+///     fn time_nanos(&self) -> u64 {
+///         // ask the host
+///         0
+///     }
+///
+///     fn print(&self, data: &[u8]) {
+///         print!("{}", String::from_utf8_lossy(data));
+///     }
+/// }
+///
+/// ic_log::Builder::new().platform(Host).init();
+/// ```
+pub trait Platform: Send + Sync {
+    /// returns the timestamp in nanoseconds since the epoch
+    fn time_nanos(&self) -> u64;
+
+    /// returns the current time, derived from [`time_nanos`](#tymethod.time_nanos)
+    /// by default
+    fn system_time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.time_nanos())
+    }
+
+    /// prints the records written to `stdout`
+    fn print(&self, data: &[u8]);
+
+    /// prints the records written to `stderr`, with [`print`](#tymethod.print)
+    /// by default
+    fn eprint(&self, data: &[u8]) {
+        self.print(data)
+    }
+}
+
+/// The IC system API, only available inside a canister.
+///
+/// This platform is only available with the `ic` feature.
+#[cfg(feature = "ic")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IcPlatform;
+
+#[cfg(feature = "ic")]
+impl Platform for IcPlatform {
+    fn time_nanos(&self) -> u64 {
+        ic_cdk::api::time()
+    }
+
+    fn print(&self, data: &[u8]) {
+        ic_cdk::print(text(data))
+    }
+}
+
+/// The standard library: the system clock, `stdout` and `stderr`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdPlatform;
+
+impl Platform for StdPlatform {
+    fn time_nanos(&self) -> u64 {
+        nanos_since_epoch(SystemTime::now())
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    // The print macros are used since they are captured by `cargo test`, the
+    // bytes are written directly by `write_stdout` and `write_stderr`
+    fn print(&self, data: &[u8]) {
+        print!("{}", text(data))
+    }
+
+    fn eprint(&self, data: &[u8]) {
+        eprint!("{}", text(data))
+    }
+}
+
+/// Returns the nanoseconds since the epoch, saturating at 0 for a clock set
+/// before 1970 and at `u64::MAX` after 2554, so a badly set clock never makes
+/// logging panic.
+pub(crate) fn nanos_since_epoch(time: SystemTime) -> u64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(elapsed) => u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX),
+        Err(_) => 0,
+    }
+}
+
+/// Borrows the formatted records, which are almost always valid UTF-8, and
+/// only replaces the invalid sequences of the others.
+pub(crate) fn text(data: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(data) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => String::from_utf8_lossy(data),
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "ic"))]
+const DEFAULT: IcPlatform = IcPlatform;
+
+#[cfg(not(all(target_arch = "wasm32", feature = "ic")))]
+const DEFAULT: StdPlatform = StdPlatform;
+
+/// The platform of a logger, shared by its clones.
+#[derive(Clone)]
+pub(crate) struct SharedPlatform(Arc<dyn Platform>);
+
+impl SharedPlatform {
+    pub fn new(platform: impl Platform + 'static) -> Self {
+        SharedPlatform(Arc::new(platform))
+    }
+
+    pub fn get(&self) -> &dyn Platform {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for SharedPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Platform").finish()
+    }
+}
+
+/// The time of the records of a logger: its time source if any, or else the
+/// time of its platform.
+pub(crate) struct Clock {
+    time_source: ArcSwapOption<fn() -> u64>,
+    platform: Option<SharedPlatform>,
+}
+
+impl Clock {
+    pub fn new(time_source: Option<fn() -> u64>, platform: Option<SharedPlatform>) -> Self {
+        Clock {
+            time_source: ArcSwapOption::from_pointee(time_source),
+            platform,
+        }
+    }
+
+    pub fn set_time_source(&self, time_source: Option<fn() -> u64>) {
+        self.time_source.store(time_source.map(Arc::new));
+    }
+
+    /// Returns the time in nanoseconds since the epoch.
+    pub fn now_nanos(&self) -> u64 {
+        if let Some(time_source) = &*self.time_source.load() {
+            return time_source();
+        }
+        match &self.platform {
+            Some(platform) => platform.get().time_nanos(),
+            None => current_timestamp_in_nanosecs(),
+        }
+    }
+}
+
+/// returns the current time
 #[inline]
 pub fn current_system_time() -> SystemTime {
+    DEFAULT.system_time()
+}
+
+/// returns the timestamp in nanoseconds
+#[inline]
+pub fn current_timestamp_in_nanosecs() -> u64 {
+    DEFAULT.time_nanos()
+}
+
+/// returns the value of an environment variable, always `None` on wasm
+pub fn env_var(name: &str) -> Option<String> {
     #[cfg(not(target_arch = "wasm32"))]
     {
-        SystemTime::now()
+        std::env::var(name).ok()
     }
 
     #[cfg(target_arch = "wasm32")]
     {
-        let timestamp_in_nanos = ic_cdk::api::time();
-        std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_nanos(timestamp_in_nanos)
+        let _ = name;
+        None
     }
 }
 
-/// returns the timestamp in nanoseconds
 #[inline]
-pub fn current_timestamp_in_nanosecs() -> u64 {
+pub fn print(data: &[u8]) {
+    DEFAULT.print(data)
+}
+
+/// writes to stdout in a single call, bypassing the `cargo test` output capture
+/// of [`print`]. On wasm it prints like [`print`]
+pub fn write_stdout(data: &[u8]) -> std::io::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
     {
-        std::time::SystemTime::now()
-            .duration_since(std::time::SystemTime::UNIX_EPOCH)
-            .expect("get current timestamp error")
-            .as_nanos() as u64
+        use std::io::Write;
+        std::io::stdout().lock().write_all(data)
     }
 
     #[cfg(target_arch = "wasm32")]
     {
-        ic_cdk::api::time()
+        print(data);
+        Ok(())
     }
 }
 
-#[inline]
-pub fn print(data: &[u8]) {
+/// writes to stderr like [`write_stdout`]
+pub fn write_stderr(data: &[u8]) -> std::io::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
     {
-        print!("{}", String::from_utf8_lossy(data))
+        use std::io::Write;
+        std::io::stderr().lock().write_all(data)
     }
 
-    // ic::time() return the nano_sec, we need to change it to sec.
     #[cfg(target_arch = "wasm32")]
     {
-        ic_cdk::print(format!("{}", String::from_utf8_lossy(data)))
+        eprint(data);
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// prints to stderr, or with `ic_cdk::print` on wasm like [`print`]
+#[inline]
+pub fn eprint(data: &[u8]) {
+    DEFAULT.eprint(data)
+}
@@ -0,0 +1,20 @@
+//! The types and macros needed to set up and use the logger.
+//!
+//! The `log` macros and types are re-exported from the `log` crate used by
+//! `ic_log`, so a canister can log without depending on `log` itself, and
+//! without risking a version of `log` different from the one of `ic_log`.
+//!
+//! ```
+//! use ic_log::prelude::*;
+//!
+//! let config = Builder::new().filter_level(LevelFilter::Info).try_init().unwrap();
+//!
+//! info!("canister initialized");
+//! config.update_filters("debug").unwrap();
+//! debug!("now visible");
+//! ```
+
+pub use log::{debug, error, info, log, log_enabled, trace, warn};
+pub use log::{Level, LevelFilter, Metadata, Record};
+
+pub use crate::{log_every_n, log_once, Builder, Logger, LoggerConfig, Target};
@@ -0,0 +1,140 @@
+//! Token-bucket rate limiting of the records of some modules.
+//!
+//! Every bucket is keyed by a module prefix and holds up to `max_records`
+//! tokens, refilled continuously over `period_ns`. A record takes a token from
+//! the bucket with the longest prefix of its target, and is suppressed if the
+//! bucket is empty.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::platform::Clock;
+
+pub(crate) struct RateLimiter {
+    clock: Arc<Clock>,
+    // Checked on every record, so it is kept out of the mutex
+    active: AtomicBool,
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+struct Bucket {
+    prefix: String,
+    max_records: u32,
+    period_ns: u64,
+    tokens: f64,
+    refilled_at: u64,
+    // suppressed since the last record let through
+    suppressed: u64,
+    total_suppressed: u64,
+}
+
+/// Whether a record is let through by the rate limit.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Admission {
+    /// The record can be logged.
+    pub allowed: bool,
+    /// The prefix of the bucket and the number of records it suppressed, to
+    /// report before the record when a suppression ends.
+    pub ended: Option<(String, u64)>,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.refilled_at);
+        if self.period_ns == 0 {
+            self.tokens = f64::from(self.max_records);
+        } else {
+            let refilled = elapsed as f64 * f64::from(self.max_records) / self.period_ns as f64;
+            self.tokens = (self.tokens + refilled).min(f64::from(self.max_records));
+        }
+        self.refilled_at = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(clock: Arc<Clock>) -> RateLimiter {
+        RateLimiter {
+            clock,
+            active: AtomicBool::new(false),
+            buckets: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Limits the records of the modules starting with `prefix` to
+    /// `max_records` per `period_ns`, replacing the previous limit of the
+    /// prefix if any.
+    pub fn set(&self, prefix: &str, max_records: u32, period_ns: u64) {
+        let mut buckets = self.lock();
+        match buckets.iter_mut().find(|bucket| bucket.prefix == prefix) {
+            Some(bucket) => {
+                bucket.max_records = max_records;
+                bucket.period_ns = period_ns;
+                bucket.tokens = bucket.tokens.min(f64::from(max_records));
+            }
+            None => buckets.push(Bucket {
+                prefix: prefix.to_owned(),
+                max_records,
+                period_ns,
+                tokens: f64::from(max_records),
+                refilled_at: self.clock.now_nanos(),
+                suppressed: 0,
+                total_suppressed: 0,
+            }),
+        }
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Removes the limit of `prefix`, returning `false` if there was none.
+    pub fn remove(&self, prefix: &str) -> bool {
+        let mut buckets = self.lock();
+        let len = buckets.len();
+        buckets.retain(|bucket| bucket.prefix != prefix);
+        self.active.store(!buckets.is_empty(), Ordering::Relaxed);
+        buckets.len() != len
+    }
+
+    /// Takes a token for a record of `target`.
+    pub fn admit(&self, target: &str) -> Admission {
+        let mut admission = Admission {
+            allowed: true,
+            ended: None,
+        };
+        if !self.active.load(Ordering::Relaxed) {
+            return admission;
+        }
+
+        let mut buckets = self.lock();
+        let bucket = buckets
+            .iter_mut()
+            .filter(|bucket| target.starts_with(&bucket.prefix))
+            .max_by_key(|bucket| bucket.prefix.len());
+        if let Some(bucket) = bucket {
+            bucket.refill(self.clock.now_nanos());
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                if bucket.suppressed > 0 {
+                    let suppressed = std::mem::take(&mut bucket.suppressed);
+                    admission.ended = Some((bucket.prefix.clone(), suppressed));
+                }
+            } else {
+                bucket.suppressed += 1;
+                bucket.total_suppressed += 1;
+                admission.allowed = false;
+            }
+        }
+        admission
+    }
+
+    /// Returns the number of records suppressed so far, by prefix.
+    pub fn suppressed(&self) -> BTreeMap<String, u64> {
+        self.lock()
+            .iter()
+            .map(|bucket| (bucket.prefix.clone(), bucket.total_suppressed))
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Bucket>> {
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,124 @@
+//! The flight recorder, keeping the low-severity records in memory.
+//!
+//! The records less severe than a threshold are formatted into a ring instead
+//! of being written to the targets. When a severe enough record is logged, the
+//! ring is written ahead of it, so the context leading to an error is logged
+//! without paying for it the rest of the time.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+
+use log::Level;
+
+pub(crate) struct FlightRecorder {
+    threshold: Level,
+    trigger: Level,
+    max_records: usize,
+    max_bytes: usize,
+    ring: Mutex<Ring>,
+}
+
+#[derive(Default)]
+struct Ring {
+    records: VecDeque<Recorded>,
+    bytes: usize,
+}
+
+/// A formatted record kept in the ring.
+pub(crate) struct Recorded {
+    pub level: Level,
+    pub target: String,
+    pub bytes: Vec<u8>,
+}
+
+impl FlightRecorder {
+    pub fn new(threshold: Level, trigger: Level, max_records: usize, max_bytes: usize) -> Self {
+        FlightRecorder {
+            threshold,
+            trigger,
+            max_records,
+            max_bytes,
+            ring: Mutex::new(Ring::default()),
+        }
+    }
+
+    /// Whether the records of `level` are kept in the ring.
+    pub fn records(&self, level: Level) -> bool {
+        level > self.threshold
+    }
+
+    /// Whether the records of `level` trigger a dump of the ring.
+    pub fn triggers(&self, level: Level) -> bool {
+        level <= self.trigger
+    }
+
+    /// Adds a record, evicting the oldest ones to stay within the capacity.
+    ///
+    /// A record larger than the whole ring is dropped.
+    pub fn push(&self, record: Recorded) {
+        if self.max_records == 0 || record.bytes.len() > self.max_bytes {
+            return;
+        }
+
+        let mut ring = self.lock();
+        while ring.records.len() >= self.max_records
+            || ring.bytes + record.bytes.len() > self.max_bytes
+        {
+            match ring.records.pop_front() {
+                Some(evicted) => ring.bytes -= evicted.bytes.len(),
+                None => break,
+            }
+        }
+        ring.bytes += record.bytes.len();
+        ring.records.push_back(record);
+    }
+
+    /// Empties the ring, returning its records from the oldest.
+    ///
+    /// The ring is released before the records are written, so the records
+    /// logged while writing them are kept for the next dump.
+    pub fn take(&self) -> VecDeque<Recorded> {
+        let mut ring = self.lock();
+        ring.bytes = 0;
+        std::mem::take(&mut ring.records)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(message: &str) -> Recorded {
+        Recorded {
+            level: Level::Debug,
+            target: String::new(),
+            bytes: message.as_bytes().to_vec(),
+        }
+    }
+
+    fn messages(recorder: &FlightRecorder) -> Vec<String> {
+        recorder
+            .take()
+            .into_iter()
+            .map(|record| String::from_utf8(record.bytes).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn oldest_records_are_evicted() {
+        let recorder = FlightRecorder::new(Level::Info, Level::Error, 3, 8);
+        for message in ["aa", "bb", "cc", "dd", "toolarge!"] {
+            recorder.push(recorded(message));
+        }
+        assert_eq!(vec!["bb", "cc", "dd"], messages(&recorder));
+
+        recorder.push(recorded("eeeee"));
+        recorder.push(recorded("ffff"));
+        assert_eq!(vec!["ffff"], messages(&recorder));
+        assert!(messages(&recorder).is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+//! Deterministic sampling of the records of some levels.
+//!
+//! A level sampled one in `n` keeps the first of every `n` records of that
+//! level, using a counter so the records kept are reproducible.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use log::Level;
+
+const LEVELS: usize = 5;
+
+#[derive(Debug, Default)]
+pub(crate) struct Sampler {
+    // One in how many records of each level are kept, 0 and 1 keeping all
+    one_in: [AtomicU32; LEVELS],
+    seen: [AtomicU64; LEVELS],
+}
+
+fn index(level: Level) -> usize {
+    level as usize - 1
+}
+
+impl Sampler {
+    /// Keeps one in `one_in` records of `level`, restarting the count.
+    pub fn set(&self, level: Level, one_in: u32) {
+        self.one_in[index(level)].store(one_in, Ordering::Relaxed);
+        self.seen[index(level)].store(0, Ordering::Relaxed);
+    }
+
+    /// Checks whether a record of `level` is kept.
+    pub fn keep(&self, level: Level) -> bool {
+        let one_in = self.one_in[index(level)].load(Ordering::Relaxed);
+        if one_in <= 1 {
+            return true;
+        }
+        self.seen[index(level)].fetch_add(1, Ordering::Relaxed) % u64::from(one_in) == 0
+    }
+}
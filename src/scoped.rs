@@ -0,0 +1,147 @@
+//! Logging with a logger that is not the global one.
+//!
+//! A [`ScopedLogger`] formats and writes records like the global logger, but
+//! only receives the records it is given, or the records of the `log` macros
+//! while one of its scopes runs. The scope is per thread on native targets,
+//! and global on wasm32 where canisters run on a single thread.
+
+use std::sync::Arc;
+
+use log::{LevelFilter, Record};
+
+use crate::Logger;
+
+/// A logger used for a scope, without replacing the global logger.
+///
+/// It lets a library, or a handler of a single request, collect its records
+/// with the formats and targets of its own [`Logger`].
+///
+/// # Examples
+///
+/// ```
+/// use ic_log::{Builder, ScopedLogger, Target};
+/// use log::LevelFilter;
+///
+/// ic_log::init_with_level(LevelFilter::Warn);
+///
+/// let (logger, _config) = Builder::new()
+///     .filter_level(LevelFilter::Debug)
+///     .target(Target::custom(|bytes| print!("request: {}", String::from_utf8_lossy(bytes))))
+///     .build_logger();
+/// let scoped = ScopedLogger::new(logger);
+///
+/// scoped.with_scope(|_| {
+///     log::debug!("handled by the scoped logger");
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ScopedLogger {
+    logger: Arc<Logger>,
+}
+
+impl ScopedLogger {
+    /// Wraps a logger built with [`Builder::build_logger`].
+    ///
+    /// [`Builder::build_logger`]: struct.Builder.html#method.build_logger
+    pub fn new(logger: Logger) -> ScopedLogger {
+        ScopedLogger {
+            logger: Arc::new(logger),
+        }
+    }
+
+    /// Logs a record, whatever the scope is.
+    pub fn log_record(&self, record: &Record) {
+        self.logger.log_record(record);
+    }
+
+    /// Runs `f` with the records of the `log` macros routed to this logger.
+    ///
+    /// The records are routed by the global logger, so it must have been
+    /// installed by this crate. They are filtered by this logger only, the
+    /// global filter applying outside of the scopes.
+    ///
+    /// Scopes can be nested, the previous logger being restored when a scope
+    /// ends, even if `f` panics. The maximum level of the `log` crate is
+    /// raised for the scope if this logger accepts more verbose records.
+    pub fn with_scope<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&ScopedLogger) -> R,
+    {
+        let max_level = log::max_level();
+        let raised = self.logger.filter() > max_level;
+        let _restore = Restore {
+            previous: Some(slot::replace(Some(self.logger.clone()))),
+            max_level: if raised { Some(max_level) } else { None },
+        };
+        if raised {
+            log::set_max_level(self.logger.filter());
+        }
+        f(self)
+    }
+}
+
+/// Restores the previous scope when dropped.
+struct Restore {
+    previous: Option<Option<Arc<Logger>>>,
+    // The maximum level to restore, if the scope raised it
+    max_level: Option<LevelFilter>,
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        slot::replace(self.previous.take().flatten());
+        if let Some(max_level) = self.max_level {
+            log::set_max_level(max_level);
+        }
+    }
+}
+
+/// Returns the logger of the current scope, if any.
+pub(crate) fn current() -> Option<Arc<Logger>> {
+    slot::current()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod slot {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    use crate::Logger;
+
+    thread_local! {
+        static CURRENT: RefCell<Option<Arc<Logger>>> = const { RefCell::new(None) };
+    }
+
+    pub fn replace(logger: Option<Arc<Logger>>) -> Option<Arc<Logger>> {
+        CURRENT
+            .try_with(|current| current.replace(logger))
+            .ok()
+            .flatten()
+    }
+
+    pub fn current() -> Option<Arc<Logger>> {
+        CURRENT
+            .try_with(|current| current.borrow().clone())
+            .ok()
+            .flatten()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod slot {
+    use std::sync::Arc;
+
+    use arc_swap::ArcSwapOption;
+
+    use crate::Logger;
+
+    static CURRENT: ArcSwapOption<Logger> = ArcSwapOption::const_empty();
+
+    pub fn replace(logger: Option<Arc<Logger>>) -> Option<Arc<Logger>> {
+        CURRENT.swap(logger)
+    }
+
+    pub fn current() -> Option<Arc<Logger>> {
+        CURRENT.load_full()
+    }
+}
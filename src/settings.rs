@@ -0,0 +1,106 @@
+//! Logger settings that can be passed as canister init arguments.
+
+use crate::error::Error;
+use crate::{Builder, LoggerConfig, Target};
+
+/// The settings of a logger, usually received as canister init arguments.
+///
+/// With the `serde` feature it can be deserialized, and with the `candid`
+/// feature it can be decoded from candid. Absent fields take their default
+/// value and unknown fields are ignored.
+///
+/// # Examples
+///
+/// ```
+/// use ic_log::LogSettings;
+///
+/// let settings = LogSettings {
+///     log_filter: Some("warn,my_canister=debug".to_owned()),
+///     ..Default::default()
+/// };
+///
+/// let (logger, _) = settings.builder().unwrap().build();
+/// assert_eq!(log::LevelFilter::Debug, logger.filter());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[cfg_attr(feature = "candid", derive(ic_cdk::export::candid::CandidType))]
+#[cfg_attr(feature = "candid", candid_path("ic_cdk::export::candid"))]
+pub struct LogSettings {
+    /// Whether the records are printed to the replica output, `stdout`
+    /// off-chain. Defaults to `true`.
+    pub enable_console: bool,
+    /// The filter directives, parsed like [`Builder::try_parse_filters`].
+    /// Defaults to `None`, logging errors only.
+    ///
+    /// [`Builder::try_parse_filters`]: struct.Builder.html#method.try_parse_filters
+    pub log_filter: Option<String>,
+    /// The output budget of a call, see [`Builder::max_bytes_per_call`].
+    /// Defaults to `None`, no budget.
+    ///
+    /// [`Builder::max_bytes_per_call`]: struct.Builder.html#method.max_bytes_per_call
+    pub max_bytes_per_call: Option<u64>,
+    /// The interval at which the repetitions of a record are reported, see
+    /// [`Builder::collapse_duplicates`]. Defaults to `None`, keeping the
+    /// duplicates.
+    ///
+    /// [`Builder::collapse_duplicates`]: struct.Builder.html#method.collapse_duplicates
+    pub collapse_duplicates_ns: Option<u64>,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            enable_console: true,
+            log_filter: None,
+            max_bytes_per_call: None,
+            collapse_duplicates_ns: None,
+        }
+    }
+}
+
+impl LogSettings {
+    /// Returns a builder configured with these settings.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the filter contains invalid directives.
+    pub fn builder(&self) -> Result<Builder, Error> {
+        let mut builder = Builder::new();
+        if let Some(filter) = &self.log_filter {
+            builder.try_parse_filters(filter)?;
+        }
+        builder.target(if self.enable_console {
+            Target::Stdout
+        } else {
+            Target::Void
+        });
+        if let Some(max_bytes) = self.max_bytes_per_call {
+            // A budget over the address space can't be reached on wasm32
+            builder.max_bytes_per_call(usize::try_from(max_bytes).unwrap_or(usize::MAX));
+        }
+        if let Some(max_interval_ns) = self.collapse_duplicates_ns {
+            builder.collapse_duplicates(max_interval_ns);
+        }
+        Ok(builder)
+    }
+}
+
+/// Initializes the global logger with the given settings.
+///
+/// # Errors
+///
+/// Fails if the filter contains invalid directives, or if a global logger not
+/// built by this crate is already installed.
+///
+/// # Examples
+///
+/// ```
+/// use ic_log::LogSettings;
+///
+/// let config = ic_log::init_log(&LogSettings::default()).unwrap();
+/// ```
+pub fn init_log(settings: &LogSettings) -> Result<LoggerConfig, Error> {
+    settings.builder()?.try_init()
+}
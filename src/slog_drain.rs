@@ -0,0 +1,98 @@
+//! Logging the records of the `slog` crate.
+//!
+//! The records are converted to `log` records and passed to the global
+//! logger, so the filters, formats and targets of the logger apply to them.
+//! The formats only write the message of a record, so the key-value pairs of
+//! the record and of its logger are appended to the message as `key=value`.
+
+use std::fmt::{self, Write};
+
+/// A `slog` drain logging the records with the global logger.
+///
+/// The target of the records is the tag of the slog record, or its module
+/// when the tag is empty. The message and the key-value pairs are written to
+/// a single `String` per record.
+///
+/// This drain is only available with the `slog` feature.
+///
+/// # Examples
+///
+/// ```
+/// use ic_log::SlogDrain;
+/// use slog::{info, o, Drain, Logger};
+///
+/// ic_log::init();
+///
+/// let logger = Logger::root(SlogDrain::new().fuse(), o!("canister" => "ledger"));
+/// info!(logger, "transfer started"; "amount" => 10);
+/// // logged as `transfer started amount=10 canister=ledger`
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SlogDrain {
+    _priv: (),
+}
+
+impl SlogDrain {
+    /// Creates the drain.
+    pub fn new() -> SlogDrain {
+        SlogDrain::default()
+    }
+}
+
+/// Appends the key-value pairs to the message.
+struct Pairs<'a>(&'a mut String);
+
+impl slog::Serializer for Pairs<'_> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments<'_>) -> slog::Result {
+        write!(self.0, " {}={}", key, val)?;
+        Ok(())
+    }
+}
+
+fn level(level: slog::Level) -> log::Level {
+    match level {
+        slog::Level::Critical | slog::Level::Error => log::Level::Error,
+        slog::Level::Warning => log::Level::Warn,
+        slog::Level::Info => log::Level::Info,
+        slog::Level::Debug => log::Level::Debug,
+        slog::Level::Trace => log::Level::Trace,
+    }
+}
+
+impl slog::Drain for SlogDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record<'_>,
+        values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let level = level(record.level());
+        if level > log::max_level() {
+            return Ok(());
+        }
+
+        let mut message = String::new();
+        let _ = write!(message, "{}", record.msg());
+        let mut pairs = Pairs(&mut message);
+        let _ = slog::KV::serialize(&record.kv(), record, &mut pairs);
+        let _ = slog::KV::serialize(values, record, &mut pairs);
+
+        let target = match record.tag() {
+            "" => record.module(),
+            tag => tag,
+        };
+        log::logger().log(
+            &log::Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target(target)
+                .module_path_static(Some(record.module()))
+                .file_static(Some(record.file()))
+                .line(Some(record.line()))
+                .build(),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Snapshots of the logger filter.
+
+use std::fmt;
+use std::sync::Arc;
+
+use log::LevelFilter;
+
+use crate::filter::{self, Filter, FilterParseError};
+
+/// The filter of a logger at some point in time, taken with
+/// [`LoggerConfig::snapshot`] and put back with [`LoggerConfig::restore`].
+///
+/// Taking a snapshot is cheap, since the filter is shared rather than copied.
+/// With the `serde` feature a snapshot is serialized as its directives string,
+/// so it can be kept in stable memory across an upgrade.
+///
+/// [`LoggerConfig::snapshot`]: struct.LoggerConfig.html#method.snapshot
+/// [`LoggerConfig::restore`]: struct.LoggerConfig.html#method.restore
+#[derive(Clone)]
+pub struct FilterSnapshot {
+    pub(crate) filter: Arc<Filter>,
+    level: LevelFilter,
+}
+
+impl FilterSnapshot {
+    pub(crate) fn new(filter: Arc<Filter>) -> FilterSnapshot {
+        let level = filter.filter();
+        FilterSnapshot { filter, level }
+    }
+
+    /// Rebuilds a snapshot from a directives string, like the one returned by
+    /// [`directives`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the string isn't valid, see [`Builder::try_parse_filters`].
+    ///
+    /// [`directives`]: #method.directives
+    /// [`Builder::try_parse_filters`]: struct.Builder.html#method.try_parse_filters
+    pub fn from_directives(directives: &str) -> Result<FilterSnapshot, FilterParseError> {
+        let filter = filter::Builder::new().try_parse(directives)?.build();
+        Ok(FilterSnapshot::new(Arc::new(filter)))
+    }
+
+    /// Returns the canonical directives string of the filter.
+    pub fn directives(&self) -> String {
+        self.filter.to_string()
+    }
+
+    /// Returns the maximum `LevelFilter` of the filter.
+    pub fn max_level(&self) -> LevelFilter {
+        self.level
+    }
+}
+
+impl fmt::Debug for FilterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FilterSnapshot")
+            .field("directives", &self.directives())
+            .field("level", &self.level)
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FilterSnapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.directives())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FilterSnapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let directives = String::deserialize(deserializer)?;
+        FilterSnapshot::from_directives(&directives).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_is_send_and_sync() {
+        fn assert_send_sync<T: Clone + Send + Sync>() {}
+        assert_send_sync::<FilterSnapshot>();
+    }
+
+    #[test]
+    fn from_directives() {
+        let snapshot = FilterSnapshot::from_directives("crate1=debug,warn").unwrap();
+        assert_eq!("warn,crate1=debug", snapshot.directives());
+        assert_eq!(LevelFilter::Debug, snapshot.max_level());
+
+        assert!(FilterSnapshot::from_directives("infoo").is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn serde_round_trip() {
+        let snapshot = FilterSnapshot::from_directives("warn,crate1=debug/needle").unwrap();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(r#""warn,crate1=debug/needle""#, json);
+
+        let restored: FilterSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.directives(), restored.directives());
+        assert!(serde_json::from_str::<FilterSnapshot>(r#""infoo""#).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use arc_swap::{ArcSwap, Guard};
+#[cfg(feature = "ic")]
+use ic_cdk::export::Principal;
+use log::{LevelFilter, Metadata};
+
+#[cfg(feature = "ic")]
+use crate::caller::CallerLevels;
+use crate::filter::Filter;
+
+/// A callback registered with `LoggerConfig::on_filter_change`.
+pub(crate) type ChangeCallback = Arc<dyn Fn(&str, LevelFilter) + Send + Sync>;
+
+/// A predicate added with `Builder::filter_fn`.
+pub(crate) type MetadataPredicate = Arc<dyn Fn(&Metadata) -> bool + Send + Sync>;
+
+/// The filter state shared between a `Logger` and its `LoggerConfig`.
+///
+/// Records are matched against the filter without locking, while updates are
+/// serialized so the global `log::max_level` always matches the filter and
+/// caller overrides installed last, even with concurrent updates. The max
+/// level is only published while the logger is the global logger.
+pub(crate) struct FilterState {
+    filter: ArcSwap<Filter>,
+    predicates: ArcSwap<Vec<MetadataPredicate>>,
+    #[cfg(feature = "ic")]
+    callers: CallerLevels,
+    // Held while updating. It contains the max level published by the last update.
+    published: Mutex<LevelFilter>,
+    callbacks: Mutex<Vec<ChangeCallback>>,
+    // Set while the logger is the global logger
+    global: AtomicBool,
+    // Set while the max level is `off`, so disabled records are rejected
+    // without matching the directives
+    off: AtomicBool,
+}
+
+impl FilterState {
+    pub fn new(filter: Filter, predicates: Vec<MetadataPredicate>) -> FilterState {
+        let published = filter.filter();
+        FilterState {
+            filter: ArcSwap::from_pointee(filter),
+            predicates: ArcSwap::from_pointee(predicates),
+            #[cfg(feature = "ic")]
+            callers: CallerLevels::default(),
+            published: Mutex::new(published),
+            callbacks: Mutex::new(Vec::new()),
+            global: AtomicBool::new(false),
+            off: AtomicBool::new(published == LevelFilter::Off),
+        }
+    }
+
+    pub fn load(&self) -> Guard<Arc<Filter>> {
+        self.filter.load()
+    }
+
+    pub fn load_full(&self) -> Arc<Filter> {
+        self.filter.load_full()
+    }
+
+    /// Checks the metadata against all the predicates.
+    pub fn allows(&self, metadata: &Metadata) -> bool {
+        self.predicates
+            .load()
+            .iter()
+            .all(|predicate| predicate(metadata))
+    }
+
+    /// Returns `true` if the filter and the caller overrides reject all the
+    /// records, with a single atomic load.
+    #[inline]
+    pub fn is_off(&self) -> bool {
+        self.off.load(Ordering::Relaxed)
+    }
+
+    /// Replaces all the predicates.
+    pub fn set_predicates(&self, predicates: Vec<MetadataPredicate>) {
+        self.predicates.store(Arc::new(predicates));
+    }
+
+    /// Returns the override for the caller of the current message, if any.
+    pub fn caller_level(&self) -> Option<LevelFilter> {
+        #[cfg(feature = "ic")]
+        {
+            self.callers.current()
+        }
+
+        #[cfg(not(feature = "ic"))]
+        {
+            None
+        }
+    }
+
+    #[cfg(feature = "ic")]
+    pub fn set_caller_level(&self, caller: Principal, level: LevelFilter) {
+        self.update(|state| state.callers.set(caller, level))
+    }
+
+    #[cfg(feature = "ic")]
+    pub fn remove_caller_level(&self, caller: &Principal) {
+        self.update(|state| state.callers.remove(caller))
+    }
+
+    /// Returns the maximum level of the filter and the caller overrides.
+    pub fn max_level(&self) -> LevelFilter {
+        let max_level = self.filter.load().filter();
+        #[cfg(feature = "ic")]
+        let max_level = max_level.max(self.callers.max_level());
+        max_level
+    }
+
+    /// Replaces the filter, returning the previous one.
+    ///
+    /// This must be called from [`update`], which publishes the new max level.
+    ///
+    /// [`update`]: #method.update
+    pub fn store(&self, filter: Arc<Filter>) -> Arc<Filter> {
+        self.filter.swap(filter)
+    }
+
+    /// Starts publishing the max level, once the logger was installed as the
+    /// global logger.
+    pub fn attach(&self) {
+        let mut published = self
+            .published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *published = self.max_level();
+        self.global.store(true, Ordering::Relaxed);
+        log::set_max_level(*published);
+    }
+
+    /// Stops publishing the max level, once another logger was installed as the
+    /// global logger.
+    pub fn detach(&self) {
+        let _published = self
+            .published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.global.store(false, Ordering::Relaxed);
+    }
+
+    /// Stops publishing the max level, turning it off if the logger was the
+    /// global logger, once the logger was shut down.
+    pub fn release(&self) {
+        let _published = self
+            .published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.global.swap(false, Ordering::Relaxed) {
+            log::set_max_level(LevelFilter::Off);
+        }
+    }
+
+    /// Derives a new filter from the current one and replaces it.
+    pub fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(&Filter) -> Filter,
+    {
+        self.update(|state| state.filter.store(Arc::new(f(&state.filter.load()))))
+    }
+
+    /// Registers a callback invoked after every update.
+    pub fn on_change(&self, callback: ChangeCallback) {
+        self.callbacks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(callback);
+    }
+
+    /// Runs an update of the state and publishes the resulting max level, while
+    /// holding the update lock.
+    ///
+    /// The change callbacks are invoked afterwards, outside of the lock, so they
+    /// can update the state again. Their panics are caught and ignored.
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&FilterState) -> R,
+    {
+        let (result, change) = {
+            let mut published = self
+                .published
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let result = f(self);
+            *published = self.max_level();
+            self.off
+                .store(*published == LevelFilter::Off, Ordering::Relaxed);
+            if self.global.load(Ordering::Relaxed) {
+                log::set_max_level(*published);
+            }
+
+            let callbacks = self
+                .callbacks
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone();
+            let change = if callbacks.is_empty() {
+                None
+            } else {
+                Some((self.filter.load().to_string(), *published, callbacks))
+            };
+            (result, change)
+        };
+
+        if let Some((directives, level, callbacks)) = change {
+            for callback in callbacks {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&directives, level)));
+            }
+        }
+        result
+    }
+
+    /// Returns the max level published by the last update, and the one it
+    /// should be.
+    #[cfg(test)]
+    pub fn published(&self) -> (LevelFilter, LevelFilter) {
+        let published = self.published.lock().unwrap();
+        (*published, self.max_level())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::filter;
+
+    #[test]
+    fn concurrent_updates_publish_the_last_max_level() {
+        let state = Arc::new(FilterState::new(filter::Builder::new().build(), Vec::new()));
+        let specs = ["error", "warn", "info", "debug", "trace", "off"];
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    #[cfg(feature = "ic")]
+                    let caller = Principal::from_slice(&[i as u8]);
+                    for j in 0..200 {
+                        let spec = specs[(i + j) % specs.len()];
+                        match j % 4 {
+                            0 => state
+                                .modify(|f| f.to_builder().filter_level(LevelFilter::Info).build()),
+                            #[cfg(feature = "ic")]
+                            1 => state.set_caller_level(caller, LevelFilter::Debug),
+                            #[cfg(feature = "ic")]
+                            2 => state.remove_caller_level(&caller),
+                            _ => {
+                                let filter = Arc::new(filter::Builder::new().parse(spec).build());
+                                state.update(|state| state.store(filter));
+                            }
+                        }
+                        let (published, expected) = state.published();
+                        assert_eq!(expected, published);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let (published, expected) = state.published();
+        assert_eq!(expected, published);
+    }
+}
@@ -0,0 +1,148 @@
+//! Statistics collected by the logger.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the statistics collected by a [`Logger`].
+///
+/// Each counter is read once, but not all at the same instant: records logged
+/// concurrently with the snapshot may be counted in some fields and not yet in
+/// others.
+///
+/// [`Logger`]: struct.Logger.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "candid", derive(ic_cdk::export::candid::CandidType))]
+#[cfg_attr(feature = "candid", candid_path("ic_cdk::export::candid"))]
+pub struct LoggerStats {
+    /// Records formatted and written to the targets.
+    pub records_logged: u64,
+    /// Records dropped because the per-call output budget was exhausted.
+    pub budget_suppressed: u64,
+    /// Records dropped by the sampling of their level.
+    pub sampled_out: u64,
+    /// Records suppressed by a rate limit.
+    pub rate_limited: u64,
+    /// Records dropped because they repeated the previous one.
+    pub duplicates_suppressed: u64,
+    /// Records matching the filter but dropped because logging was disabled.
+    pub disabled_suppressed: u64,
+    /// Records matching the directives but rejected by a `filter_fn` predicate.
+    pub filter_fn_suppressed: u64,
+    /// Records dropped because their message was empty, see
+    /// `Builder::skip_empty_messages`.
+    pub empty_skipped: u64,
+    /// Records dropped because the cycles balance was low, see
+    /// `Builder::min_cycles_for_level`.
+    pub cycles_throttled: u64,
+    /// Panics caught while writing records to a custom target.
+    pub target_panics: u64,
+    /// Panics caught while formatting records with a custom format.
+    pub format_panics: u64,
+    /// Records that failed to be formatted or written to a target.
+    pub write_errors: u64,
+    /// Records dropped because a batch was full.
+    pub overflow_dropped: u64,
+}
+
+/// The counters shared between a `Logger` and its `LoggerConfig`.
+///
+/// They are updated with relaxed atomic additions, which compile to plain
+/// loads and stores on single-threaded wasm32. The counters updated for every
+/// record share the first cache line.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+pub(crate) struct Counters {
+    pub records_logged: AtomicU64,
+    pub budget_suppressed: AtomicU64,
+    pub sampled_out: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub duplicates_suppressed: AtomicU64,
+    pub disabled_suppressed: AtomicU64,
+    pub filter_fn_suppressed: AtomicU64,
+    pub empty_skipped: AtomicU64,
+    pub cycles_throttled: AtomicU64,
+    pub write_errors: AtomicU64,
+    pub format_panics: AtomicU64,
+}
+
+impl Counters {
+    pub fn snapshot(&self) -> LoggerStats {
+        LoggerStats {
+            records_logged: self.records_logged.load(Ordering::Relaxed),
+            budget_suppressed: self.budget_suppressed.load(Ordering::Relaxed),
+            sampled_out: self.sampled_out.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            duplicates_suppressed: self.duplicates_suppressed.load(Ordering::Relaxed),
+            disabled_suppressed: self.disabled_suppressed.load(Ordering::Relaxed),
+            filter_fn_suppressed: self.filter_fn_suppressed.load(Ordering::Relaxed),
+            empty_skipped: self.empty_skipped.load(Ordering::Relaxed),
+            cycles_throttled: self.cycles_throttled.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            format_panics: self.format_panics.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+/// A snapshot of the statistics of a single target, returned by
+/// [`LoggerConfig::writer_stats`].
+///
+/// With the `serde` feature it can be returned from a canister query.
+///
+/// [`LoggerConfig::writer_stats`]: struct.LoggerConfig.html#method.writer_stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TargetStats {
+    /// Records written to the target, or added to its batch.
+    pub records_written: u64,
+    /// Bytes of the records counted in `records_written`.
+    pub bytes_written: u64,
+    /// Records that failed to be written to the target.
+    pub failed_writes: u64,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TargetStats {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut stats = serializer.serialize_struct("TargetStats", 3)?;
+        stats.serialize_field("records_written", &self.records_written)?;
+        stats.serialize_field("bytes_written", &self.bytes_written)?;
+        stats.serialize_field("failed_writes", &self.failed_writes)?;
+        stats.end()
+    }
+}
+
+/// The counters of a single target.
+#[derive(Debug, Default)]
+pub(crate) struct TargetCounters {
+    records_written: AtomicU64,
+    bytes_written: AtomicU64,
+    failed_writes: AtomicU64,
+}
+
+impl TargetCounters {
+    /// Counts the result of writing a record of `len` bytes, which is `false`
+    /// if the record was dropped.
+    pub fn record(&self, len: usize, result: &io::Result<bool>) {
+        match result {
+            Ok(true) => {
+                self.records_written.fetch_add(1, Ordering::Relaxed);
+                self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+            }
+            Ok(false) => {}
+            Err(_) => {
+                self.failed_writes.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> TargetStats {
+        TargetStats {
+            records_written: self.records_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            failed_writes: self.failed_writes.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::filter::Filter;
+use crate::platform::Clock;
+
+/// Tracks a temporary filter set with [`LoggerConfig::update_filters_for`] and
+/// the filter to restore when it expires.
+///
+/// [`LoggerConfig::update_filters_for`]: struct.LoggerConfig.html#method.update_filters_for
+pub(crate) struct TemporaryFilter {
+    // The expiry in nanoseconds, zero when there is no temporary filter. It is
+    // checked on every record, so it is kept out of the mutex.
+    expires_at: AtomicU64,
+    previous: Mutex<Option<Arc<Filter>>>,
+    clock: Arc<Clock>,
+}
+
+impl TemporaryFilter {
+    pub fn new(clock: Arc<Clock>) -> TemporaryFilter {
+        TemporaryFilter {
+            expires_at: AtomicU64::new(0),
+            previous: Mutex::new(None),
+            clock,
+        }
+    }
+
+    /// Starts a temporary filter lasting `duration_ns`.
+    ///
+    /// If a temporary filter is already active, the filter in place before it
+    /// is kept as the one to restore.
+    pub fn begin(&self, previous: Arc<Filter>, duration_ns: u64) {
+        let mut current = self.previous.lock().unwrap_or_else(|e| e.into_inner());
+        if current.is_none() {
+            *current = Some(previous);
+        }
+        let expires_at = self.clock.now_nanos().saturating_add(duration_ns).max(1);
+        self.expires_at.store(expires_at, Ordering::Release);
+    }
+
+    /// Ends the temporary filter, returning the filter to restore if one was active.
+    pub fn cancel(&self) -> Option<Arc<Filter>> {
+        let mut current = self.previous.lock().unwrap_or_else(|e| e.into_inner());
+        self.expires_at.store(0, Ordering::Release);
+        current.take()
+    }
+
+    /// Checks if there is a temporary filter to end, without locking.
+    pub fn is_due(&self) -> bool {
+        let expires_at = self.expires_at.load(Ordering::Acquire);
+        expires_at != 0 && self.clock.now_nanos() >= expires_at
+    }
+
+    /// Ends the temporary filter if it expired, returning the filter to restore.
+    pub fn expired(&self) -> Option<Arc<Filter>> {
+        if !self.is_due() {
+            return None;
+        }
+        self.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::TemporaryFilter;
+    use crate::filter;
+    use crate::platform::Clock;
+
+    static NOW: AtomicU64 = AtomicU64::new(1_000);
+
+    fn clock() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn expires_and_keeps_the_first_previous_filter() {
+        let temporary = TemporaryFilter::new(Arc::new(Clock::new(Some(clock), None)));
+        let first = Arc::new(filter::Builder::new().parse("warn").build());
+        let second = Arc::new(filter::Builder::new().parse("debug").build());
+
+        assert!(temporary.expired().is_none());
+
+        temporary.begin(first, 100);
+        temporary.begin(second, 200);
+        assert!(temporary.expired().is_none());
+
+        NOW.store(1_199, Ordering::SeqCst);
+        assert!(temporary.expired().is_none());
+
+        NOW.store(1_200, Ordering::SeqCst);
+        let restored = temporary.expired().unwrap();
+        assert_eq!("warn", restored.to_string());
+        assert!(temporary.expired().is_none());
+        assert!(temporary.cancel().is_none());
+    }
+}
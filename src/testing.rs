@@ -0,0 +1,180 @@
+//! Helpers to assert on the records logged by the code under test.
+//!
+//! [`capture`] routes the records logged from the current thread to an
+//! in-memory list:
+//!
+//! ```
+//! let logs = ic_log::testing::capture();
+//!
+//! log::info!("transfer started");
+//!
+//! assert!(logs.contains(log::Level::Info, "transfer started"));
+//! ```
+//!
+//! The global logger is installed on the first call, unless one was already
+//! installed with [`init`] or [`Builder::try_init`]. In that case its targets
+//! are replaced, while its filter and format are kept. The targets of the
+//! loggers initialized afterwards are replaced as well.
+//!
+//! `cargo test` runs every test on its own thread, so tests running in
+//! parallel never see the records of each other. For the same reason, records
+//! logged from the threads spawned by a test, or from the background thread of
+//! a [non-blocking] logger, are not captured.
+//!
+//! [`init`]: ../fn.init.html
+//! [`Builder::try_init`]: ../struct.Builder.html#method.try_init
+//! [non-blocking]: ../struct.Builder.html#method.non_blocking
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, PoisonError};
+
+use arc_swap::ArcSwapOption;
+use log::{Level, LevelFilter};
+
+use crate::fmt::writer::Writer;
+use crate::{Builder, Target};
+
+/// The writer of the logger installed by this crate, if any.
+static INSTALLED: ArcSwapOption<Writer> = ArcSwapOption::const_empty();
+
+static ROUTES: Once = Once::new();
+
+// Whether the targets of the global logger are replaced by the captures
+static ROUTED: AtomicBool = AtomicBool::new(false);
+
+type Records = Arc<Mutex<Vec<CapturedRecord>>>;
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Records>> = const { RefCell::new(None) };
+}
+
+/// Records the writer of the global logger, so the captures can replace its
+/// targets.
+pub(crate) fn register(writer: &Arc<Writer>) {
+    INSTALLED.store(Some(writer.clone()));
+    if ROUTED.load(Ordering::Acquire) {
+        route(writer);
+    }
+}
+
+/// A record logged while a [`Capture`] was active.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedRecord {
+    /// The level of the record.
+    pub level: Level,
+    /// The formatted record, without the trailing newline.
+    pub message: String,
+}
+
+/// Collects the records logged from the thread that created it.
+///
+/// The records are no longer collected once it is dropped, or once another
+/// capture is started on the same thread.
+#[derive(Debug)]
+pub struct Capture {
+    records: Records,
+}
+
+impl Capture {
+    /// Returns the records captured so far, in order.
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.lock().clone()
+    }
+
+    /// Whether a record of `level` contains `text`.
+    pub fn contains(&self, level: Level, text: &str) -> bool {
+        self.lock()
+            .iter()
+            .any(|record| record.level == level && record.message.contains(text))
+    }
+
+    /// Discards the records captured so far.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<CapturedRecord>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = CAPTURE.try_with(|capture| {
+            let mut capture = capture.borrow_mut();
+            if capture
+                .as_ref()
+                .map_or(false, |records| Arc::ptr_eq(records, &self.records))
+            {
+                *capture = None;
+            }
+        });
+    }
+}
+
+/// Starts capturing the records logged from the current thread.
+///
+/// If no logger is installed yet, a logger printing only the messages of the
+/// records at all levels is installed.
+///
+/// # Panics
+///
+/// This function panics if a global logger not built by this crate is already
+/// installed.
+pub fn capture() -> Capture {
+    ROUTES.call_once(|| {
+        if INSTALLED.load().is_none() {
+            let _ = Builder::new()
+                .filter_level(LevelFilter::Trace)
+                .format(|buf, record| writeln!(buf, "{}", record.args()))
+                .try_init();
+        }
+        ROUTED.store(true, Ordering::Release);
+        let writer = INSTALLED
+            .load_full()
+            .expect("a global logger not built by ic_log is already installed");
+        route(&writer);
+    });
+
+    let records = Records::default();
+    CAPTURE.with(|capture| *capture.borrow_mut() = Some(records.clone()));
+    Capture { records }
+}
+
+/// Replaces the targets of `writer` with the captures.
+fn route(writer: &Writer) {
+    let levels = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+    let routes = levels
+        .into_iter()
+        .map(|level| {
+            let target = Target::custom(move |bytes| collect(level, bytes));
+            (target, (Bound::Included(level), Bound::Included(level)))
+        })
+        .collect();
+    let _ = writer.set_routes(routes);
+}
+
+/// Adds a record to the capture of the current thread, if any.
+fn collect(level: Level, bytes: &[u8]) {
+    let records = match CAPTURE.try_with(|capture| capture.borrow().clone()) {
+        Ok(Some(records)) => records,
+        _ => return,
+    };
+    let message = String::from_utf8_lossy(bytes);
+    records
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(CapturedRecord {
+            level,
+            message: message.trim_end_matches('\n').to_owned(),
+        });
+}
@@ -0,0 +1,147 @@
+//! Logging the events of the `tracing` crate.
+//!
+//! The events are converted to records and passed to the global logger, so
+//! the filters, formats and targets of the logger apply to them. The formats
+//! only write the message of a record, so the fields of the event and of its
+//! spans are appended to the message as `key=value` pairs, followed by the
+//! chain of the names of the spans as `span=outer:inner`.
+
+use std::fmt::{self, Write};
+
+use ::tracing::field::{Field, Visit};
+use ::tracing::span::{Attributes, Id, Record as SpanRecord};
+use ::tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A `tracing_subscriber` layer logging the events with the global logger.
+///
+/// This layer is only available with the `tracing` feature.
+///
+/// # Examples
+///
+/// ```
+/// use ic_log::TracingLayer;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// ic_log::init();
+///
+/// let subscriber = tracing_subscriber::registry().with(TracingLayer::new());
+/// tracing::subscriber::with_default(subscriber, || {
+///     let span = tracing::info_span!("transfer", amount = 10);
+///     let _entered = span.enter();
+///     tracing::info!(to = "alice", "transfer started");
+/// });
+/// // logged as `transfer started to="alice" amount=10 span=transfer`
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingLayer {
+    _priv: (),
+}
+
+impl TracingLayer {
+    /// Creates the layer.
+    pub fn new() -> TracingLayer {
+        TracingLayer::default()
+    }
+}
+
+/// The fields of a span, formatted as `key=value` pairs, each preceded by a
+/// space.
+struct SpanFields(String);
+
+/// Formats the message and the other fields of an event or span.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    pairs: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.pairs, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+fn level(level: &Level) -> log::Level {
+    match *level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl<S> Layer<S> for TracingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(SpanFields(fields.pairs));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &SpanRecord<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            values.record(&mut fields);
+            if let Some(SpanFields(pairs)) = span.extensions_mut().get_mut::<SpanFields>() {
+                pairs.push_str(&fields.pairs);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = level(metadata.level());
+        if level > log::max_level() {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut names = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            // The fields of the innermost spans come first, like the fields
+            // of the event before them
+            for span in scope {
+                if let Some(SpanFields(pairs)) = span.extensions().get::<SpanFields>() {
+                    fields.pairs.push_str(pairs);
+                }
+                names.insert_str(0, span.name());
+                names.insert(0, ':');
+            }
+        }
+        if !names.is_empty() {
+            fields.pairs.push_str(" span=");
+            fields.pairs.push_str(&names[1..]);
+        }
+
+        log::logger().log(
+            &log::Record::builder()
+                .args(format_args!("{}{}", fields.message, fields.pairs))
+                .level(level)
+                .target(metadata.target())
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+    }
+}
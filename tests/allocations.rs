@@ -0,0 +1,66 @@
+//! Counting the allocations made by the default format.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Write};
+
+use ic_log::{Builder, Target, WriteStyle};
+use log::{Level, LevelFilter, Log, Record};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// A pipe discarding the records.
+struct Sink;
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn simple_records_are_written_without_allocating() {
+    let (logger, _config) = Builder::new()
+        .target(Target::Pipe(Box::new(Sink)))
+        .write_style(WriteStyle::Never)
+        .filter_level(LevelFilter::Trace)
+        .build();
+    let log = || {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("transfer started"))
+                .level(Level::Info)
+                .target("ledger")
+                .module_path_static(Some("ledger::transfer"))
+                .build(),
+        )
+    };
+    // The first record allocates the reused formatter
+    log();
+
+    let before = ALLOCATIONS.with(Cell::get);
+    log();
+    assert_eq!(before, ALLOCATIONS.with(Cell::get));
+}
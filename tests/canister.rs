@@ -0,0 +1,77 @@
+//! The canister endpoints managing the logger, called as plain functions.
+
+#![cfg(feature = "candid")]
+
+use std::cell::{Cell, RefCell};
+
+use ic_log::log::{self, LevelFilter};
+use ic_log::{Builder, LoggerConfig};
+
+thread_local! {
+    static CONFIG: RefCell<Option<LoggerConfig>> = const { RefCell::new(None) };
+    static ALLOWED: Cell<bool> = const { Cell::new(true) };
+}
+
+fn logger_config() -> LoggerConfig {
+    CONFIG.with(|config| config.borrow().clone().unwrap())
+}
+
+fn is_allowed() -> Result<(), String> {
+    match ALLOWED.with(Cell::get) {
+        true => Ok(()),
+        false => Err("not a controller".to_owned()),
+    }
+}
+
+ic_log::export_log_endpoints!(logger_config, guard = is_allowed);
+
+mod unguarded {
+    ic_log::export_log_endpoints!(super::logger_config);
+
+    pub fn set_filter(filters: &str) -> Result<(), String> {
+        set_logger_filter(filters.to_owned())
+    }
+}
+
+#[test]
+fn the_endpoints_manage_the_logger() {
+    let (logger, config) = Builder::new().filter_level(LevelFilter::Info).build();
+    CONFIG.with(|slot| *slot.borrow_mut() = Some(config.clone()));
+
+    set_logger_filter("debug".to_owned()).unwrap();
+    assert_eq!("debug", config.current_filter_string());
+    assert!(set_logger_filter("debug,=".to_owned()).is_err());
+
+    log::Log::log(
+        &logger,
+        &log::Record::builder().args(format_args!("m")).build(),
+    );
+    assert_eq!(config.stats(), logger_stats());
+    assert_eq!(1, logger_stats().records_logged);
+
+    ALLOWED.with(|allowed| allowed.set(false));
+    assert_eq!(
+        Err("not a controller".to_owned()),
+        set_logger_filter("trace".to_owned())
+    );
+    assert_eq!("debug", config.current_filter_string());
+    assert!(std::panic::catch_unwind(logger_stats).is_err());
+
+    unguarded::set_filter("warn").unwrap();
+    assert_eq!("warn", config.current_filter_string());
+}
+
+#[test]
+fn the_stats_are_encoded_with_candid() {
+    use ic_cdk::export::candid::{Decode, Encode};
+
+    #[derive(ic_cdk::export::candid::CandidType, serde::Deserialize)]
+    #[candid_path("ic_cdk::export::candid")]
+    struct Stats {
+        records_logged: u64,
+    }
+
+    let (_, config) = Builder::new().build();
+    let bytes = Encode!(&config.stats()).unwrap();
+    assert_eq!(0, Decode!(&bytes, Stats).unwrap().records_logged);
+}
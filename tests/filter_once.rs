@@ -0,0 +1,47 @@
+//! Evaluating the filter once per logged record.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ic_log::log::{self, Level, LevelFilter, Log, Record};
+use ic_log::testing::capture;
+use ic_log::{Builder, Target};
+
+static EVALUATIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn counting_builder() -> Builder {
+    let mut builder = Builder::new();
+    builder.filter_level(LevelFilter::Info).filter_fn(|_| {
+        EVALUATIONS.fetch_add(1, Ordering::Relaxed);
+        true
+    });
+    builder
+}
+
+#[test]
+fn records_are_filtered_once() {
+    // The log macros only check the max level before calling `Log::log`
+    counting_builder().init();
+    let logs = capture();
+
+    log::info!("logged");
+    log::debug!("rejected by the max level");
+    assert_eq!(1, EVALUATIONS.swap(0, Ordering::Relaxed));
+
+    // `Log::log` filters records on its own, without a preceding `enabled`
+    let (logger, _config) = counting_builder().target(Target::Void).build();
+    logger.log(
+        &Record::builder()
+            .args(format_args!("logged"))
+            .level(Level::Info)
+            .build(),
+    );
+    logger.log(
+        &Record::builder()
+            .args(format_args!("rejected"))
+            .level(Level::Debug)
+            .build(),
+    );
+    assert_eq!(1, EVALUATIONS.swap(0, Ordering::Relaxed));
+
+    assert_eq!(1, logs.records().len());
+}
@@ -0,0 +1,61 @@
+#![cfg(all(feature = "journald", unix))]
+
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use ic_log::{Builder, Target};
+use log::{Level, Log, Record};
+
+#[test]
+fn records_are_sent_to_the_journald_socket() {
+    let dir = std::env::temp_dir().join(format!("ic_log-journald-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("socket");
+    let _ = std::fs::remove_file(&path);
+    let journald = UnixDatagram::bind(&path).unwrap();
+    journald
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let (logger, config) = Builder::new()
+        .target(Target::journald_socket(&path))
+        .format(|buf, record| {
+            use std::io::Write;
+            writeln!(buf, "{}", record.args())
+        })
+        .build();
+    logger.log(
+        &Record::builder()
+            .args(format_args!("indexed"))
+            .level(Level::Error)
+            .target("indexer")
+            .build(),
+    );
+
+    let mut datagram = [0; 1024];
+    let len = journald.recv(&mut datagram).unwrap();
+    let mut expected = b"PRIORITY=3\nSYSLOG_IDENTIFIER=indexer\nMESSAGE\n".to_vec();
+    expected.extend_from_slice(&7u64.to_le_bytes());
+    expected.extend_from_slice(b"indexed\n");
+    assert_eq!(expected, &datagram[..len]);
+    assert_eq!(1, config.writer_stats()["journald#0"].records_written);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreachable_journald_falls_back_to_stderr() {
+    let (logger, config) = Builder::new()
+        .target(Target::journald_socket("/nonexistent/ic_log/socket"))
+        .build();
+    logger.log(
+        &Record::builder()
+            .args(format_args!("fallback"))
+            .level(Level::Error)
+            .build(),
+    );
+
+    let stats = config.writer_stats();
+    assert_eq!(1, stats["journald#0"].records_written);
+    assert_eq!(0, stats["journald#0"].failed_writes);
+}
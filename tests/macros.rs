@@ -0,0 +1,42 @@
+//! The rate limiting macros, counted with the capture sink.
+
+use ic_log::log::Level;
+use ic_log::testing::capture;
+use ic_log::{log_every_n, log_once};
+
+fn messages(logs: &ic_log::testing::Capture) -> Vec<String> {
+    logs.records()
+        .into_iter()
+        .map(|record| record.message)
+        .collect()
+}
+
+#[test]
+fn log_once_logs_the_first_time_only() {
+    let logs = capture();
+
+    for attempt in 0..3 {
+        log_once!(Level::Warn, "slow ledger, attempt {}", attempt);
+        log_once!(target: "ledger", Level::Error, "ledger down");
+    }
+
+    assert_eq!(
+        vec!["slow ledger, attempt 0", "ledger down"],
+        messages(&logs)
+    );
+}
+
+#[test]
+fn log_every_n_logs_every_nth_time() {
+    let logs = capture();
+
+    for request in 0..7 {
+        log_every_n!(3, Level::Info, "request {}", request);
+    }
+    for request in 0..2 {
+        log_every_n!(0, target: "http", Level::Info, "http {}", request);
+    }
+
+    let expected = vec!["request 0", "request 3", "request 6", "http 0", "http 1"];
+    assert_eq!(expected, messages(&logs));
+}
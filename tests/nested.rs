@@ -0,0 +1,49 @@
+//! Nesting a logger within a global logger not built by this crate.
+
+use std::sync::Arc;
+
+use ic_log::log::{self, Level, LevelFilter, Log, Metadata, Record};
+use ic_log::{Builder, Error, Logger, Target};
+
+struct Forward(Arc<Logger>);
+
+impl Log for Forward {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.log(record)
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn nested_configs_leave_the_global_max_level_alone() {
+    let (logger, config) = Builder::new()
+        .target(Target::Void)
+        .filter_level(LevelFilter::Info)
+        .build_logger();
+    log::set_boxed_logger(Box::new(Forward(Arc::new(logger)))).unwrap();
+    log::set_max_level(LevelFilter::Warn);
+
+    config.update_filters("debug").unwrap();
+    assert_eq!(LevelFilter::Warn, log::max_level());
+    assert_eq!(LevelFilter::Debug, config.max_level());
+
+    // The parent applies the level of the nested logger
+    config.on_filter_change(|_, max_level| log::set_max_level(max_level));
+    config.update_filters("error").unwrap();
+    assert_eq!(LevelFilter::Error, log::max_level());
+
+    // Neither an unrelated logger nor a failed init change it
+    let (_, other) = Builder::new().target(Target::Void).build();
+    other.update_filters("trace").unwrap();
+    let failed = Builder::new().filter_level(LevelFilter::Trace).try_init();
+    assert!(matches!(failed, Err(Error::AlreadyInitialized)));
+    assert_eq!(LevelFilter::Error, log::max_level());
+    assert!(log::logger().enabled(&Metadata::builder().level(Level::Error).build()));
+}
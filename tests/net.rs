@@ -0,0 +1,84 @@
+#![cfg(all(feature = "net", not(target_arch = "wasm32")))]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use ic_log::{Builder, Logger, Target};
+use log::{Level, Log, Record};
+
+fn build(target: Target) -> Logger {
+    let (logger, _) = Builder::new()
+        .target(target)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .build();
+    logger
+}
+
+fn log(logger: &Logger, message: &str) {
+    logger.log(
+        &Record::builder()
+            .args(format_args!("{}", message))
+            .level(Level::Error)
+            .build(),
+    );
+}
+
+#[test]
+fn udp_sends_one_record_per_datagram() {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let logger = build(Target::udp(collector.local_addr().unwrap()).unwrap());
+
+    log(&logger, "first");
+    log(&logger, "second");
+
+    let mut datagram = [0; 64];
+    let len = collector.recv(&mut datagram).unwrap();
+    assert_eq!(b"first\n", &datagram[..len]);
+    let len = collector.recv(&mut datagram).unwrap();
+    assert_eq!(b"second\n", &datagram[..len]);
+}
+
+#[test]
+fn tcp_sends_newline_delimited_records() {
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let logger = build(Target::tcp(collector.local_addr().unwrap()).unwrap());
+
+    log(&logger, "first");
+    log(&logger, "second");
+
+    let (stream, _) = collector.accept().unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!("first", lines.next().unwrap().unwrap());
+    assert_eq!("second", lines.next().unwrap().unwrap());
+}
+
+#[test]
+fn tcp_buffers_records_until_reconnected() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (logger, config) = Builder::new()
+        .target(Target::tcp_with_timeout(addr, Duration::from_millis(20)).unwrap())
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .build();
+
+    // the collector isn't listening, the record is kept
+    log(&logger, "buffered");
+    assert_eq!(1, config.stats().write_errors);
+
+    let collector = TcpListener::bind(addr).unwrap();
+    thread::sleep(Duration::from_millis(1100));
+    log(&logger, "sent");
+
+    let (stream, _) = collector.accept().unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!("buffered", lines.next().unwrap().unwrap());
+    assert_eq!("sent", lines.next().unwrap().unwrap());
+    assert_eq!(0, config.stats().overflow_dropped);
+}
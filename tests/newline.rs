@@ -0,0 +1,54 @@
+//! Stripping the trailing newline of the printed records.
+
+use std::sync::{Arc, Mutex};
+
+use ic_log::log::{Level, Log, Record};
+use ic_log::platform::Platform;
+use ic_log::Builder;
+
+/// Writes every print as a separate line, like the canister log.
+#[derive(Clone, Default)]
+struct Lines {
+    log: Arc<Mutex<String>>,
+}
+
+impl Platform for Lines {
+    fn time_nanos(&self) -> u64 {
+        0
+    }
+
+    fn print(&self, data: &[u8]) {
+        let mut log = self.log.lock().unwrap();
+        log.push_str(&String::from_utf8_lossy(data));
+        log.push('\n');
+    }
+}
+
+fn print_records(trim: bool) -> String {
+    let lines = Lines::default();
+    let (logger, _config) = Builder::new()
+        .format(|buf, record| {
+            use std::io::Write;
+            writeln!(buf, "{}", record.args())
+        })
+        .trim_trailing_newline(trim)
+        .platform(lines.clone())
+        .build();
+
+    for message in ["first", "second\ncontinued"] {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Error)
+                .build(),
+        );
+    }
+    let log = lines.log.lock().unwrap().clone();
+    log
+}
+
+#[test]
+fn every_record_ends_with_a_single_newline() {
+    assert_eq!("first\nsecond\ncontinued\n", print_records(true));
+    assert_eq!("first\n\nsecond\ncontinued\n\n", print_records(false));
+}
@@ -0,0 +1,25 @@
+//! Logging the panics with the panic hook.
+
+use std::panic;
+
+use ic_log::log::Level;
+use ic_log::testing::capture;
+
+#[test]
+fn panics_are_logged_as_errors() {
+    let logs = capture();
+    ic_log::install_panic_hook();
+
+    let line = line!() + 1;
+    let result = panic::catch_unwind(|| panic!("transfer {} failed", 7));
+
+    assert!(result.is_err());
+    let location = format!("{}:{}:", file!(), line);
+    let records = logs.records();
+    assert_eq!(1, records.len());
+    assert_eq!(Level::Error, records[0].level);
+    assert!(records[0]
+        .message
+        .starts_with("panicked at 'transfer 7 failed', "));
+    assert!(records[0].message.contains(&location));
+}
@@ -0,0 +1,75 @@
+//! Selecting the platform of the logger.
+
+use std::sync::{Arc, Mutex};
+
+use ic_log::log::{self, LevelFilter, Log};
+use ic_log::platform::Platform;
+use ic_log::Builder;
+
+#[derive(Clone)]
+struct Mock {
+    time_nanos: u64,
+    printed: Arc<Mutex<Vec<String>>>,
+}
+
+impl Mock {
+    fn new(time_nanos: u64) -> Self {
+        Mock {
+            time_nanos,
+            printed: Default::default(),
+        }
+    }
+}
+
+impl Platform for Mock {
+    fn time_nanos(&self) -> u64 {
+        self.time_nanos
+    }
+
+    fn print(&self, data: &[u8]) {
+        self.printed
+            .lock()
+            .unwrap()
+            .push(format!("out {}", String::from_utf8_lossy(data)));
+    }
+
+    fn eprint(&self, data: &[u8]) {
+        self.printed
+            .lock()
+            .unwrap()
+            .push(format!("err {}", String::from_utf8_lossy(data)));
+    }
+}
+
+#[test]
+fn records_are_timed_and_printed_by_the_platform_of_the_logger() {
+    let mock = Mock::new(1_000_000_000);
+    Builder::new()
+        .filter_level(LevelFilter::Info)
+        .format_target(false)
+        .platform(mock.clone())
+        .init();
+
+    // Building another logger doesn't change the platform of the first one
+    let other = Mock::new(2_000_000_000);
+    let (other_logger, _) = Builder::new()
+        .filter_level(LevelFilter::Info)
+        .format_target(false)
+        .platform(other.clone())
+        .build();
+
+    log::info!("from the mock");
+    other_logger.log(&log::Record::builder().args(format_args!("other")).build());
+    // The platform isn't selected globally
+    assert_ne!(
+        1_000_000_000,
+        ic_log::platform::current_timestamp_in_nanosecs()
+    );
+
+    #[cfg(feature = "humantime")]
+    let expected = "out [1970-01-01T00:00:01.000000000Z INFO ] from the mock\n";
+    #[cfg(not(feature = "humantime"))]
+    let expected = "out [1000000000 INFO ] from the mock\n";
+    assert_eq!(vec![expected], *mock.printed.lock().unwrap());
+    assert_eq!(1, other.printed.lock().unwrap().len());
+}
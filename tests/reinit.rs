@@ -0,0 +1,44 @@
+//! Initializing the global logger again, as in `post_upgrade`.
+
+use std::io::Write;
+
+use ic_log::log::{self, LevelFilter};
+use ic_log::testing::capture;
+use ic_log::Builder;
+
+#[test]
+fn the_logger_initialized_last_wins() {
+    assert!(!ic_log::is_initialized());
+    let first = Builder::new()
+        .filter_level(LevelFilter::Warn)
+        .format(|buf, record| writeln!(buf, "first {}", record.args()))
+        .try_init()
+        .unwrap();
+    assert!(ic_log::is_initialized());
+    let logs = capture();
+
+    log::warn!("upgrading");
+    log::info!("filtered");
+
+    let second = Builder::new()
+        .filter_level(LevelFilter::Info)
+        .format(|buf, record| writeln!(buf, "second {}", record.args()))
+        .try_init()
+        .unwrap();
+    first.set_default_level(LevelFilter::Off);
+
+    log::warn!("upgraded");
+    log::info!("visible");
+
+    let messages: Vec<_> = logs
+        .records()
+        .into_iter()
+        .map(|record| record.message)
+        .collect();
+    assert_eq!(
+        vec!["first upgrading", "second upgraded", "second visible"],
+        messages
+    );
+    assert_eq!(LevelFilter::Info, log::max_level());
+    assert_eq!(2, second.stats().records_logged);
+}
@@ -0,0 +1,74 @@
+//! Scoped loggers, routed by the global logger.
+
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, Once};
+
+use ic_log::log::{self, LevelFilter};
+use ic_log::{Builder, ScopedLogger, Target};
+
+fn scoped(name: &'static str, records: &Arc<Mutex<Vec<String>>>) -> ScopedLogger {
+    let records = records.clone();
+    let (logger, _) = Builder::new()
+        .filter_level(LevelFilter::Debug)
+        .format(|buf, record| write!(buf, "{}", record.args()))
+        .target(Target::custom(move |bytes| {
+            let message = String::from_utf8_lossy(bytes);
+            records
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", name, message));
+        }))
+        .build_logger();
+    ScopedLogger::new(logger)
+}
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        Builder::new().filter_level(LevelFilter::Off).init();
+    });
+}
+
+#[test]
+fn scopes_route_the_records_and_restore_the_previous_logger() {
+    init();
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let outer = scoped("outer", &records);
+    let inner = scoped("inner", &records);
+
+    log::info!("no scope");
+    outer.with_scope(|_| {
+        log::debug!("first");
+        inner.with_scope(|_| log::debug!("nested"));
+        log::debug!("second");
+    });
+    log::info!("no scope again");
+    inner.log_record(&log::Record::builder().args(format_args!("direct")).build());
+
+    let expected = vec![
+        "outer: first",
+        "inner: nested",
+        "outer: second",
+        "inner: direct",
+    ];
+    assert_eq!(expected, *records.lock().unwrap());
+}
+
+#[test]
+fn panics_do_not_leave_the_scope_installed() {
+    init();
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let scoped = scoped("scoped", &records);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        scoped.with_scope(|_| {
+            log::info!("before the panic");
+            panic!("request failed");
+        })
+    }));
+    log::info!("after the panic");
+
+    assert!(result.is_err());
+    assert_eq!(vec!["scoped: before the panic"], *records.lock().unwrap());
+}
@@ -0,0 +1,48 @@
+//! Installs the global logger from settings decoded from JSON and candid.
+
+#![cfg(all(feature = "json", feature = "candid"))]
+
+use ic_cdk::export::candid::{Decode, Encode};
+use ic_log::LogSettings;
+use log::Level;
+
+#[test]
+fn logger_is_initialized_from_decoded_settings() {
+    let json = r#"{"log_filter": "warn", "collapse_duplicates_ns": 1000000000, "unknown": 1}"#;
+    let settings: LogSettings = serde_json::from_str(json).unwrap();
+    let expected = LogSettings {
+        enable_console: true,
+        log_filter: Some("warn".to_owned()),
+        max_bytes_per_call: None,
+        collapse_duplicates_ns: Some(1_000_000_000),
+    };
+    assert_eq!(expected, settings);
+
+    let bytes = Encode!(&settings).unwrap();
+    assert_eq!(settings, Decode!(&bytes, LogSettings).unwrap());
+
+    let invalid = LogSettings {
+        log_filter: Some("infoo".to_owned()),
+        ..settings.clone()
+    };
+    let result = ic_log::init_log(&invalid);
+    assert!(matches!(result, Err(ic_log::Error::InvalidFilter(_))));
+
+    ic_log::init_log(&settings).unwrap();
+    ic_log::init_log(&settings).unwrap();
+    let logs = ic_log::testing::capture();
+
+    log::info!("filtered");
+    log::warn!("retry");
+    log::warn!("retry");
+    log::warn!("done");
+
+    // The records are printed with the default format
+    let records = logs.records();
+    let expected = ["] retry", "] last message repeated 1 times", "] done"];
+    assert_eq!(expected.len(), records.len());
+    for (record, expected) in records.iter().zip(expected) {
+        assert_eq!(Level::Warn, record.level);
+        assert!(record.message.ends_with(expected), "{}", record.message);
+    }
+}
@@ -0,0 +1,44 @@
+//! Shutting down the global logger, which stays registered with `log`.
+
+use std::io::Write;
+
+use ic_log::log::{self, LevelFilter};
+use ic_log::testing::capture;
+use ic_log::Builder;
+
+#[test]
+fn a_shut_down_global_logger_is_silent_until_replaced() {
+    let first = Builder::new()
+        .filter_level(LevelFilter::Info)
+        .format(|buf, record| writeln!(buf, "first {}", record.args()))
+        .try_init()
+        .unwrap();
+    let logs = capture();
+
+    log::info!("before");
+    first.shutdown().unwrap();
+    assert_eq!(LevelFilter::Off, log::max_level());
+    assert!(ic_log::is_initialized());
+    log::logger().log(&log::Record::builder().args(format_args!("after")).build());
+
+    // Updating the filter of the shut down logger doesn't turn logging back on
+    first.set_default_level(LevelFilter::Trace);
+    assert_eq!(LevelFilter::Off, log::max_level());
+
+    let second = Builder::new()
+        .filter_level(LevelFilter::Warn)
+        .format(|buf, record| writeln!(buf, "second {}", record.args()))
+        .try_init()
+        .unwrap();
+    assert_eq!(LevelFilter::Warn, log::max_level());
+    log::warn!("replaced");
+
+    let messages: Vec<_> = logs
+        .records()
+        .into_iter()
+        .map(|record| record.message)
+        .collect();
+    assert_eq!(vec!["first before", "second replaced"], messages);
+    assert_eq!(1, first.stats().disabled_suppressed);
+    assert!(second.is_active());
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "slog")]
+//! Logging the slog records with the drain.
+
+use ic_log::log::Level;
+use ic_log::testing::capture;
+use ic_log::SlogDrain;
+use slog::{o, Drain, Logger, OwnedKVList, Record, RecordLocation, RecordStatic};
+
+static LOCATION: RecordLocation = RecordLocation {
+    file: file!(),
+    line: line!(),
+    column: column!(),
+    function: "",
+    module: module_path!(),
+};
+
+#[test]
+fn levels_are_mapped_to_the_log_levels() {
+    let logs = capture();
+    let levels = [
+        (slog::Level::Critical, Level::Error),
+        (slog::Level::Error, Level::Error),
+        (slog::Level::Warning, Level::Warn),
+        (slog::Level::Info, Level::Info),
+        (slog::Level::Debug, Level::Debug),
+        (slog::Level::Trace, Level::Trace),
+    ];
+
+    for (level, _) in levels {
+        let record = RecordStatic {
+            location: &LOCATION,
+            level,
+            tag: "",
+        };
+        let values = OwnedKVList::from(o!());
+        SlogDrain::new()
+            .log(
+                &Record::new(&record, &format_args!("{}", level.as_str()), slog::b!()),
+                &values,
+            )
+            .unwrap();
+    }
+
+    let records = logs.records();
+    assert_eq!(levels.len(), records.len());
+    for ((level, expected), record) in levels.iter().zip(&records) {
+        assert_eq!(*expected, record.level);
+        assert_eq!(level.as_str(), record.message);
+    }
+}
+
+#[test]
+fn pairs_are_appended_to_the_message() {
+    let logs = capture();
+    let logger = Logger::root(SlogDrain::new().fuse(), o!("canister" => "ledger"));
+    let child = logger.new(o!("call" => 3));
+
+    slog::info!(child, "transfer {}", "started"; "amount" => 10, "ok" => true);
+
+    let records = logs.records();
+    assert_eq!(1, records.len());
+    assert_eq!(Level::Info, records[0].level);
+    assert_eq!(
+        "transfer started ok=true amount=10 call=3 canister=ledger",
+        records[0].message
+    );
+}
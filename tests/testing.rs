@@ -0,0 +1,79 @@
+//! The global logger is shared by all the tests of a binary, so the captures
+//! are tested on their own.
+
+use std::thread;
+
+use ic_log::testing::{capture, Capture, CapturedRecord};
+use log::Level;
+
+#[test]
+fn records_are_captured_by_level() {
+    let logs = capture();
+
+    log::warn!("disk almost full");
+    log::error!("disk full");
+
+    assert!(logs.contains(Level::Warn, "almost full"));
+    assert!(logs.contains(Level::Error, "disk full"));
+    assert!(!logs.contains(Level::Error, "almost full"));
+
+    logs.clear();
+    log::info!("disk cleaned");
+    let expected = vec![CapturedRecord {
+        level: Level::Info,
+        message: "disk cleaned".to_owned(),
+    }];
+    assert_eq!(expected, logs.records());
+}
+
+#[test]
+fn captures_are_isolated_between_threads() {
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let logs = capture();
+                for j in 0..20 {
+                    log::info!("thread {} record {}", i, j);
+                }
+                let records = logs.records();
+                assert_eq!(20, records.len());
+                let prefix = format!("thread {} ", i);
+                assert!(records
+                    .iter()
+                    .all(|record| record.message.starts_with(&prefix)));
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn dropped_captures_stop_collecting() {
+    let messages = |logs: &Capture| -> Vec<String> {
+        logs.records()
+            .into_iter()
+            .map(|record| record.message)
+            .collect()
+    };
+
+    let first = capture();
+    log::error!("first");
+    // the capture is swapped, not stacked
+    let second = capture();
+    log::error!("second");
+    assert_eq!(vec!["first"], messages(&first));
+
+    // dropping a replaced capture doesn't stop the current one
+    drop(first);
+    log::error!("third");
+    assert_eq!(vec!["second", "third"], messages(&second));
+
+    drop(second);
+    log::error!("fourth");
+    let third = capture();
+    log::error!("fifth");
+    assert_eq!(vec!["fifth"], messages(&third));
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "tracing")]
+//! Logging the tracing events with the layer.
+
+use ic_log::log::Level;
+use ic_log::testing::capture;
+use ic_log::TracingLayer;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[test]
+fn events_are_logged_with_their_fields_and_spans() {
+    let logs = capture();
+
+    let subscriber = tracing_subscriber::registry().with(TracingLayer::new());
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::warn!("no span");
+
+        let outer = tracing::info_span!("canister", id = 7);
+        let _outer = outer.enter();
+        let inner = tracing::debug_span!("transfer", amount = tracing::field::Empty);
+        let _inner = inner.enter();
+        inner.record("amount", 10);
+        tracing::info!(to = "alice", ok = true, "transfer {}", "started");
+    });
+
+    let records = logs.records();
+    assert_eq!(2, records.len());
+    assert_eq!(Level::Warn, records[0].level);
+    assert_eq!("no span", records[0].message);
+    assert_eq!(Level::Info, records[1].level);
+    assert_eq!(
+        "transfer started to=\"alice\" ok=true amount=10 id=7 span=canister:transfer",
+        records[1].message
+    );
+}
@@ -0,0 +1,45 @@
+//! Stopping the background writer thread of the global logger.
+#![cfg(feature = "async-writer")]
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ic_log::log;
+use ic_log::{Builder, OverflowPolicy, Target};
+
+/// A pipe slower than the logging calls.
+struct Slow(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Slow {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        thread::sleep(Duration::from_millis(1));
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn no_record_is_lost_when_the_guard_is_dropped() {
+    let file = Arc::new(Mutex::new(Vec::new()));
+    let (_config, guard) = Builder::new()
+        .target(Target::Pipe(Box::new(Slow(file.clone()))))
+        .non_blocking(8)
+        .overflow_policy(OverflowPolicy::Block)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .try_init_with_guard()
+        .unwrap();
+
+    for i in 0..50 {
+        log::error!("{}", i);
+    }
+
+    drop(guard);
+    let expected: String = (0..50).map(|i| format!("{}\n", i)).collect();
+    assert_eq!(expected.as_bytes(), &file.lock().unwrap()[..]);
+}